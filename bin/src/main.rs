@@ -1,42 +1,72 @@
 #[macro_use]
 extern crate log;
 
-use futures::Stream;
+use futures::channel::mpsc::{self, Sender};
+use futures::future::poll_fn;
+use futures::{FutureExt, Stream};
 
 use crate::stream_adapter::{StrictOrLazyLineBuilder, StrictOrLazyLines};
-use config::{Config, DbPath};
+use config::reload::ConfigReloader;
+use config::{Config, DbPath, OutputConfig};
 use env_logger::Env;
+use fs::multiline::{
+    MultilineLookup, MultilineRule, DEFAULT_FLUSH_TIMEOUT, DEFAULT_MAX_BYTES, DEFAULT_MAX_LINES,
+};
 use fs::tail::Tailer as FSSource;
 use futures::StreamExt;
 use http::batch::TimedRequestBatcherStreamExt;
 use http::client::{Client, ClientError, SendStatus};
+use http::offsets::Offset;
 use http::retry::{retry, RetryItem};
+use http::sink::{FileSink, StdoutSink};
+use http::types::body::{IngestBodyBuffer, Line, LineBuilder};
 
 #[cfg(feature = "libjournald")]
 use journald::libjournald::source::create_source;
 
 use journald::journalctl::create_journalctl_source;
+use journald::{journal_origins, JournalLine};
 
 use k8s::event_source::K8sEventStream;
 
 use k8s::middleware::K8sMetadata;
 use k8s::K8sTrackingConf;
 use metrics::Metrics;
-use middleware::line_rules::LineRules;
-use middleware::Executor;
+use middleware::dedup::Dedup;
+use middleware::file_fields::FileFields;
+use middleware::json_fields::JsonFields;
+use middleware::levels::Levels;
+use middleware::line_rules::{FieldRedactor, LineRules};
+use middleware::rate_limit::RateLimit;
+use middleware::reload::Reloadable;
+use middleware::routing::{copy_line, Route};
+use middleware::timestamps::Timestamps;
+use middleware::{Executor, LineMut, TimestampedLine};
 
 use pin_utils::pin_mut;
 use state::AgentState;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use syslog::SyslogLine;
 use tokio::signal::*;
 
 mod dep_audit;
+mod reload;
 mod stream_adapter;
 
+/// The number of lines waiting to be batched for each destination other than the primary one
+const DESTINATION_BUFFER_SIZE: usize = 1024;
+
+/// The least time between two warnings about a destination whose buffer is full
+const DESTINATION_FULL_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the lines created by the middlewares are taken when no line is read
+const CREATED_LINES_INTERVAL: Duration = Duration::from_secs(1);
+
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
@@ -67,15 +97,28 @@ async fn main() {
         }
     };
 
+    let mut config_reloader = ConfigReloader::new(&config);
+
     let mut _agent_state = None;
     let mut offset_state = None;
     let mut initial_offsets = None;
+    let mut journald_cursors = None;
+    let mut initial_cursors = HashMap::new();
 
     if let DbPath::Path(db_path) = config.log.db_path {
         match AgentState::new(db_path) {
             Ok(agent_state) => {
                 let _offset_state = agent_state.get_offset_state();
                 let offsets = _offset_state.offsets();
+                let cursors = agent_state.get_journald_cursors();
+                match cursors.cursors() {
+                    Ok(cs) => initial_cursors = cs,
+                    Err(e) => warn!(
+                        "couldn't retrieve journald cursors from agent state, {:?}",
+                        e
+                    ),
+                }
+                journald_cursors = Some(cursors);
                 _agent_state = Some(agent_state);
                 offset_state = Some(_offset_state);
                 match offsets {
@@ -95,9 +138,15 @@ async fn main() {
     let handles = offset_state
         .as_ref()
         .map(|os| (os.write_handle(), os.flush_handle()));
+    let offset_shutdown = offset_state.as_ref().and_then(|os| {
+        os.shutdown_handle()
+            .map_err(|e| warn!("offsets won't be flushed on shutdown: {}", e))
+            .ok()
+    });
 
     let (retry, retry_stream) = retry(
-        PathBuf::from_str("/tmp/logdna").expect("Failed to create retry stream"),
+        config.http.retry_dir,
+        config.http.retry_limits,
         config.http.retry_base_delay,
         config.http.retry_step_delay,
     );
@@ -109,13 +158,60 @@ async fn main() {
 
     client.borrow_mut().set_timeout(config.http.timeout);
 
+    match config.output {
+        OutputConfig::Http => {}
+        OutputConfig::Stdout => {
+            info!("Writing log lines to stdout instead of sending them");
+            client.borrow_mut().set_sink(Box::new(StdoutSink::new()));
+        }
+        OutputConfig::File {
+            path,
+            max_size,
+            max_files,
+        } => {
+            info!(
+                "Writing log lines to {} instead of sending them",
+                path.display()
+            );
+            client
+                .borrow_mut()
+                .set_sink(Box::new(FileSink::new(path, max_size, max_files)));
+        }
+    }
+
+    // Lines matching the route of a destination are copied to its own batcher and client
+    let mut routes = Vec::new();
+    let mut destinations = Vec::new();
+    for destination in config.destinations {
+        let (retry, retry_stream) = retry(
+            destination.retry_dir,
+            config.http.retry_limits,
+            config.http.retry_base_delay,
+            config.http.retry_step_delay,
+        );
+        // Only the primary destination records the file offsets
+        let mut client = Client::new(destination.template, retry, None);
+        client.set_timeout(config.http.timeout);
+        let (sender, received) = mpsc::channel(DESTINATION_BUFFER_SIZE);
+        info!("Enabling the {} destination", destination.name);
+        routes.push((
+            destination.name.clone(),
+            destination.route,
+            RefCell::new(sender),
+        ));
+        destinations.push((destination.name, client, received, retry_stream));
+    }
+
+    let mut multiline_rules = config.log.multiline_rules;
     let mut executor = Executor::new();
     if config.log.use_k8s_enrichment == K8sTrackingConf::Always
         && PathBuf::from("/var/log/containers/").exists()
     {
-        match K8sMetadata::new().await {
+        match K8sMetadata::new(config.log.k8s_filter, config.log.k8s_metadata_fields).await {
             Ok(v) => {
-                executor.register(v);
+                let k8s_metadata = Arc::new(v);
+                multiline_rules.set_lookup(k8s_multiline_lookup(k8s_metadata.clone()));
+                executor.register(k8s_metadata);
                 info!("Registered k8s metadata middleware");
             }
             Err(e) => {
@@ -129,17 +225,74 @@ async fn main() {
         };
     }
 
-    match LineRules::new(
+    // Fields are redacted before any of them is copied out of the JSON lines
+    let field_redactor = match FieldRedactor::new(&config.log.line_redact_fields) {
+        Ok(v) => Reloadable::new(v),
+        Err(e) => {
+            error!("line redact fields are invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let field_redactor_handle = field_redactor.handle();
+    executor.register(field_redactor);
+
+    if !config.log.file_fields.is_empty() {
+        executor.register(FileFields::new(config.log.file_fields));
+        info!("Registered file fields middleware");
+    }
+
+    if !config.log.timestamp_rules.is_empty() {
+        executor.register(Timestamps::new(config.log.timestamp_rules));
+        info!("Registered timestamps middleware");
+    }
+
+    if let Some(json) = config.log.json.as_ref() {
+        executor.register(JsonFields::new(
+            json.field_names.clone(),
+            json.rewrite_message,
+        ));
+        info!("Registered JSON fields middleware");
+    }
+
+    // Levels are read once the JSON fields are extracted
+    if let Some(levels) = config.log.levels {
+        executor.register(Levels::new(levels.detect, levels.min_level_rules));
+        info!("Registered levels middleware");
+    }
+
+    let line_rules = match LineRules::new(
         &config.log.line_exclusion_regex,
         &config.log.line_inclusion_regex,
         &config.log.line_redact_regex,
     ) {
-        Ok(v) => executor.register(v),
+        Ok(v) => Reloadable::new(v),
         Err(e) => {
             error!("line regex is invalid: {}", e);
             std::process::exit(1);
         }
     };
+    let line_rules_handle = line_rules.handle();
+    executor.register(line_rules);
+
+    if let Some(dedup) = config.log.dedup.as_ref() {
+        match Dedup::new(dedup.window, &dedup.normalize_regex, dedup.max_sources) {
+            Ok(v) => executor.register(v),
+            Err(e) => {
+                error!("dedup normalize regex is invalid: {}", e);
+                std::process::exit(1);
+            }
+        }
+        info!("Registered dedup middleware");
+    }
+
+    // Lines are limited once the excluded ones are removed
+    if !config.log.rate_limits.is_empty() || config.log.sampling.is_some() {
+        executor.register(RateLimit::new(
+            config.log.rate_limits.clone(),
+            config.log.sampling.clone(),
+        ));
+        info!("Registered rate limit middleware");
+    }
 
     executor.init();
 
@@ -147,35 +300,60 @@ async fn main() {
     let mut fs_source = FSSource::new(
         config.log.dirs,
         config.log.rules,
+        multiline_rules,
+        config.log.container_log_rules,
         config.log.lookback,
         initial_offsets,
     );
+    let fs_rules_handle = fs_source.rules_handle();
+
+    let journal_origins = journal_origins(
+        &config.journald.paths,
+        &config.journald.namespaces,
+        config.journald.merge,
+    );
 
     #[cfg(feature = "libjournald")]
     let (journalctl_source, journald_source) = if config.journald.paths.is_empty() {
-        let journalctl_source = create_journalctl_source()
-            .map(|s| s.map(StrictOrLazyLineBuilder::Strict))
-            .map_err(|e| {
-                info!("Journalctl source was not initialized");
-                debug!("Journalctl source initialization error: {}", e);
-            });
+        let journalctl_source = create_journalctl_source(
+            &journal_origins,
+            &initial_cursors,
+            &config.journald.filter,
+            &config.journald.meta_fields,
+        )
+        .map(|s| s.map(StrictOrLazyLineBuilder::Journald))
+        .map_err(|e| {
+            info!("Journalctl source was not initialized");
+            debug!("Journalctl source initialization error: {}", e);
+        });
         (journalctl_source.ok(), None)
     } else {
         (
             None,
-            Some(create_source(&config.journald.paths).map(StrictOrLazyLineBuilder::Strict)),
+            Some(
+                create_source(
+                    &journal_origins,
+                    &initial_cursors,
+                    &config.journald.filter,
+                    &config.journald.meta_fields,
+                )
+                .map(StrictOrLazyLineBuilder::Journald),
+            ),
         )
     };
 
     #[cfg(not(feature = "libjournald"))]
-    let journalctl_source = create_journalctl_source()
-        .map(|s| s.map(StrictOrLazyLineBuilder::Strict))
-        .map_err(|e| warn!("Error initializing journalctl source: {}", e))
-        .ok();
-
-    if let Some(offset_state) = offset_state {
-        tokio::spawn(offset_state.run().unwrap());
-    }
+    let journalctl_source = create_journalctl_source(
+        &journal_origins,
+        &initial_cursors,
+        &config.journald.filter,
+        &config.journald.meta_fields,
+    )
+    .map(|s| s.map(StrictOrLazyLineBuilder::Journald))
+    .map_err(|e| warn!("Error initializing journalctl source: {}", e))
+    .ok();
+
+    let offset_driver = offset_state.map(|os| tokio::spawn(os.run().unwrap()));
 
     let fs_source = fs_source
         .process(&mut fs_tailer_buf)
@@ -208,11 +386,14 @@ async fn main() {
             let namespace = std::env::var("NAMESPACE").ok();
             let pod_label = std::env::var("POD_APP_LABEL").ok();
             match (pod_name, namespace, pod_label) {
-                (Some(pod_name), Some(namespace), Some(pod_label)) => {
-                    K8sEventStream::try_default(pod_name, namespace, pod_label)
-                        .map_err(|e| warn!("Error initialising Kubernetes event logging: {}", e))
-                        .ok()
-                }
+                (Some(pod_name), Some(namespace), Some(pod_label)) => K8sEventStream::try_default(
+                    pod_name,
+                    namespace,
+                    pod_label,
+                    config.log.k8s_events_filter,
+                )
+                .map_err(|e| warn!("Error initialising Kubernetes event logging: {}", e))
+                .ok(),
                 (pn, n, pl) => {
                     if pn.is_none() {
                         warn!("Kubernetes event logging is configured, but POD_NAME env is not set")
@@ -243,15 +424,27 @@ async fn main() {
         None
     };
 
+    let syslog_source = if config.syslog.listeners.is_empty() {
+        None
+    } else {
+        syslog::create_source(&config.syslog.listeners)
+            .await
+            .map(|s| s.map(StrictOrLazyLineBuilder::Syslog))
+            .map_err(|e| warn!("Error initializing syslog source: {}", e))
+            .ok()
+    };
+
     pin_mut!(fs_source);
     pin_mut!(k8s_event_source);
     pin_mut!(journalctl_source);
+    pin_mut!(syslog_source);
 
     #[cfg(feature = "libjournald")]
     pin_mut!(journald_source);
 
     let mut k8s_event_source: Option<std::pin::Pin<&mut _>> = k8s_event_source.as_pin_mut();
     let mut journalctl_source: Option<std::pin::Pin<&mut _>> = journalctl_source.as_pin_mut();
+    let mut syslog_source: Option<std::pin::Pin<&mut _>> = syslog_source.as_pin_mut();
 
     #[cfg(feature = "libjournald")]
     let mut journald_source: Option<std::pin::Pin<&mut _>> = journald_source.as_pin_mut();
@@ -281,113 +474,231 @@ async fn main() {
         sources.push(k)
     };
 
-    let lines_stream = sources.map(|line| match line {
-        StrictOrLazyLineBuilder::Strict(mut line) => {
-            if executor.process(&mut line).is_some() {
-                match line.build() {
-                    Ok(line) => Some(StrictOrLazyLines::Strict(line)),
-                    Err(e) => {
-                        error!("Couldn't build line from linebuilder {:?}", e);
-                        None
-                    }
+    if let Some(s) = syslog_source.as_mut() {
+        info!("Enabling syslog source");
+        sources.push(s)
+    };
+
+    // Resolves once the agent is shutting down so that no more lines are read
+    let (stop_sources, sources_stopped) = futures::channel::oneshot::channel::<()>();
+    let sources_stopped = sources_stopped.shared();
+
+    let sources = sources.take_until(sources_stopped.clone());
+
+    let lines_stream = sources.map(|line| {
+        let mut copies = Vec::new();
+        let line = match line {
+            StrictOrLazyLineBuilder::Strict(line) => {
+                let mut line = TimestampedLine {
+                    line,
+                    timestamp: None,
+                };
+                if executor.process(&mut line).is_some()
+                    && route_line(&routes, &mut line, &mut copies)
+                {
+                    build_timestamped_line(line).map(StrictOrLazyLines::Strict)
+                } else {
+                    None
                 }
-            } else {
-                None
             }
-        }
-        StrictOrLazyLineBuilder::Lazy(mut line) => {
-            if executor.process(&mut line).is_some() {
-                Some(StrictOrLazyLines::Lazy(line))
-            } else {
-                None
+            StrictOrLazyLineBuilder::Lazy(mut line) => {
+                if executor.process(&mut line).is_some()
+                    && route_line(&routes, &mut line, &mut copies)
+                {
+                    Some(StrictOrLazyLines::Lazy(line))
+                } else {
+                    None
+                }
+            }
+            StrictOrLazyLineBuilder::Journald(JournalLine {
+                line,
+                timestamp,
+                cursor,
+            }) => {
+                let mut line = TimestampedLine { line, timestamp };
+                if executor.process(&mut line).is_some()
+                    && route_line(&routes, &mut line, &mut copies)
+                {
+                    build_timestamped_line(line).map(|line| {
+                        match (journald_cursors.as_ref(), cursor) {
+                            // The cursor is stored once the batch of the line is acknowledged
+                            (Some(cursors), Some(cursor)) => StrictOrLazyLines::Journald(
+                                line,
+                                cursors.track(&cursor.source, cursor.cursor),
+                            ),
+                            _ => StrictOrLazyLines::Strict(line),
+                        }
+                    })
+                } else {
+                    None
+                }
+            }
+            StrictOrLazyLineBuilder::Syslog(SyslogLine { line, timestamp }) => {
+                let mut line = TimestampedLine { line, timestamp };
+                if executor.process(&mut line).is_some()
+                    && route_line(&routes, &mut line, &mut copies)
+                {
+                    build_timestamped_line(line).map(StrictOrLazyLines::Strict)
+                } else {
+                    None
+                }
+            }
+        };
+        // Lines created by the middlewares, such as the summaries of dropped lines, go first
+        let mut lines = take_created_lines(&executor, &routes, &mut copies);
+        lines.extend(line);
+        (lines, copies)
+    });
+
+    // The lines created by the middlewares are also taken while no line is read, such as the
+    // summary of the lines repeated by a source that stopped writing
+    let created_lines_stream = futures::stream::unfold((), |_| async {
+        tokio::time::sleep(CREATED_LINES_INTERVAL).await;
+        Some(((), ()))
+    })
+    .take_until(sources_stopped.clone())
+    .map(|_| {
+        let mut copies = Vec::new();
+        let lines = take_created_lines(&executor, &routes, &mut copies);
+        (lines, copies)
+    });
+    let lines_stream = futures::stream::select(lines_stream, created_lines_stream);
+
+    // A destination that falls behind holds up the lines until it has room for their copies,
+    // so that the lines routed to it are not lost
+    let full_warnings: Vec<Cell<Option<Instant>>> =
+        routes.iter().map(|_| Cell::new(None)).collect();
+    let lines_stream = lines_stream.then(|(lines, copies)| {
+        let routes = &routes;
+        let full_warnings = &full_warnings;
+        async move {
+            for (index, copy) in copies {
+                let (name, _, sender) = &routes[index];
+                let copy = match sender.borrow_mut().try_send(copy) {
+                    Ok(()) => continue,
+                    Err(e) if e.is_full() => e.into_inner(),
+                    Err(_) => {
+                        debug!("The {} destination is closed, dropping line", name);
+                        continue;
+                    }
+                };
+                let now = Instant::now();
+                if full_warnings[index].get().map_or(true, |warned| {
+                    now.duration_since(warned) >= DESTINATION_FULL_WARNING_INTERVAL
+                }) {
+                    full_warnings[index].set(Some(now));
+                    warn!(
+                        "The {} destination is full, waiting for it to catch up",
+                        name
+                    );
+                }
+                let sent = match poll_fn(|cx| sender.borrow_mut().poll_ready(cx)).await {
+                    Ok(()) => sender.borrow_mut().try_send(copy).is_ok(),
+                    Err(_) => false,
+                };
+                if !sent {
+                    debug!("The {} destination is closed, dropping line", name);
+                }
             }
+            lines
         }
     });
 
     let body_offsets_stream = lines_stream
-        .filter_map(|l| async { l })
+        .flat_map(futures::stream::iter)
         // TODO: paramaterise the flush frequency
         .timed_request_batches(config.http.body_size, Duration::from_millis(250))
         .map(|b| async { b })
         .buffered(1);
 
-    fn handle_client_error<T>(e: ClientError<T>)
-    where
-        T: Send + 'static,
-    {
-        match e {
-            ClientError::BadRequest(s) => {
-                warn!("bad http request: {}", s);
-            }
-            ClientError::Http(e) => {
-                warn!("failed sending http request: {}", e);
-            }
-            ClientError::Retry(r) => {
-                error!("failed to retry request: {}", r);
-            }
-            ClientError::State(s) => {
-                error!("Unable to flush state to disk. error: {}", s);
-            }
-        }
-    }
-
-    fn handle_send_status(s: SendStatus) {
-        match s {
-            SendStatus::Retry(e) => {
-                warn!("failed sending http request, retrying: {}", e);
-            }
-            SendStatus::RetryTimeout => {
-                warn!("failed sending http request, retrying: request timed out!");
-            }
-            _ => {}
-        }
-    }
+    // Set when shutting down, bodies not sent by then are stored for the next run
+    let drain_deadline: Cell<Option<Instant>> = Cell::new(None);
 
     let lines_driver = body_offsets_stream.for_each(|body_offsets| async {
         match body_offsets {
             Ok((body, offsets)) => {
-                match client
-                    .borrow()
-                    .send(body, Some(offsets.items_as_ref()))
-                    .await
-                {
-                    Ok(s) => handle_send_status(s),
-                    Err(e) => handle_client_error(e),
-                }
+                let client = client.borrow();
+                let offsets = Some(offsets.items_as_ref());
+                send_body(&client, body, offsets, drain_deadline.get()).await;
             }
             Err(e) => error!("Couldn't batch lines {:?}", e),
         }
     });
 
-    let retry_driver = retry_stream.into_stream().for_each(|body_offsets| async {
-        match body_offsets {
-            Ok(item) => {
-                let RetryItem {
-                    body_buffer,
-                    offsets,
-                    path,
-                } = item;
-                match client
-                    .borrow()
-                    .send(body_buffer, offsets.as_ref().map(|o| o.as_ref()))
-                    .await
-                {
-                    Ok(s) => match s {
-                        SendStatus::Sent => {
-                            debug!("cleaned up retry file");
-                            if let Err(e) = std::fs::remove_file(path) {
-                                error!("couldn't clean up retry file {}", e)
-                            }
+    let body_size = config.http.body_size;
+    let destination_drivers = destinations.into_iter().map(|destination| {
+        let (name, client, received, retry_stream) = destination;
+        let drain_deadline = &drain_deadline;
+        let retry_stream = retry_stream
+            .into_stream()
+            .take_until(sources_stopped.clone());
+        async move {
+            let lines_driver = received
+                .map(StrictOrLazyLines::Strict)
+                .timed_request_batches(body_size, Duration::from_millis(250))
+                .for_each(|body_offsets| async {
+                    match body_offsets {
+                        Ok((body, offsets)) => {
+                            let offsets = Some(offsets.items_as_ref());
+                            send_body(&client, body, offsets, drain_deadline.get()).await;
                         }
-                        _ => handle_send_status(s),
-                    },
-                    Err(e) => handle_client_error(e),
+                        Err(e) => error!("Couldn't batch lines for {} {:?}", name, e),
+                    }
+                });
+            let retry_driver = retry_stream.for_each(|item| async {
+                match item {
+                    Ok(item) => send_retry(&client, item, drain_deadline.get()).await,
+                    Err(e) => error!("Couldn't read retry file for {} {:?}", name, e),
                 }
-            }
+            });
+            futures::future::join(lines_driver, retry_driver).await;
+        }
+    });
+    let destinations_driver = futures::future::join_all(destination_drivers);
+
+    let retry_stream = retry_stream.into_stream().take_until(sources_stopped);
+
+    let retry_driver = retry_stream.for_each(|body_offsets| async {
+        match body_offsets {
+            Ok(item) => send_retry(&client.borrow(), item, drain_deadline.get()).await,
             Err(e) => error!("Couldn't batch lines {:?}", e),
         }
     });
 
+    let reload_driver = async {
+        let triggers = reload::triggers(config_reloader.files());
+        pin_mut!(triggers);
+        while let Some(trigger) = triggers.next().await {
+            info!("Reloading configuration after {}", trigger);
+            let reloaded = match config_reloader.reload() {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("configuration was not reloaded: {}", e);
+                    continue;
+                }
+            };
+
+            if reloaded.changes.is_empty() && !reloaded.requires_restart {
+                debug!("configuration is unchanged");
+                continue;
+            }
+
+            fs_rules_handle.set(reloaded.config.log.rules).await;
+            line_rules_handle.swap(reloaded.line_rules);
+            field_redactor_handle.swap(reloaded.field_redactor);
+            client.borrow().set_template(reloaded.config.http.template);
+
+            for change in reloaded.changes.iter() {
+                info!("Applied configuration change: {}", change);
+            }
+            if reloaded.requires_restart {
+                warn!("Some of the configuration changes are only applied after a restart");
+            }
+        }
+        // Keep running the agent when the config can't be watched
+        futures::future::pending::<()>().await
+    };
+
     tokio::spawn(async {
         Metrics::log_periodically().await;
     });
@@ -402,13 +713,210 @@ async fn main() {
         });
     }
 
+    pin_mut!(lines_driver);
+    pin_mut!(retry_driver);
+    pin_mut!(destinations_driver);
+    let has_destinations = !routes.is_empty();
+
     // Concurrently run the line streams and listen for the `shutdown` signal
-    tokio::select! {
-        _ = lines_driver => {}
-        _ = retry_driver => {}
-        signal_name = get_signal() => {
-            info!("Received {} signal, shutting down", signal_name)
+    let signal_name = tokio::select! {
+        _ = &mut lines_driver => None,
+        _ = &mut retry_driver => None,
+        _ = &mut destinations_driver, if has_destinations => None,
+        _ = reload_driver => None,
+        signal_name = get_signal() => Some(signal_name),
+    };
+
+    if let Some(signal_name) = signal_name {
+        info!(
+            "Received {} signal, sending buffered lines for up to {:?} before shutting down",
+            signal_name, config.http.shutdown_timeout
+        );
+        drain_deadline.set(Some(Instant::now() + config.http.shutdown_timeout));
+        // Ending the sources makes the batcher emit the lines it holds
+        let _ = stop_sources.send(());
+        let primary_driver = async {
+            futures::future::join(lines_driver, retry_driver).await;
+            // No more lines are copied to the other destinations
+            for (_, _, sender) in routes.iter() {
+                sender.borrow_mut().close_channel();
+            }
+        };
+        futures::future::join(primary_driver, destinations_driver).await;
+    }
+
+    // Write the offsets of the lines that were sent before the state is closed
+    if let Some(offset_shutdown) = offset_shutdown {
+        offset_shutdown.shutdown();
+    }
+    if let Some(offset_driver) = offset_driver {
+        if let Err(e) = offset_driver.await {
+            error!("Unable to flush offsets on shutdown. error: {}", e);
+        }
+    }
+    info!("Shutdown complete");
+}
+
+/// Takes the lines created by the middlewares and routes them like the lines read from the
+/// sources, they are sent with the timestamp set by the middleware
+fn take_created_lines(
+    executor: &Executor,
+    routes: &[(String, Route, RefCell<Sender<Line>>)],
+    copies: &mut Vec<(usize, Line)>,
+) -> Vec<StrictOrLazyLines> {
+    executor
+        .take_lines()
+        .into_iter()
+        .filter_map(|mut created| {
+            if !route_line(routes, &mut created, copies) {
+                return None;
+            }
+            build_timestamped_line(created).map(StrictOrLazyLines::Strict)
+        })
+        .collect()
+}
+
+/// Builds a line sent with the timestamp set by the source or the middlewares, if any
+fn build_timestamped_line(line: TimestampedLine) -> Option<Line> {
+    let timestamp = line.timestamp;
+    build_line(line.line).map(|mut line| {
+        if let Some(timestamp) = timestamp {
+            line.timestamp = timestamp;
+        }
+        line
+    })
+}
+
+fn build_line(line: LineBuilder) -> Option<Line> {
+    match line.build() {
+        Ok(line) => Some(line),
+        Err(e) => {
+            error!("Couldn't build line from linebuilder {:?}", e);
+            None
+        }
+    }
+}
+
+/// Reads the multi-line pattern of the container files from the annotations of their pod
+fn k8s_multiline_lookup(k8s_metadata: Arc<K8sMetadata>) -> MultilineLookup {
+    Box::new(move |path| {
+        let file = path.to_str()?;
+        let hints = k8s_metadata.file_hints(file)?;
+        MultilineRule::new(
+            file,
+            Some(hints.multiline_pattern()?),
+            None,
+            DEFAULT_MAX_LINES,
+            DEFAULT_MAX_BYTES,
+            DEFAULT_FLUSH_TIMEOUT,
+        )
+        .map_err(|e| warn!("ignoring the multi-line pattern of {}: {}", file, e))
+        .ok()
+    })
+}
+
+/// Decides which destinations receive the line, copies are made for the destinations other
+/// than the primary one. Returns whether the primary destination receives the line.
+///
+/// The copies keep the timestamp of the line.
+fn route_line(
+    routes: &[(String, Route, RefCell<Sender<Line>>)],
+    line: &mut dyn LineMut,
+    copies: &mut Vec<(usize, Line)>,
+) -> bool {
+    let timestamp = line.get_timestamp();
+    let mut primary = true;
+    for (index, (name, route, _)) in routes.iter().enumerate() {
+        if !route.matches(line) {
+            continue;
+        }
+        match copy_line(line).map(|copy| copy.build()) {
+            Some(Ok(mut copy)) => {
+                if let Some(timestamp) = timestamp {
+                    copy.timestamp = timestamp;
+                }
+                copies.push((index, copy))
+            }
+            Some(Err(e)) => error!("Couldn't build line for {} {:?}", name, e),
+            None => warn!("Couldn't read line to copy it for {}", name),
+        }
+        primary &= !route.exclusive;
+    }
+    primary
+}
+
+fn handle_client_error<T>(e: ClientError<T>)
+where
+    T: Send + 'static,
+{
+    match e {
+        ClientError::BadRequest(s) => {
+            warn!("bad http request: {}", s);
+        }
+        ClientError::Http(e) => {
+            warn!("failed sending http request: {}", e);
+        }
+        ClientError::Retry(r) => {
+            error!("failed to retry request: {}", r);
+        }
+        ClientError::State(s) => {
+            error!("Unable to flush state to disk. error: {}", s);
+        }
+        ClientError::Sink(e) => {
+            error!("failed writing to the output sink: {}", e);
+        }
+    }
+}
+
+fn handle_send_status(s: SendStatus) {
+    match s {
+        SendStatus::Retry(e) => {
+            warn!("failed sending http request, retrying: {}", e);
+        }
+        SendStatus::RetryTimeout => {
+            warn!("failed sending http request, retrying: request timed out!");
+        }
+        _ => {}
+    }
+}
+
+/// Sends the body, when shutting down the body is stored for the next run if it couldn't be
+/// sent before the deadline
+async fn send(
+    client: &Client,
+    body: IngestBodyBuffer,
+    offsets: Option<&[Offset]>,
+    deadline: Option<Instant>,
+) -> Result<SendStatus, ClientError<IngestBodyBuffer>> {
+    match deadline {
+        Some(deadline) => client.send_until(body, offsets, deadline).await,
+        None => client.send(body, offsets).await,
+    }
+}
+
+async fn send_body(
+    client: &Client,
+    body: IngestBodyBuffer,
+    offsets: Option<&[Offset]>,
+    deadline: Option<Instant>,
+) {
+    match send(client, body, offsets, deadline).await {
+        Ok(s) => handle_send_status(s),
+        Err(e) => handle_client_error(e),
+    }
+}
+
+async fn send_retry(client: &Client, item: RetryItem, deadline: Option<Instant>) {
+    let path = item.path.clone();
+    match client.send_retry(item, deadline).await {
+        Ok(SendStatus::Sent) => {
+            debug!("cleaned up retry file");
+            if let Err(e) = std::fs::remove_file(path) {
+                error!("couldn't clean up retry file {}", e)
+            }
         }
+        Ok(s) => handle_send_status(s),
+        Err(e) => handle_client_error(e),
     }
 }
 
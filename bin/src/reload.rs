@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use futures::{Stream, StreamExt};
+use inotify::{Inotify, WatchMask};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Returns a stream yielding the reason every time the configuration should be read again,
+/// either a SIGHUP was received or one of the config files changed
+pub fn triggers(files: Vec<PathBuf>) -> impl Stream<Item = &'static str> {
+    let sighup = signal(SignalKind::hangup())
+        .map_err(|e| warn!("unable to listen for SIGHUP: {}", e))
+        .ok()
+        .map(|sighup| {
+            futures::stream::unfold(sighup, |mut sighup| async {
+                sighup.recv().await.map(|_| ("SIGHUP", sighup))
+            })
+        });
+
+    let changes = watch(&files)
+        .map_err(|e| warn!("unable to watch the config files for changes: {}", e))
+        .ok();
+
+    futures::stream::select(
+        futures::stream::iter(sighup).flatten(),
+        futures::stream::iter(changes).flatten(),
+    )
+}
+
+fn watch(files: &[PathBuf]) -> std::io::Result<impl Stream<Item = &'static str>> {
+    let mut inotify = Inotify::init()?;
+    let mut names = HashSet::new();
+    let mut dirs = HashSet::new();
+    for file in files {
+        // The directory is watched as editors and Kubernetes ConfigMaps replace the file
+        if let (Some(dir), Some(name)) = (file.parent(), file.file_name()) {
+            names.insert(name.to_os_string());
+            dirs.insert(dir.to_path_buf());
+        }
+    }
+
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        inotify.add_watch(
+            dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::DELETE,
+        )?;
+    }
+
+    Ok(inotify
+        .event_stream(vec![0u8; 4096])?
+        .filter_map(move |event| {
+            let changed = match event {
+                Ok(event) => event
+                    .name
+                    .map_or(false, |name| is_config_change(&names, &name)),
+                Err(e) => {
+                    warn!("error watching the config files: {}", e);
+                    false
+                }
+            };
+            async move {
+                if changed {
+                    Some("config file change")
+                } else {
+                    None
+                }
+            }
+        }))
+}
+
+/// Kubernetes updates the files of a mounted ConfigMap by replacing the `..data` symlink
+fn is_config_change(names: &HashSet<OsString>, name: &OsString) -> bool {
+    names.contains(name) || name.to_string_lossy().starts_with("..")
+}
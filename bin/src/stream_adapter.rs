@@ -6,18 +6,24 @@ use http::types::serialize::{
     IngestLineSerialize, IngestLineSerializeError, SerializeI64, SerializeMap, SerializeStr,
     SerializeUtf8, SerializeValue,
 };
-use state::GetOffset;
+use journald::JournalLine;
+use state::{GetOffset, JOURNALD_OFFSET_KEY};
 use std::collections::HashMap;
+use syslog::SyslogLine;
 
 pub(crate) enum StrictOrLazyLineBuilder {
     Strict(LineBuilder),
     Lazy(LazyLineSerializer),
+    Journald(JournalLine),
+    Syslog(SyslogLine),
 }
 
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum StrictOrLazyLines {
     Strict(Line),
     Lazy(LazyLineSerializer),
+    /// A journald entry with the sequence number tracking its cursor
+    Journald(Line, u64),
 }
 
 #[async_trait]
@@ -28,7 +34,9 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
 
     fn has_annotations(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_annotations().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_annotations().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_annotations().is_some(),
         }
     }
@@ -40,13 +48,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).annotations(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).annotations(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.annotations(writer).await,
         }
     }
     fn has_app(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_app().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_app().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_app().is_some(),
         }
     }
@@ -55,13 +67,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).app(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).app(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.app(writer).await,
         }
     }
     fn has_env(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_env().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_env().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_env().is_some(),
         }
     }
@@ -70,13 +86,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).env(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).env(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.env(writer).await,
         }
     }
     fn has_file(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_file().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_file().is_some()
+            }
             StrictOrLazyLines::Lazy(_) => true,
         }
     }
@@ -85,13 +105,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).file(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).file(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.file(writer).await,
         }
     }
     fn has_host(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_host().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_host().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_host().is_some(),
         }
     }
@@ -100,13 +124,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).host(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).host(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.host(writer).await,
         }
     }
     fn has_labels(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_labels().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_labels().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_labels().is_some(),
         }
     }
@@ -115,13 +143,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).labels(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).labels(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.labels(writer).await,
         }
     }
     fn has_level(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_level().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_level().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_level().is_some(),
         }
     }
@@ -130,13 +162,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).level(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).level(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.level(writer).await,
         }
     }
     fn has_meta(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_meta().is_some(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                line.get_meta().is_some()
+            }
             StrictOrLazyLines::Lazy(line) => line.get_meta().is_some(),
         }
     }
@@ -145,7 +181,9 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeValue + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).meta(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).meta(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.meta(writer).await,
         }
     }
@@ -154,7 +192,9 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeUtf8<bytes::Bytes> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).line(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).line(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.line(writer).await,
         }
     }
@@ -163,13 +203,17 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeI64 + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).timestamp(writer).await,
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).timestamp(writer).await
+            }
             StrictOrLazyLines::Lazy(line) => line.timestamp(writer).await,
         }
     }
     fn field_count(&self) -> usize {
         match self {
-            StrictOrLazyLines::Strict(line) => (&*line).field_count(),
+            StrictOrLazyLines::Strict(line) | StrictOrLazyLines::Journald(line, _) => {
+                (&*line).field_count()
+            }
             StrictOrLazyLines::Lazy(line) => line.field_count(),
         }
     }
@@ -180,6 +224,7 @@ impl GetOffset for StrictOrLazyLines {
         match self {
             StrictOrLazyLines::Strict(_) => None,
             StrictOrLazyLines::Lazy(line) => line.get_offset(),
+            StrictOrLazyLines::Journald(_, seq) => Some(*seq),
         }
     }
 
@@ -187,6 +232,7 @@ impl GetOffset for StrictOrLazyLines {
         match self {
             StrictOrLazyLines::Strict(_) => None,
             StrictOrLazyLines::Lazy(line) => line.get_key(),
+            StrictOrLazyLines::Journald(..) => Some(JOURNALD_OFFSET_KEY),
         }
     }
 }
@@ -39,6 +39,16 @@ pub mod env {
     pub const REDACT: &str = "LOGDNA_REDACT_REGEX";
     pub const INGEST_TIMEOUT: &str = "LOGDNA_INGEST_TIMEOUT";
     pub const INGEST_BUFFER_SIZE: &str = "LOGDNA_INGEST_BUFFER_SIZE";
+    pub const SHUTDOWN_TIMEOUT: &str = "LOGDNA_SHUTDOWN_TIMEOUT";
+    pub const RETRY_DIR: &str = "LOGDNA_RETRY_DIR";
+    pub const RETRY_DISK_LIMIT: &str = "LOGDNA_RETRY_DISK_LIMIT";
+    pub const RETRY_MAX_AGE: &str = "LOGDNA_RETRY_MAX_AGE";
+    pub const SYSLOG_UDP: &str = "LOGDNA_SYSLOG_UDP";
+    pub const SYSLOG_TCP: &str = "LOGDNA_SYSLOG_TCP";
+    pub const OUTPUT_SINK: &str = "LOGDNA_OUTPUT_SINK";
+    pub const OUTPUT_PATH: &str = "LOGDNA_OUTPUT_PATH";
+    pub const OUTPUT_MAX_SIZE: &str = "LOGDNA_OUTPUT_MAX_SIZE";
+    pub const OUTPUT_MAX_FILES: &str = "LOGDNA_OUTPUT_MAX_FILES";
 
     pub const INGESTION_KEY_ALTERNATE: &str = "LOGDNA_AGENT_KEY";
     pub const CONFIG_FILE_DEPRECATED: &str = "DEFAULT_CONF_FILE";
@@ -141,6 +151,14 @@ pub struct ArgumentOptions {
     #[structopt(long, env = env::JOURNALD_PATHS)]
     journald_paths: Vec<String>,
 
+    /// List of addresses to receive syslog messages on over UDP, for example: 0.0.0.0:514
+    #[structopt(long, env = env::SYSLOG_UDP)]
+    syslog_udp: Vec<String>,
+
+    /// List of addresses to receive syslog messages on over TCP, for example: 0.0.0.0:601
+    #[structopt(long, env = env::SYSLOG_TCP)]
+    syslog_tcp: Vec<String>,
+
     /// The lookback strategy on startup ("smallfiles", "start" or "none").
     /// Defaults to "smallfiles".
     #[structopt(long, env = env::LOOKBACK)]
@@ -201,6 +219,43 @@ pub struct ArgumentOptions {
     /// Defaults to 2097152 (2 MB).
     #[structopt(long, env = env::INGEST_BUFFER_SIZE)]
     ingest_buffer_size: Option<usize>,
+
+    /// The time in milliseconds given on shutdown to send the buffered log content and
+    /// store the file offsets. Defaults to 5000 ms.
+    #[structopt(long, env = env::SHUTDOWN_TIMEOUT)]
+    shutdown_timeout: Option<u64>,
+
+    /// The directory in which the log content that couldn't be sent is stored to be retried.
+    /// Defaults to /tmp/logdna.
+    #[structopt(long, env = env::RETRY_DIR)]
+    retry_dir: Option<String>,
+
+    /// The maximum size, in bytes, of the log content stored to be retried, the oldest content
+    /// is discarded first.
+    #[structopt(long, env = env::RETRY_DISK_LIMIT)]
+    retry_disk_limit: Option<u64>,
+
+    /// The maximum age, in milliseconds, of the log content stored to be retried.
+    #[structopt(long, env = env::RETRY_MAX_AGE)]
+    retry_max_age: Option<u64>,
+
+    /// Where the log content is sent ("http", "file" or "stdout"). The "file" and "stdout"
+    /// sinks write the content that would be sent as NDJSON instead. Defaults to "http".
+    #[structopt(long, env = env::OUTPUT_SINK)]
+    output_sink: Option<String>,
+
+    /// The file written by the "file" sink.
+    #[structopt(long, env = env::OUTPUT_PATH)]
+    output_path: Option<String>,
+
+    /// The size, in bytes, at which the file written by the "file" sink is rotated.
+    /// Defaults to 10485760.
+    #[structopt(long, env = env::OUTPUT_MAX_SIZE)]
+    output_max_size: Option<u64>,
+
+    /// The number of rotated files kept by the "file" sink. Defaults to 5.
+    #[structopt(long, env = env::OUTPUT_MAX_FILES)]
+    output_max_files: Option<usize>,
 }
 
 impl ArgumentOptions {
@@ -271,6 +326,38 @@ impl ArgumentOptions {
             raw.http.body_size = self.ingest_buffer_size;
         }
 
+        if self.shutdown_timeout.is_some() {
+            raw.http.shutdown_timeout = self.shutdown_timeout;
+        }
+
+        if self.retry_dir.is_some() {
+            raw.http.retry_dir = self.retry_dir.map(PathBuf::from);
+        }
+
+        if self.retry_disk_limit.is_some() {
+            raw.http.retry_disk_limit = self.retry_disk_limit;
+        }
+
+        if self.retry_max_age.is_some() {
+            raw.http.retry_max_age = self.retry_max_age;
+        }
+
+        if self.output_sink.is_some() {
+            raw.output.sink = self.output_sink;
+        }
+
+        if self.output_path.is_some() {
+            raw.output.path = self.output_path.map(PathBuf::from);
+        }
+
+        if self.output_max_size.is_some() {
+            raw.output.max_size = self.output_max_size;
+        }
+
+        if self.output_max_files.is_some() {
+            raw.output.max_files = self.output_max_files;
+        }
+
         if !self.log_dirs.is_empty() {
             with_csv(self.log_dirs)
                 .iter()
@@ -303,6 +390,16 @@ impl ArgumentOptions {
                 .for_each(|v| paths.push(PathBuf::from(v)));
         }
 
+        if !self.syslog_udp.is_empty() {
+            let udp = raw.syslog.udp.get_or_insert(Vec::new());
+            udp.extend(with_csv(self.syslog_udp));
+        }
+
+        if !self.syslog_tcp.is_empty() {
+            let tcp = raw.syslog.tcp.get_or_insert(Vec::new());
+            tcp.extend(with_csv(self.syslog_tcp));
+        }
+
         if self.lookback.is_some() {
             raw.log.lookback = self.lookback.map(|v| v.to_string());
         }
@@ -589,6 +686,11 @@ mod test {
         assert_eq!(config.http.use_compression, Some(true));
         assert_eq!(config.http.gzip_level, Some(2));
         assert_eq!(config.http.body_size, Some(2 * 1024 * 1024));
+        assert_eq!(config.http.shutdown_timeout, Some(5_000));
+        assert_eq!(config.http.retry_dir, Some(PathBuf::from("/tmp/logdna")));
+        assert_eq!(config.http.retry_disk_limit, None);
+        assert_eq!(config.http.retry_max_age, None);
+        assert_eq!(config.output.sink, None);
         assert_eq!(config.log.lookback, None);
         assert_eq!(config.log.dirs, vec![PathBuf::from("/var/log/")]);
         assert_eq!(
@@ -625,6 +727,15 @@ mod test {
             journald_paths: vec_strings!("/a"),
             ingest_timeout: Some(1111111),
             ingest_buffer_size: Some(222222),
+            shutdown_timeout: Some(3333),
+            retry_dir: some_string!("/retry"),
+            retry_disk_limit: Some(4444),
+            retry_max_age: Some(5555),
+            syslog_tcp: vec_strings!("0.0.0.0:601"),
+            output_sink: some_string!("file"),
+            output_path: some_string!("/out/agent.ndjson"),
+            output_max_size: Some(6666),
+            output_max_files: Some(7),
             ..ArgumentOptions::default()
         };
         let config = argv.merge(RawConfig::default());
@@ -635,6 +746,14 @@ mod test {
         assert_eq!(config.http.gzip_level, Some(3));
         assert_eq!(config.http.timeout, Some(1111111));
         assert_eq!(config.http.body_size, Some(222222));
+        assert_eq!(config.http.shutdown_timeout, Some(3333));
+        assert_eq!(config.http.retry_dir, Some(PathBuf::from("/retry")));
+        assert_eq!(config.http.retry_disk_limit, Some(4444));
+        assert_eq!(config.http.retry_max_age, Some(5555));
+        assert_eq!(config.output.sink, some_string!("file"));
+        assert_eq!(config.output.path, Some(PathBuf::from("/out/agent.ndjson")));
+        assert_eq!(config.output.max_size, Some(6666));
+        assert_eq!(config.output.max_files, Some(7));
         let params = config.http.params.unwrap();
         assert_eq!(params.hostname, "my_host");
         assert_eq!(params.tags, Some(Tags::from(vec_strings!("a", "b"))));
@@ -650,6 +769,7 @@ mod test {
         assert_eq!(config.log.db_path, Some(PathBuf::from("a/b/c")));
         assert_eq!(config.log.metrics_port, Some(9089));
         assert_eq!(config.journald.paths, Some(vec_paths!["/a"]));
+        assert_eq!(config.syslog.tcp, Some(vec_strings!("0.0.0.0:601")));
     }
 
     #[test]
@@ -657,6 +777,7 @@ mod test {
         let argv = ArgumentOptions {
             log_dirs: vec_strings!("/my/path,/other"),
             journald_paths: vec_strings!("/a,/b"),
+            syslog_udp: vec_strings!("0.0.0.0:514,[::]:514"),
             ..ArgumentOptions::default()
         };
         let config = argv.merge(RawConfig::default());
@@ -665,6 +786,10 @@ mod test {
             vec_paths!["/var/log", "/my/path", "/other"]
         );
         assert_eq!(config.journald.paths, Some(vec_paths!["/a", "/b"]));
+        assert_eq!(
+            config.syslog.udp,
+            Some(vec_strings!("0.0.0.0:514", "[::]:514"))
+        );
     }
 
     #[test]
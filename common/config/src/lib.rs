@@ -4,16 +4,34 @@ extern crate log;
 use std::convert::{TryFrom, TryInto};
 use std::fs::File;
 use std::io::Read;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use sysinfo::{RefreshKind, System, SystemExt};
 
 use async_compression::Level;
 
+use fs::container::container_log_rules;
+use fs::multiline::{
+    MultilineRule, MultilineRules, DEFAULT_FLUSH_TIMEOUT, DEFAULT_MAX_BYTES, DEFAULT_MAX_LINES,
+};
 use fs::rule::{GlobRule, RegexRule, Rules};
 use fs::tail::{DirPathBuf, Lookback};
+use http::retry::RetryLimits;
 use http::types::request::{Encoding, RequestTemplate, Schema};
+use journald::filter::{parse_priority, JournalFilter, JournalMatch};
+use k8s::event_source::EventFilter;
+use k8s::middleware::{EnrichmentField, PodFilter, PodSelector};
 use k8s::K8sTrackingConf;
+use middleware::dedup::{DEFAULT_MAX_SOURCES, DEFAULT_WINDOW};
+use middleware::file_fields::{FieldTemplates, FileFieldRule};
+use middleware::json_fields::JsonFieldNames;
+use middleware::levels::{Level, MinLevelRule};
+use middleware::line_rules::{FieldRedaction, RedactMode};
+use middleware::rate_limit::{LineField, RateLimitRule, Sampling};
+use middleware::routing::{Route, RouteRule};
+use middleware::timestamps::{TimestampFormat, TimestampRule};
+use syslog::Listener;
 
 use crate::argv::ArgumentOptions;
 use crate::error::ConfigError;
@@ -23,6 +41,7 @@ mod argv;
 pub mod error;
 mod properties;
 pub mod raw;
+pub mod reload;
 
 // Symbols that will be populated in the main.rs file
 extern "Rust" {
@@ -65,6 +84,11 @@ pub struct Config {
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    pub syslog: SyslogConfig,
+    pub output: OutputConfig,
+    pub destinations: Vec<DestinationConfig>,
+    // The settings the config was built from, used to detect changes on reload
+    raw: RawConfig,
 }
 
 #[derive(Debug)]
@@ -72,6 +96,9 @@ pub struct HttpConfig {
     pub template: RequestTemplate,
     pub timeout: Duration,
     pub body_size: usize,
+    pub shutdown_timeout: Duration,
+    pub retry_dir: PathBuf,
+    pub retry_limits: RetryLimits,
 
     // Development only settings
     pub retry_base_delay: Duration,
@@ -84,17 +111,83 @@ pub struct LogConfig {
     pub db_path: DbPath,
     pub metrics_port: Option<u16>,
     pub rules: Rules,
+    pub multiline_rules: MultilineRules,
+    /// The files whose container runtime format is detected
+    pub container_log_rules: Rules,
     pub line_exclusion_regex: Vec<String>,
     pub line_inclusion_regex: Vec<String>,
     pub line_redact_regex: Vec<String>,
+    pub line_redact_fields: Vec<FieldRedaction>,
     pub lookback: Lookback,
     pub use_k8s_enrichment: K8sTrackingConf,
     pub log_k8s_events: K8sTrackingConf,
+    pub k8s_filter: PodFilter,
+    pub k8s_events_filter: EventFilter,
+    pub k8s_metadata_fields: Vec<EnrichmentField>,
+    pub json: Option<JsonConfig>,
+    pub rate_limits: Vec<RateLimitRule>,
+    pub sampling: Option<Sampling>,
+    pub dedup: Option<DedupConfig>,
+    pub timestamp_rules: Vec<TimestampRule>,
+    pub levels: Option<LevelsConfig>,
+    pub file_fields: Vec<FileFieldRule>,
+}
+
+#[derive(Debug)]
+pub struct LevelsConfig {
+    pub detect: bool,
+    pub min_level_rules: Vec<MinLevelRule>,
+}
+
+#[derive(Debug)]
+pub struct DedupConfig {
+    pub window: Duration,
+    pub normalize_regex: Vec<String>,
+    pub max_sources: usize,
+}
+
+#[derive(Debug)]
+pub struct JsonConfig {
+    pub field_names: JsonFieldNames,
+    pub rewrite_message: bool,
 }
 
 #[derive(Debug)]
 pub struct JournaldConfig {
     pub paths: Vec<PathBuf>,
+    /// The systemd namespaces whose journals are read
+    pub namespaces: Vec<String>,
+    /// Whether the journals of the machine are merged with the remote journals
+    pub merge: bool,
+    pub filter: JournalFilter,
+    /// The fields of the journal entries put into the meta of their lines
+    pub meta_fields: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct SyslogConfig {
+    pub listeners: Vec<Listener>,
+}
+
+/// An ingestion destination receiving the lines matched by its route
+#[derive(Debug)]
+pub struct DestinationConfig {
+    pub name: String,
+    pub template: RequestTemplate,
+    pub retry_dir: PathBuf,
+    pub route: Route,
+}
+
+/// Where the batched log lines are written
+#[derive(Debug, PartialEq)]
+pub enum OutputConfig {
+    Http,
+    Stdout,
+    File {
+        path: PathBuf,
+        max_size: u64,
+        max_files: usize,
+    },
 }
 
 impl Config {
@@ -144,11 +237,38 @@ impl TryFrom<RawConfig> for Config {
     type Error = ConfigError;
 
     fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+        let original = raw.clone();
         let mut template_builder = RequestTemplate::builder();
 
-        template_builder.api_key(raw.http.ingestion_key.filter(|s| !s.is_empty()).ok_or(
-            ConfigError::MissingFieldOrEnvVar("http.ingestion_key", argv::env::INGESTION_KEY),
-        )?);
+        let output = match raw.output.sink.as_deref() {
+            None | Some("http") => OutputConfig::Http,
+            Some("stdout") => OutputConfig::Stdout,
+            Some("file") => OutputConfig::File {
+                path: raw.output.path.ok_or(ConfigError::MissingFieldOrEnvVar(
+                    "output.path",
+                    argv::env::OUTPUT_PATH,
+                ))?,
+                max_size: raw.output.max_size.unwrap_or(10 * 1024 * 1024),
+                max_files: raw.output.max_files.unwrap_or(5),
+            },
+            Some(sink) => {
+                return Err(ConfigError::PropertyInvalid(format!(
+                    "{} is not a valid output sink",
+                    sink
+                )))
+            }
+        };
+
+        let ingestion_key = raw.http.ingestion_key.filter(|s| !s.is_empty());
+        // The key is only needed to send to the ingestion API
+        let ingestion_key = match output {
+            OutputConfig::Http => ingestion_key.ok_or(ConfigError::MissingFieldOrEnvVar(
+                "http.ingestion_key",
+                argv::env::INGESTION_KEY,
+            ))?,
+            _ => ingestion_key.unwrap_or_default(),
+        };
+        template_builder.api_key(ingestion_key);
 
         let use_ssl = raw.http.use_ssl.ok_or(ConfigError::MissingFieldOrEnvVar(
             "http.use_ssl",
@@ -217,6 +337,8 @@ impl TryFrom<RawConfig> for Config {
         let (pkg_name, pkg_version) = unsafe { (PKG_NAME, PKG_VERSION) };
 
         template_builder.user_agent(format!("{}/{} ({})", pkg_name, pkg_version, info).as_str());
+        // The other destinations only differ in where the lines are sent
+        let destination_template = template_builder.clone();
 
         let http = HttpConfig {
             template: template_builder.build()?,
@@ -229,6 +351,19 @@ impl TryFrom<RawConfig> for Config {
                 .http
                 .body_size
                 .ok_or(ConfigError::MissingField("http.body_size"))?,
+            shutdown_timeout: Duration::from_millis(
+                raw.http
+                    .shutdown_timeout
+                    .ok_or(ConfigError::MissingField("http.shutdown_timeout"))?,
+            ),
+            retry_dir: raw
+                .http
+                .retry_dir
+                .ok_or(ConfigError::MissingField("http.retry_dir"))?,
+            retry_limits: RetryLimits {
+                disk_limit: raw.http.retry_disk_limit,
+                max_age: raw.http.retry_max_age.map(Duration::from_millis),
+            },
             retry_base_delay: Duration::from_millis(
                 raw.http.retry_base_delay_ms.unwrap_or(15_000) as u64
             ),
@@ -255,9 +390,12 @@ impl TryFrom<RawConfig> for Config {
             db_path: DbPath::from(raw.log.db_path),
             metrics_port: raw.log.metrics_port,
             rules: Rules::new(),
+            multiline_rules: MultilineRules::new(),
+            container_log_rules: container_log_rules(),
             line_exclusion_regex: raw.log.line_exclusion_regex.unwrap_or_default(),
             line_inclusion_regex: raw.log.line_inclusion_regex.unwrap_or_default(),
             line_redact_regex: raw.log.line_redact_regex.unwrap_or_default(),
+            line_redact_fields: Vec::new(),
             lookback: raw
                 .log
                 .lookback
@@ -273,6 +411,89 @@ impl TryFrom<RawConfig> for Config {
                 argv::env::LOG_K8S_EVENTS,
                 K8sTrackingConf::Never,
             ),
+            k8s_filter: raw
+                .log
+                .k8s_filter
+                .map(|filter| {
+                    let selectors = |selectors: Option<Vec<raw::PodSelector>>| {
+                        selectors
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|s| PodSelector {
+                                namespace: s.namespace,
+                                pod: s.pod,
+                                container: s.container,
+                                labels: s.labels.unwrap_or_default(),
+                                annotations: s.annotations.unwrap_or_default(),
+                            })
+                            .collect()
+                    };
+                    PodFilter {
+                        include: selectors(filter.include),
+                        exclude: selectors(filter.exclude),
+                    }
+                })
+                .unwrap_or_default(),
+            k8s_events_filter: raw
+                .log
+                .k8s_events_filter
+                .map(|filter| {
+                    let types = filter
+                        .types
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|type_| match type_.to_ascii_lowercase().as_str() {
+                            "normal" => Ok("Normal".to_string()),
+                            "warning" => Ok("Warning".to_string()),
+                            _ => Err(ConfigError::PropertyInvalid(format!(
+                                "{} is not a valid k8s event type",
+                                type_
+                            ))),
+                        })
+                        .collect::<Result<_, ConfigError>>()?;
+                    Ok::<_, ConfigError>(EventFilter {
+                        namespaces: filter.namespaces.unwrap_or_default(),
+                        kinds: filter.kinds.unwrap_or_default(),
+                        types,
+                        reasons: filter.reasons.unwrap_or_default(),
+                    })
+                })
+                .transpose()?
+                .unwrap_or_default(),
+            k8s_metadata_fields: raw
+                .log
+                .k8s_metadata_fields
+                .unwrap_or_default()
+                .iter()
+                .map(|field| {
+                    field
+                        .parse::<EnrichmentField>()
+                        .map_err(|e| ConfigError::PropertyInvalid(e.to_string()))
+                })
+                .collect::<Result<_, _>>()?,
+            json: raw.log.json.map(|json| JsonConfig {
+                field_names: JsonFieldNames {
+                    level: Some(json.level_field.unwrap_or_else(|| "level".into())),
+                    app: json.app_field,
+                    meta: json.meta_field,
+                    timestamp: Some(json.timestamp_field.unwrap_or_else(|| "timestamp".into())),
+                    message: Some(json.message_field.unwrap_or_else(|| "message".into())),
+                },
+                rewrite_message: json.rewrite_message.unwrap_or(false),
+            }),
+            rate_limits: Vec::new(),
+            sampling: None,
+            dedup: raw.log.dedup.map(|dedup| DedupConfig {
+                window: dedup
+                    .window_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_WINDOW),
+                normalize_regex: dedup.normalize_regex.unwrap_or_default(),
+                max_sources: dedup.max_sources.unwrap_or(DEFAULT_MAX_SOURCES),
+            }),
+            timestamp_rules: Vec::new(),
+            levels: None,
+            file_fields: Vec::new(),
         };
 
         if log.use_k8s_enrichment == K8sTrackingConf::Never
@@ -306,14 +527,259 @@ impl TryFrom<RawConfig> for Config {
             }
         }
 
+        for rule in raw.log.multiline.unwrap_or_default() {
+            log.multiline_rules.add(MultilineRule::new(
+                &rule.glob,
+                rule.start_regex.as_deref(),
+                rule.continuation_regex.as_deref(),
+                rule.max_lines.unwrap_or(DEFAULT_MAX_LINES),
+                rule.max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+                rule.flush_timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_FLUSH_TIMEOUT),
+            )?)
+        }
+
+        if let Some(globs) = raw.log.container_log_globs {
+            log.container_log_rules = Rules::new();
+            for glob in globs {
+                log.container_log_rules
+                    .add_inclusion(GlobRule::new(&*glob)?)
+            }
+        }
+
+        for rule in raw.log.line_redact_fields.unwrap_or_default() {
+            let mode = match rule.mode.as_deref() {
+                None | Some("redact") => RedactMode::Replace,
+                Some("hash") => RedactMode::Hash {
+                    salt: rule.salt.unwrap_or_default(),
+                },
+                Some("last4") => RedactMode::KeepLastFour,
+                Some(mode) => {
+                    return Err(ConfigError::PropertyInvalid(format!(
+                        "{} is not a valid redaction mode for {}",
+                        mode, rule.path
+                    )))
+                }
+            };
+            log.line_redact_fields.push(FieldRedaction {
+                path: rule.path,
+                mode,
+            });
+        }
+
+        for rule in raw.log.timestamps.unwrap_or_default() {
+            let invalid = |e| {
+                ConfigError::PropertyInvalid(format!(
+                    "{} timestamp rule is invalid: {}",
+                    rule.glob, e
+                ))
+            };
+            let format = rule.format.parse::<TimestampFormat>().map_err(invalid)?;
+            log.timestamp_rules.push(
+                TimestampRule::new(&rule.glob, format, rule.regex.as_deref()).map_err(invalid)?,
+            );
+        }
+
+        for rule in raw.log.file_fields.unwrap_or_default() {
+            let fields = FieldTemplates {
+                app: rule.app,
+                env: rule.env,
+                meta: rule.meta.unwrap_or_default(),
+                labels: rule.labels.unwrap_or_default(),
+            };
+            log.file_fields.push(
+                FileFieldRule::new(rule.glob.as_deref(), rule.regex.as_deref(), fields).map_err(
+                    |e| ConfigError::PropertyInvalid(format!("file fields rule is invalid: {}", e)),
+                )?,
+            );
+        }
+
+        if let Some(levels) = raw.log.levels {
+            let mut min_level_rules = Vec::new();
+            for rule in levels.min_levels.unwrap_or_default() {
+                let invalid =
+                    |e| ConfigError::PropertyInvalid(format!("min level rule is invalid: {}", e));
+                let min_level = rule.min_level.parse::<Level>().map_err(invalid)?;
+                min_level_rules.push(
+                    MinLevelRule::new(rule.glob.as_deref(), rule.app.as_deref(), min_level)
+                        .map_err(invalid)?,
+                );
+            }
+            log.levels = Some(LevelsConfig {
+                detect: levels.detect.unwrap_or(true),
+                min_level_rules,
+            });
+        }
+
+        for rule in raw.log.rate_limits.unwrap_or_default() {
+            let key = parse_line_field(&rule.key)?;
+            if key == LineField::Line {
+                return Err(ConfigError::PropertyInvalid(
+                    "rate limits can't be keyed by the line".into(),
+                ));
+            }
+            if rule.lines_per_second == 0 {
+                return Err(ConfigError::PropertyInvalid(format!(
+                    "the {} rate limit must allow at least one line per second",
+                    rule.key
+                )));
+            }
+            log.rate_limits.push(RateLimitRule {
+                key,
+                lines_per_second: rule.lines_per_second,
+                burst: rule.burst.unwrap_or(rule.lines_per_second).max(1),
+            });
+        }
+
+        if let Some(sampling) = raw.log.sampling {
+            let rate = sampling.rate.unwrap_or(1);
+            if rate == 0 {
+                return Err(ConfigError::PropertyInvalid(
+                    "the sampling rate must be at least 1".into(),
+                ));
+            }
+            log.sampling = match sampling.field {
+                Some(field) => Some(Sampling::Hash {
+                    field: parse_line_field(&field)?,
+                    n: rate,
+                }),
+                None if rate > 1 => Some(Sampling::OneIn(rate)),
+                None => None,
+            };
+        }
+
+        let journal_match = |m: Option<raw::JournalMatch>| {
+            m.map(|m| JournalMatch {
+                units: m.units.unwrap_or_default(),
+                identifiers: m.identifiers.unwrap_or_default(),
+                transports: m.transports.unwrap_or_default(),
+                fields: m.fields.unwrap_or_default(),
+            })
+            .unwrap_or_default()
+        };
         let journald = JournaldConfig {
             paths: raw.journald.paths.unwrap_or_default().into_iter().collect(),
+            namespaces: raw.journald.namespaces.unwrap_or_default(),
+            merge: raw.journald.merge.unwrap_or(false),
+            filter: JournalFilter {
+                include: journal_match(raw.journald.include),
+                exclude: journal_match(raw.journald.exclude),
+                max_priority: raw
+                    .journald
+                    .max_priority
+                    .map(|priority| {
+                        parse_priority(&priority).ok_or_else(|| {
+                            ConfigError::PropertyInvalid(format!(
+                                "{} is not a valid journald priority",
+                                priority
+                            ))
+                        })
+                    })
+                    .transpose()?,
+            },
+            meta_fields: raw.journald.meta_fields.unwrap_or_default(),
         };
 
+        let parse_address = |address: &str| {
+            address.parse::<SocketAddr>().map_err(|_| {
+                ConfigError::PropertyInvalid(format!(
+                    "{} is not a valid syslog listener address",
+                    address
+                ))
+            })
+        };
+        let mut listeners = Vec::new();
+        for address in raw.syslog.udp.unwrap_or_default() {
+            listeners.push(Listener::Udp(parse_address(&address)?));
+        }
+        for address in raw.syslog.tcp.unwrap_or_default() {
+            listeners.push(Listener::Tcp(parse_address(&address)?));
+        }
+        for listener in raw.syslog.tls.unwrap_or_default() {
+            listeners.push(Listener::Tls {
+                address: parse_address(&listener.address)?,
+                cert: listener.cert,
+                key: listener.key,
+            });
+        }
+        let syslog = SyslogConfig { listeners };
+
+        let mut destinations: Vec<DestinationConfig> = Vec::new();
+        for destination in raw.destinations.unwrap_or_default() {
+            let name = destination.name;
+            if name.is_empty() || destinations.iter().any(|d| d.name == name) {
+                return Err(ConfigError::PropertyInvalid(format!(
+                    "destination names must be unique and not empty, found \"{}\"",
+                    name
+                )));
+            }
+
+            let mut template_builder = destination_template.clone();
+            template_builder.api_key(
+                destination
+                    .ingestion_key
+                    .filter(|s| !s.is_empty())
+                    .ok_or_else(|| {
+                        ConfigError::PropertyInvalid(format!(
+                            "destination {} has no ingestion_key",
+                            name
+                        ))
+                    })?,
+            );
+            if let Some(host) = destination.host {
+                template_builder.host(host);
+            }
+            if let Some(endpoint) = destination.endpoint {
+                template_builder.endpoint(endpoint);
+            }
+            match destination.use_ssl {
+                Some(true) => {
+                    template_builder.schema(Schema::Https);
+                }
+                Some(false) => {
+                    template_builder.schema(Schema::Http);
+                }
+                None => {}
+            }
+
+            let rules = destination
+                .routes
+                .iter()
+                .map(|route| {
+                    RouteRule::new(
+                        route.glob.as_deref(),
+                        route.app.as_deref(),
+                        route.level.as_deref(),
+                        route.line_regex.as_deref(),
+                    )
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|e| {
+                    ConfigError::PropertyInvalid(format!("destination {}: {}", name, e))
+                })?;
+
+            destinations.push(DestinationConfig {
+                template: template_builder.build()?,
+                retry_dir: destination
+                    .retry_dir
+                    .unwrap_or_else(|| http.retry_dir.join(&name)),
+                route: Route {
+                    rules,
+                    exclusive: destination.exclusive.unwrap_or(false),
+                },
+                name,
+            });
+        }
+
         Ok(Config {
             http,
             log,
             journald,
+            syslog,
+            output,
+            destinations,
+            raw: original,
         })
     }
 }
@@ -386,6 +852,19 @@ fn parse_k8s_tracking_or_warn(
     }
 }
 
+fn parse_line_field(value: &str) -> Result<LineField, ConfigError> {
+    match value {
+        "file" => Ok(LineField::File),
+        "app" => Ok(LineField::App),
+        "namespace" => Ok(LineField::Namespace),
+        "line" => Ok(LineField::Line),
+        _ => Err(ConfigError::PropertyInvalid(format!(
+            "{} is not a valid line field, expected file, app, namespace or line",
+            value
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -417,6 +896,113 @@ mod tests {
         assert!(Config::try_from(raw).is_ok());
     }
 
+    #[test]
+    fn test_syslog_listeners() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.syslog.udp = Some(vec!["0.0.0.0:514".to_string()]);
+        raw.syslog.tls = Some(vec![raw::SyslogTlsListener {
+            address: "127.0.0.1:6514".to_string(),
+            cert: PathBuf::from("/etc/certs/syslog.pem"),
+            key: PathBuf::from("/etc/certs/syslog.key"),
+        }]);
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(
+            config.syslog.listeners,
+            vec![
+                Listener::Udp("0.0.0.0:514".parse().unwrap()),
+                Listener::Tls {
+                    address: "127.0.0.1:6514".parse().unwrap(),
+                    cert: PathBuf::from("/etc/certs/syslog.pem"),
+                    key: PathBuf::from("/etc/certs/syslog.key"),
+                }
+            ]
+        );
+
+        raw.syslog.tcp = Some(vec!["localhost".to_string()]);
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::PropertyInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        let mut raw = RawConfig::default();
+        raw.output.sink = Some("stdout".to_string());
+        // The ingestion key is not needed to write locally
+        assert_eq!(
+            Config::try_from(raw.clone()).unwrap().output,
+            OutputConfig::Stdout
+        );
+
+        raw.output.sink = Some("file".to_string());
+        assert!(matches!(
+            Config::try_from(raw.clone()),
+            Err(ConfigError::MissingFieldOrEnvVar("output.path", _))
+        ));
+        raw.output.path = Some(PathBuf::from("/tmp/agent.ndjson"));
+        raw.output.max_files = Some(2);
+        assert_eq!(
+            Config::try_from(raw.clone()).unwrap().output,
+            OutputConfig::File {
+                path: PathBuf::from("/tmp/agent.ndjson"),
+                max_size: 10 * 1024 * 1024,
+                max_files: 2,
+            }
+        );
+
+        raw.output.sink = Some("kafka".to_string());
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::PropertyInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_destinations() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("primarykey".to_string());
+        raw.destinations = Some(vec![raw::DestinationConfig {
+            name: "security".to_string(),
+            host: Some("security.logdna.test".to_string()),
+            endpoint: None,
+            use_ssl: None,
+            ingestion_key: Some("securitykey".to_string()),
+            retry_dir: None,
+            exclusive: Some(true),
+            routes: vec![raw::RouteConfig {
+                glob: Some("/var/log/auth.log".to_string()),
+                app: None,
+                level: None,
+                line_regex: None,
+            }],
+        }]);
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.destinations.len(), 1);
+        let destination = &config.destinations[0];
+        assert_eq!(destination.name, "security");
+        assert_eq!(destination.retry_dir, PathBuf::from("/tmp/logdna/security"));
+        assert!(destination.route.exclusive);
+        assert_eq!(destination.route.rules.len(), 1);
+
+        // The names are used for the retry directories
+        let mut duplicated = raw.clone();
+        let destinations = duplicated.destinations.as_mut().unwrap();
+        destinations.push(destinations[0].clone());
+        assert!(matches!(
+            Config::try_from(duplicated),
+            Err(ConfigError::PropertyInvalid(_))
+        ));
+
+        raw.destinations.as_mut().unwrap()[0].routes[0].line_regex = Some("(".to_string());
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::PropertyInvalid(_))
+        ));
+    }
+
     #[test]
     fn test_user_agent() {
         let result = get_default_config();
@@ -521,6 +1107,412 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_multiline_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        raw.log.multiline = Some(vec![raw::MultilineRule {
+            glob: "/var/log/app/*.log".to_string(),
+            start_regex: Some(r"^\d{4}-\d{2}-\d{2}".to_string()),
+            continuation_regex: None,
+            max_lines: None,
+            max_bytes: None,
+            flush_timeout_ms: Some(500),
+        }]);
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        let rules = &config.log.multiline_rules;
+        assert!(rules.find(Path::new("/var/log/app/server.log")).is_some());
+        assert!(rules.find(Path::new("/var/log/syslog.log")).is_none());
+
+        // A rule needs at least one pattern to split events
+        raw.log.multiline.as_mut().unwrap()[0].start_regex = None;
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_container_log_globs() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        let rules = &config.log.container_log_rules;
+        assert!(rules
+            .passes(Path::new("/var/log/pods/ns_pod_uid/app/0.log"))
+            .is_ok());
+        assert!(!rules.passes(Path::new("/var/log/app/server.log")).is_ok());
+
+        raw.log.container_log_globs = Some(vec!["/var/lib/docker/containers/**".to_string()]);
+        let config = Config::try_from(raw).unwrap();
+        let rules = &config.log.container_log_rules;
+        assert!(rules
+            .passes(Path::new("/var/lib/docker/containers/abc/abc-json.log"))
+            .is_ok());
+        assert!(!rules
+            .passes(Path::new("/var/log/pods/ns_pod_uid/app/0.log"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_line_redact_fields() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        raw.log.line_redact_fields = Some(vec![
+            raw::RedactFieldRule {
+                path: "$.user.email".to_string(),
+                mode: None,
+                salt: None,
+            },
+            raw::RedactFieldRule {
+                path: "$.user.id".to_string(),
+                mode: Some("hash".to_string()),
+                salt: Some("pepper".to_string()),
+            },
+            raw::RedactFieldRule {
+                path: "$.card".to_string(),
+                mode: Some("last4".to_string()),
+                salt: None,
+            },
+        ]);
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        let modes: Vec<_> = config
+            .log
+            .line_redact_fields
+            .into_iter()
+            .map(|field| field.mode)
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                RedactMode::Replace,
+                RedactMode::Hash {
+                    salt: "pepper".to_string()
+                },
+                RedactMode::KeepLastFour
+            ]
+        );
+
+        raw.log.line_redact_fields.as_mut().unwrap()[0].mode = Some("erase".to_string());
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.log.json.is_none());
+
+        raw.log.json = Some(raw::JsonConfig {
+            app_field: Some("service.name".to_string()),
+            message_field: Some("msg".to_string()),
+            ..raw::JsonConfig::default()
+        });
+        let config = Config::try_from(raw).unwrap();
+        let json = config.log.json.unwrap();
+        assert_eq!(json.field_names.level.as_deref(), Some("level"));
+        assert_eq!(json.field_names.app.as_deref(), Some("service.name"));
+        assert_eq!(json.field_names.meta, None);
+        assert_eq!(json.field_names.timestamp.as_deref(), Some("timestamp"));
+        assert_eq!(json.field_names.message.as_deref(), Some("msg"));
+        assert!(!json.rewrite_message);
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        raw.log.rate_limits = Some(vec![raw::RateLimitRule {
+            key: "namespace".to_string(),
+            lines_per_second: 100,
+            burst: None,
+        }]);
+        raw.log.sampling = Some(raw::SamplingConfig {
+            rate: Some(10),
+            field: None,
+        });
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(
+            config.log.rate_limits,
+            vec![RateLimitRule {
+                key: LineField::Namespace,
+                lines_per_second: 100,
+                burst: 100,
+            }]
+        );
+        assert_eq!(config.log.sampling, Some(Sampling::OneIn(10)));
+
+        raw.log.sampling.as_mut().unwrap().field = Some("app".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(
+            config.log.sampling,
+            Some(Sampling::Hash {
+                field: LineField::App,
+                n: 10
+            })
+        );
+
+        raw.log.rate_limits.as_mut().unwrap()[0].key = "pod".to_string();
+        assert!(Config::try_from(raw.clone()).is_err());
+        raw.log.rate_limits.as_mut().unwrap()[0].key = "line".to_string();
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        raw.log.timestamps = Some(vec![
+            raw::TimestampRule {
+                glob: "/var/log/nginx/*.log".to_string(),
+                format: "common_log".to_string(),
+                regex: None,
+            },
+            raw::TimestampRule {
+                glob: "/var/log/app/*.log".to_string(),
+                format: "%d.%m.%Y %H:%M:%S".to_string(),
+                regex: Some(r"time=(\S+ \S+)".to_string()),
+            },
+        ]);
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.log.timestamp_rules.len(), 2);
+
+        raw.log.timestamps.as_mut().unwrap()[0].format = "iso".to_string();
+        assert!(Config::try_from(raw.clone()).is_err());
+        raw.log.timestamps.as_mut().unwrap()[0].format = "rfc3339".to_string();
+        raw.log.timestamps.as_mut().unwrap()[1].regex = Some("time=(".to_string());
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_file_fields() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        raw.log.file_fields = Some(vec![raw::FileFieldRule {
+            glob: None,
+            regex: Some(r"^/var/log/(?P<app>[^/]+)/.*\.log$".to_string()),
+            app: Some("$app".to_string()),
+            env: None,
+            meta: None,
+            labels: Some(
+                vec![("team".to_string(), "core".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+        }]);
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.log.file_fields.len(), 1);
+
+        raw.log.file_fields.as_mut().unwrap()[0].glob = Some("/var/log/*".to_string());
+        assert!(Config::try_from(raw.clone()).is_err());
+        raw.log.file_fields.as_mut().unwrap()[0].regex = None;
+        assert!(Config::try_from(raw).is_ok());
+    }
+
+    #[test]
+    fn test_k8s_filter() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.log.k8s_filter, PodFilter::default());
+
+        raw.log.k8s_filter = Some(raw::K8sFilterConfig {
+            include: None,
+            exclude: Some(vec![raw::PodSelector {
+                namespace: Some("kube-system".to_string()),
+                pod: None,
+                container: None,
+                labels: Some(
+                    vec![("tier".to_string(), "control-plane".to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                annotations: None,
+            }]),
+        });
+        let config = Config::try_from(raw).unwrap();
+        assert!(config.log.k8s_filter.include.is_empty());
+        assert_eq!(config.log.k8s_filter.exclude.len(), 1);
+        assert_eq!(
+            config.log.k8s_filter.exclude[0].namespace.as_deref(),
+            Some("kube-system")
+        );
+        assert_eq!(config.log.k8s_filter.exclude[0].labels.len(), 1);
+    }
+
+    #[test]
+    fn test_k8s_events_filter() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.log.k8s_events_filter, EventFilter::default());
+
+        raw.log.k8s_events_filter = Some(raw::K8sEventsFilterConfig {
+            namespaces: Some(vec!["prod".to_string()]),
+            kinds: None,
+            types: Some(vec!["warning".to_string()]),
+            reasons: Some(vec!["OOMKilled".to_string()]),
+        });
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(
+            config.log.k8s_events_filter,
+            EventFilter {
+                namespaces: vec!["prod".to_string()],
+                kinds: Vec::new(),
+                types: vec!["Warning".to_string()],
+                reasons: vec!["OOMKilled".to_string()],
+            }
+        );
+
+        raw.log.k8s_events_filter = Some(raw::K8sEventsFilterConfig {
+            namespaces: None,
+            kinds: None,
+            types: Some(vec!["Error".to_string()]),
+            reasons: None,
+        });
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_k8s_metadata_fields() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.log.k8s_metadata_fields.is_empty());
+
+        raw.log.k8s_metadata_fields = Some(vec![
+            "node".to_string(),
+            "owner".to_string(),
+            "namespace_labels".to_string(),
+        ]);
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(
+            config.log.k8s_metadata_fields,
+            vec![
+                EnrichmentField::Node,
+                EnrichmentField::Owner,
+                EnrichmentField::NamespaceLabels
+            ]
+        );
+
+        raw.log.k8s_metadata_fields = Some(vec!["deployment".to_string()]);
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_journald_filter() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert_eq!(config.journald.filter, JournalFilter::default());
+
+        raw.journald.include = Some(raw::JournalMatch {
+            units: Some(vec!["nginx.service".to_string()]),
+            identifiers: None,
+            transports: None,
+            fields: None,
+        });
+        raw.journald.exclude = Some(raw::JournalMatch {
+            units: None,
+            identifiers: None,
+            transports: Some(vec!["kernel".to_string()]),
+            fields: Some(
+                vec![("_COMM".to_string(), "cron".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+        });
+        raw.journald.max_priority = Some("warning".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        let filter = config.journald.filter;
+        assert_eq!(filter.include.units, vec!["nginx.service".to_string()]);
+        assert_eq!(filter.exclude.transports, vec!["kernel".to_string()]);
+        assert_eq!(filter.exclude.fields.len(), 1);
+        assert_eq!(filter.max_priority, Some(4));
+
+        raw.journald.max_priority = Some("verbose".to_string());
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_journald_origins() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.journald.namespaces.is_empty());
+        assert!(!config.journald.merge);
+
+        raw.journald.namespaces = Some(vec!["audit".to_string()]);
+        raw.journald.merge = Some(true);
+        let config = Config::try_from(raw).unwrap();
+        assert_eq!(config.journald.namespaces, vec!["audit".to_string()]);
+        assert!(config.journald.merge);
+    }
+
+    #[test]
+    fn test_journald_meta_fields() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.journald.meta_fields.is_empty());
+
+        raw.journald.meta_fields = Some(vec!["_PID".to_string(), "CONTAINER_ID".to_string()]);
+        let config = Config::try_from(raw).unwrap();
+        assert_eq!(
+            config.journald.meta_fields,
+            vec!["_PID".to_string(), "CONTAINER_ID".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.log.levels.is_none());
+
+        raw.log.levels = Some(raw::LevelsConfig {
+            detect: None,
+            min_levels: Some(vec![raw::MinLevelRule {
+                glob: None,
+                app: Some("chatty".to_string()),
+                min_level: "warn".to_string(),
+            }]),
+        });
+        let config = Config::try_from(raw.clone()).unwrap();
+        let levels = config.log.levels.unwrap();
+        assert!(levels.detect);
+        assert_eq!(levels.min_level_rules.len(), 1);
+
+        let levels = raw.log.levels.as_mut().unwrap();
+        levels.min_levels.as_mut().unwrap()[0].min_level = "verbose".to_string();
+        assert!(Config::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("dummy-test-key".to_string());
+
+        let config = Config::try_from(raw.clone()).unwrap();
+        assert!(config.log.dedup.is_none());
+
+        raw.log.dedup = Some(raw::DedupConfig {
+            window_ms: Some(5000),
+            ..raw::DedupConfig::default()
+        });
+        let config = Config::try_from(raw).unwrap();
+        let dedup = config.log.dedup.unwrap();
+        assert_eq!(dedup.window, Duration::from_secs(5));
+        assert!(dedup.normalize_regex.is_empty());
+        assert_eq!(dedup.max_sources, DEFAULT_MAX_SOURCES);
+    }
+
     #[test]
     fn e2e() {
         let _ = remove_file("test.yaml");
@@ -47,6 +47,10 @@ from_env_name!(LINE_INCLUSION);
 from_env_name!(REDACT);
 from_env_name!(INGEST_TIMEOUT);
 from_env_name!(INGEST_BUFFER_SIZE);
+from_env_name!(SHUTDOWN_TIMEOUT);
+from_env_name!(RETRY_DIR);
+from_env_name!(RETRY_DISK_LIMIT);
+from_env_name!(RETRY_MAX_AGE);
 
 enum Key {
     FromEnv(&'static str),
@@ -105,6 +109,9 @@ fn from_property_map(map: HashMap<String, String>) -> Result<Config, ConfigError
         http: Default::default(),
         log: Default::default(),
         journald: Default::default(),
+        syslog: Default::default(),
+        output: Default::default(),
+        destinations: None,
     };
     result.http.ingestion_key = map.get(&INGESTION_KEY).map(|s| s.to_string());
 
@@ -167,6 +174,26 @@ fn from_property_map(map: HashMap<String, String>) -> Result<Config, ConfigError
         })?);
     }
 
+    if let Some(value) = map.get(&SHUTDOWN_TIMEOUT) {
+        result.http.shutdown_timeout = Some(value.parse().map_err(|e| {
+            ConfigError::PropertyInvalid(format!("shutdown_timeout is invalid: {}", e))
+        })?);
+    }
+
+    result.http.retry_dir = map.get(&RETRY_DIR).map(PathBuf::from);
+
+    if let Some(value) = map.get(&RETRY_DISK_LIMIT) {
+        result.http.retry_disk_limit = Some(value.parse().map_err(|e| {
+            ConfigError::PropertyInvalid(format!("retry_disk_limit is invalid: {}", e))
+        })?);
+    }
+
+    if let Some(value) = map.get(&RETRY_MAX_AGE) {
+        result.http.retry_max_age = Some(value.parse().map_err(|e| {
+            ConfigError::PropertyInvalid(format!("retry_max_age is invalid: {}", e))
+        })?);
+    }
+
     if let Some(log_dirs) = map.get(&LOG_DIRS) {
         // To support the legacy agent behaviour, we override the default (/var/log)
         // This results in a different behaviour depending on the format:
@@ -2,6 +2,7 @@ use crate::error::ConfigError;
 use crate::{argv, get_hostname, properties};
 use http::types::params::Params;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
@@ -98,6 +99,14 @@ impl Merge for usize {
     }
 }
 
+impl Merge for BTreeMap<String, String> {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        if *other != *default {
+            *self = other.clone();
+        }
+    }
+}
+
 impl<T: PartialEq + Merge + Clone + Default> Merge for Option<T> {
     fn merge(&mut self, other: &Self, default: &Self) {
         if *other != *default {
@@ -132,21 +141,32 @@ pub struct Config {
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    #[serde(default)]
+    pub syslog: SyslogConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destinations: Option<Vec<DestinationConfig>>,
 }
 
 impl Config {
-    /// Tries to parse from java properties format and then using
-    pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+    /// Returns the files that are read for the config path, in the order they are merged
+    pub fn files<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
         let path = path.as_ref();
-        let is_default_path = path.to_string_lossy() == argv::DEFAULT_YAML_FILE;
-        let conf_files = if is_default_path {
+        if path.to_string_lossy() == argv::DEFAULT_YAML_FILE {
             vec![
-                Path::new(argv::DEFAULT_CONF_FILE),
-                Path::new(argv::DEFAULT_YAML_FILE),
+                PathBuf::from(argv::DEFAULT_CONF_FILE),
+                PathBuf::from(argv::DEFAULT_YAML_FILE),
             ]
         } else {
-            vec![path]
-        };
+            vec![path.to_path_buf()]
+        }
+    }
+
+    /// Tries to parse from java properties format and then using
+    pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let conf_files = Config::files(path);
+        let conf_files: Vec<&Path> = conf_files.iter().map(PathBuf::as_path).collect();
 
         let indiv_confs = try_load_confs(&conf_files);
         let (final_conf, mut error_list) = merge_all_confs(indiv_confs);
@@ -186,6 +206,14 @@ pub struct HttpConfig {
     pub params: Option<Params>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_disk_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_max_age: Option<u64>,
 
     // Mostly for development, these settings are hidden from the user
     // There's no guarantee that these settings will exist in the future
@@ -213,29 +241,477 @@ pub struct LogConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub line_redact_regex: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_redact_fields: Option<Vec<RedactFieldRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub lookback: Option<String>,
     pub use_k8s_enrichment: Option<String>,
     pub log_k8s_events: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_filter: Option<K8sFilterConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_events_filter: Option<K8sEventsFilterConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_metadata_fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiline: Option<Vec<MultilineRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_log_globs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<JsonConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limits: Option<Vec<RateLimitRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<SamplingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<DedupConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Vec<TimestampRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub levels: Option<LevelsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_fields: Option<Vec<FileFieldRule>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MultilineRule {
+    pub glob: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct K8sFilterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<PodSelector>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<PodSelector>>,
+}
+
+impl Default for K8sFilterConfig {
+    fn default() -> Self {
+        K8sFilterConfig {
+            include: None,
+            exclude: None,
+        }
+    }
+}
+
+impl Merge for K8sFilterConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.include.merge(&other.include, &default.include);
+        self.exclude.merge(&other.exclude, &default.exclude);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct K8sEventsFilterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub types: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasons: Option<Vec<String>>,
+}
+
+impl Default for K8sEventsFilterConfig {
+    fn default() -> Self {
+        K8sEventsFilterConfig {
+            namespaces: None,
+            kinds: None,
+            types: None,
+            reasons: None,
+        }
+    }
+}
+
+impl Merge for K8sEventsFilterConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.namespaces
+            .merge(&other.namespaces, &default.namespaces);
+        self.kinds.merge(&other.kinds, &default.kinds);
+        self.types.merge(&other.types, &default.types);
+        self.reasons.merge(&other.reasons, &default.reasons);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct PodSelector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct TimestampRule {
+    pub glob: String,
+    pub format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct LevelsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_levels: Option<Vec<MinLevelRule>>,
+}
+
+impl Default for LevelsConfig {
+    fn default() -> Self {
+        LevelsConfig {
+            detect: None,
+            min_levels: None,
+        }
+    }
+}
+
+impl Merge for LevelsConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.detect.merge(&other.detect, &default.detect);
+        self.min_levels
+            .merge(&other.min_levels, &default.min_levels);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MinLevelRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    pub min_level: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct FileFieldRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RedactFieldRule {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RateLimitRule {
+    pub key: String,
+    pub lines_per_second: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct SamplingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            rate: None,
+            field: None,
+        }
+    }
+}
+
+impl Merge for SamplingConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.rate.merge(&other.rate, &default.rate);
+        self.field.merge(&other.field, &default.field);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct DedupConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize_regex: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sources: Option<usize>,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            window_ms: None,
+            normalize_regex: None,
+            max_sources: None,
+        }
+    }
+}
+
+impl Merge for DedupConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.window_ms.merge(&other.window_ms, &default.window_ms);
+        self.normalize_regex
+            .merge(&other.normalize_regex, &default.normalize_regex);
+        self.max_sources
+            .merge(&other.max_sources, &default.max_sources);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct JsonConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite_message: Option<bool>,
+}
+
+impl Default for JsonConfig {
+    fn default() -> Self {
+        JsonConfig {
+            level_field: None,
+            app_field: None,
+            meta_field: None,
+            timestamp_field: None,
+            message_field: None,
+            rewrite_message: None,
+        }
+    }
+}
+
+impl Merge for JsonConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.level_field
+            .merge(&other.level_field, &default.level_field);
+        self.app_field.merge(&other.app_field, &default.app_field);
+        self.meta_field
+            .merge(&other.meta_field, &default.meta_field);
+        self.timestamp_field
+            .merge(&other.timestamp_field, &default.timestamp_field);
+        self.message_field
+            .merge(&other.message_field, &default.message_field);
+        self.rewrite_message
+            .merge(&other.rewrite_message, &default.rewrite_message);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct JournaldConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paths: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<JournalMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<JournalMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_priority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_fields: Option<Vec<String>>,
 }
 
 impl Default for JournaldConfig {
     fn default() -> Self {
-        JournaldConfig { paths: None }
+        JournaldConfig {
+            paths: None,
+            namespaces: None,
+            merge: None,
+            include: None,
+            exclude: None,
+            max_priority: None,
+            meta_fields: None,
+        }
     }
 }
 
 impl Merge for JournaldConfig {
     fn merge(&mut self, other: &Self, default: &Self) {
         self.paths.merge(&other.paths, &default.paths);
+        self.namespaces
+            .merge(&other.namespaces, &default.namespaces);
+        self.merge.merge(&other.merge, &default.merge);
+        self.include.merge(&other.include, &default.include);
+        self.exclude.merge(&other.exclude, &default.exclude);
+        self.max_priority
+            .merge(&other.max_priority, &default.max_priority);
+        self.meta_fields
+            .merge(&other.meta_fields, &default.meta_fields);
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct JournalMatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifiers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transports: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<BTreeMap<String, String>>,
+}
+
+impl Default for JournalMatch {
+    fn default() -> Self {
+        JournalMatch {
+            units: None,
+            identifiers: None,
+            transports: None,
+            fields: None,
+        }
+    }
+}
+
+impl Merge for JournalMatch {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.units.merge(&other.units, &default.units);
+        self.identifiers
+            .merge(&other.identifiers, &default.identifiers);
+        self.transports
+            .merge(&other.transports, &default.transports);
+        self.fields.merge(&other.fields, &default.fields);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct SyslogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<Vec<SyslogTlsListener>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct SyslogTlsListener {
+    pub address: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            udp: None,
+            tcp: None,
+            tls: None,
+        }
+    }
+}
+
+impl Merge for SyslogConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.udp.merge(&other.udp, &default.udp);
+        self.tcp.merge(&other.tcp, &default.tcp);
+        self.tls.merge(&other.tls, &default.tls);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct OutputConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sink: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            sink: None,
+            path: None,
+            max_size: None,
+            max_files: None,
+        }
+    }
+}
+
+impl Merge for OutputConfig {
+    fn merge(&mut self, other: &Self, default: &Self) {
+        self.sink.merge(&other.sink, &default.sink);
+        self.path.merge(&other.path, &default.path);
+        self.max_size.merge(&other.max_size, &default.max_size);
+        self.max_files.merge(&other.max_files, &default.max_files);
+    }
+}
+
+/// An ingestion destination other than the one defined by the http settings
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct DestinationConfig {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_ssl: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingestion_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclusive: Option<bool>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RouteConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_regex: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct Rules {
     pub glob: Vec<String>,
@@ -264,6 +740,9 @@ impl Default for Config {
             http: HttpConfig::default(),
             log: LogConfig::default(),
             journald: JournaldConfig::default(),
+            syslog: SyslogConfig::default(),
+            output: OutputConfig::default(),
+            destinations: None,
         }
     }
 }
@@ -273,6 +752,10 @@ impl Merge for Config {
         self.http.merge(&other.http, &default.http);
         self.log.merge(&other.log, &default.log);
         self.journald.merge(&other.journald, &default.journald);
+        self.syslog.merge(&other.syslog, &default.syslog);
+        self.output.merge(&other.output, &default.output);
+        self.destinations
+            .merge(&other.destinations, &default.destinations);
     }
 }
 
@@ -291,6 +774,10 @@ impl Default for HttpConfig {
                 .build()
                 .ok(),
             body_size: Some(2 * 1024 * 1024),
+            shutdown_timeout: Some(5_000),
+            retry_dir: Some(PathBuf::from("/tmp/logdna")),
+            retry_disk_limit: None,
+            retry_max_age: None,
             retry_base_delay_ms: None,
             retry_step_delay_ms: None,
         }
@@ -311,6 +798,13 @@ impl Merge for HttpConfig {
             .merge(&other.ingestion_key, &default.ingestion_key);
         self.params.merge(&other.params, &default.params);
         self.body_size.merge(&other.body_size, &default.body_size);
+        self.shutdown_timeout
+            .merge(&other.shutdown_timeout, &default.shutdown_timeout);
+        self.retry_dir.merge(&other.retry_dir, &default.retry_dir);
+        self.retry_disk_limit
+            .merge(&other.retry_disk_limit, &default.retry_disk_limit);
+        self.retry_max_age
+            .merge(&other.retry_max_age, &default.retry_max_age);
         self.retry_base_delay_ms
             .merge(&other.retry_base_delay_ms, &default.retry_base_delay_ms);
         self.retry_step_delay_ms
@@ -348,9 +842,22 @@ impl Default for LogConfig {
             line_exclusion_regex: None,
             line_inclusion_regex: None,
             line_redact_regex: None,
+            line_redact_fields: None,
             lookback: None,
             use_k8s_enrichment: None,
             log_k8s_events: None,
+            k8s_filter: None,
+            k8s_events_filter: None,
+            k8s_metadata_fields: None,
+            multiline: None,
+            container_log_globs: None,
+            json: None,
+            rate_limits: None,
+            sampling: None,
+            dedup: None,
+            timestamps: None,
+            levels: None,
+            file_fields: None,
         }
     }
 }
@@ -369,11 +876,32 @@ impl Merge for LogConfig {
             .merge(&other.line_inclusion_regex, &default.line_inclusion_regex);
         self.line_redact_regex
             .merge(&other.line_redact_regex, &default.line_redact_regex);
+        self.line_redact_fields
+            .merge(&other.line_redact_fields, &default.line_redact_fields);
         self.lookback.merge(&other.lookback, &default.lookback);
         self.use_k8s_enrichment
             .merge(&other.use_k8s_enrichment, &default.use_k8s_enrichment);
         self.log_k8s_events
             .merge(&other.log_k8s_events, &default.log_k8s_events);
+        self.k8s_filter
+            .merge(&other.k8s_filter, &default.k8s_filter);
+        self.k8s_events_filter
+            .merge(&other.k8s_events_filter, &default.k8s_events_filter);
+        self.k8s_metadata_fields
+            .merge(&other.k8s_metadata_fields, &default.k8s_metadata_fields);
+        self.multiline.merge(&other.multiline, &default.multiline);
+        self.container_log_globs
+            .merge(&other.container_log_globs, &default.container_log_globs);
+        self.json.merge(&other.json, &default.json);
+        self.rate_limits
+            .merge(&other.rate_limits, &default.rate_limits);
+        self.sampling.merge(&other.sampling, &default.sampling);
+        self.dedup.merge(&other.dedup, &default.dedup);
+        self.timestamps
+            .merge(&other.timestamps, &default.timestamps);
+        self.levels.merge(&other.levels, &default.levels);
+        self.file_fields
+            .merge(&other.file_fields, &default.file_fields);
     }
 }
 
@@ -828,10 +1356,13 @@ ingest_buffer_size = 3145728
     fn journald_config_merge() {
         let mut left_conf = JournaldConfig {
             paths: Some(vec![Path::new("/left").to_path_buf()]),
+            ..JournaldConfig::default()
         };
 
         let right_conf = JournaldConfig {
             paths: Some(vec![Path::new("/right").to_path_buf()]),
+            max_priority: Some("warning".to_string()),
+            ..JournaldConfig::default()
         };
 
         left_conf.merge(&right_conf, &JournaldConfig::default());
@@ -840,6 +1371,7 @@ ingest_buffer_size = 3145728
             .paths
             .expect("expected paths to not be None after merge");
         assert_eq!(actual_paths, vec![PathBuf::from("/right")]);
+        assert_eq!(left_conf.max_priority.as_deref(), Some("warning"));
     }
 
     #[test]
@@ -875,6 +1407,10 @@ ingest_buffer_size = 3145728
                 .build()
                 .ok(),
             body_size: Some(1337),
+            shutdown_timeout: Some(1337),
+            retry_dir: Some(PathBuf::from("/left/retry")),
+            retry_disk_limit: Some(1337),
+            retry_max_age: None,
             retry_base_delay_ms: Some(10_000),
             retry_step_delay_ms: Some(10_000),
         };
@@ -892,6 +1428,10 @@ ingest_buffer_size = 3145728
                 .build()
                 .ok(),
             body_size: Some(7331),
+            shutdown_timeout: Some(7331),
+            retry_dir: Some(PathBuf::from("/right/retry")),
+            retry_disk_limit: Some(7331),
+            retry_max_age: Some(7331),
             retry_base_delay_ms: Some(2_000),
             retry_step_delay_ms: Some(2_000),
         };
@@ -910,6 +1450,10 @@ ingest_buffer_size = 3145728
             Some("right.local".to_string())
         );
         assert_eq!(left_conf.body_size, Some(7331));
+        assert_eq!(left_conf.shutdown_timeout, Some(7331));
+        assert_eq!(left_conf.retry_dir, Some(PathBuf::from("/right/retry")));
+        assert_eq!(left_conf.retry_disk_limit, Some(7331));
+        assert_eq!(left_conf.retry_max_age, Some(7331));
         assert_eq!(left_conf.retry_base_delay_ms, Some(2_000));
         assert_eq!(left_conf.retry_step_delay_ms, Some(2_000));
     }
@@ -0,0 +1,159 @@
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use middleware::line_rules::{FieldRedactor, LineRules};
+
+use crate::argv::ArgumentOptions;
+use crate::error::ConfigError;
+use crate::raw::Config as RawConfig;
+use crate::Config;
+
+/// The configuration read again while the agent is running
+pub struct Reload {
+    /// The new configuration, settings that are only applied on restart keep their previous value
+    pub config: Config,
+    /// The line rules built from the new configuration
+    pub line_rules: LineRules,
+    /// The field redactor built from the new configuration
+    pub field_redactor: FieldRedactor,
+    /// A description of each setting that changed
+    pub changes: Vec<String>,
+    /// Whether settings that are only applied on restart were changed
+    pub requires_restart: bool,
+}
+
+/// Reads the configuration sources again and compares them with the settings in use
+pub struct ConfigReloader {
+    path: PathBuf,
+    current: RawConfig,
+}
+
+impl ConfigReloader {
+    pub fn new(config: &Config) -> Self {
+        ConfigReloader {
+            path: ArgumentOptions::from_args_with_all_env_vars().config,
+            current: config.raw.clone(),
+        }
+    }
+
+    /// The config files to watch for changes
+    pub fn files(&self) -> Vec<PathBuf> {
+        RawConfig::files(&self.path)
+    }
+
+    pub fn reload(&mut self) -> Result<Reload, ConfigError> {
+        let argv_options = ArgumentOptions::from_args_with_all_env_vars();
+        // Unlike at startup, a config file that can't be parsed is not replaced by the defaults
+        let raw = argv_options.merge(RawConfig::parse(&self.path)?);
+
+        let (applied, changes, requires_restart) = apply(&self.current, &raw);
+        let config = Config::try_from(applied.clone())?;
+        // Build the line rules before the new settings are considered in use
+        let line_rules = LineRules::new(
+            &config.log.line_exclusion_regex,
+            &config.log.line_inclusion_regex,
+            &config.log.line_redact_regex,
+        )
+        .map_err(|e| ConfigError::PropertyInvalid(e.to_string()))?;
+        let field_redactor = FieldRedactor::new(&config.log.line_redact_fields)
+            .map_err(|e| ConfigError::PropertyInvalid(e.to_string()))?;
+        self.current = applied;
+
+        Ok(Reload {
+            config,
+            line_rules,
+            field_redactor,
+            changes,
+            requires_restart,
+        })
+    }
+}
+
+/// Takes the settings that can be changed while running from the new config
+fn apply(current: &RawConfig, new: &RawConfig) -> (RawConfig, Vec<String>, bool) {
+    let mut applied = current.clone();
+    let mut changes = Vec::new();
+
+    macro_rules! apply_field {
+        // The values of secret settings are not described
+        ($name:expr, $($field:ident).+, secret) => {
+            if current.$($field).+ != new.$($field).+ {
+                changes.push(format!("{} changed", $name));
+                applied.$($field).+ = new.$($field).+.clone();
+            }
+        };
+        ($name:expr, $($field:ident).+) => {
+            if current.$($field).+ != new.$($field).+ {
+                changes.push(format!(
+                    "{} changed from {:?} to {:?}",
+                    $name,
+                    current.$($field).+,
+                    new.$($field).+
+                ));
+                applied.$($field).+ = new.$($field).+.clone();
+            }
+        };
+    }
+
+    apply_field!("log.include", log.include);
+    apply_field!("log.exclude", log.exclude);
+    apply_field!("log.line_exclusion_regex", log.line_exclusion_regex);
+    apply_field!("log.line_inclusion_regex", log.line_inclusion_regex);
+    apply_field!("log.line_redact_regex", log.line_redact_regex);
+    apply_field!("log.line_redact_fields", log.line_redact_fields, secret);
+    apply_field!("http.params", http.params);
+
+    let requires_restart = applied != *new;
+    (applied, changes, requires_restart)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::{RedactFieldRule, Rules};
+
+    #[test]
+    fn applies_reloadable_settings() {
+        let current = RawConfig::default();
+        let mut new = current.clone();
+        new.log.exclude = Some(Rules {
+            glob: vec!["/var/log/noisy.log".to_string()],
+            regex: Vec::new(),
+        });
+        new.log.line_exclusion_regex = Some(vec!["DEBUG".to_string()]);
+        new.log.line_redact_fields = Some(vec![RedactFieldRule {
+            path: "$.user".to_string(),
+            mode: Some("hash".to_string()),
+            salt: Some("secret-salt".to_string()),
+        }]);
+
+        let (applied, changes, requires_restart) = apply(&current, &new);
+        assert_eq!(applied, new);
+        assert!(!requires_restart);
+        assert_eq!(changes.len(), 3);
+        assert!(changes[0].starts_with("log.exclude changed from"));
+        assert!(changes[1].contains("DEBUG"));
+        assert_eq!(changes[2], "log.line_redact_fields changed");
+    }
+
+    #[test]
+    fn keeps_settings_that_require_a_restart() {
+        let current = RawConfig::default();
+        let mut new = current.clone();
+        new.log.db_path = Some(PathBuf::from("/tmp/agent-state"));
+        new.log.line_inclusion_regex = Some(vec!["ERROR".to_string()]);
+
+        let (applied, changes, requires_restart) = apply(&current, &new);
+        assert!(requires_restart);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(applied.log.db_path, current.log.db_path);
+        assert_eq!(
+            applied.log.line_inclusion_regex,
+            new.log.line_inclusion_regex
+        );
+
+        let (_, changes, requires_restart) = apply(&current, &current);
+        assert!(changes.is_empty());
+        assert!(!requires_restart);
+    }
+}
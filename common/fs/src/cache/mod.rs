@@ -2,6 +2,8 @@ use crate::cache::entry::Entry;
 use crate::cache::event::Event;
 use crate::cache::tailed_file::TailedFile;
 use crate::cache::watch::{WatchEvent, Watcher};
+use crate::container::ContainerLogDecoder;
+use crate::multiline::MultilineRules;
 use crate::rule::{GlobRule, Rules, Status};
 
 use std::cell::RefCell;
@@ -144,6 +146,15 @@ fn get_resume_events(
         .filter_map(|e| async { e })
 }
 
+fn get_rule_events(
+    fs: &Arc<Mutex<FileSystem>>,
+) -> impl Stream<Item = (Result<Event, Error>, EventTimestamp)> {
+    let _fs = fs.try_lock().expect("couldn't lock filesystem cache");
+    _fs.rule_events_recv
+        .clone()
+        .map(|event| (Ok(event), chrono::offset::Utc::now()))
+}
+
 pub struct FileSystem {
     watcher: Watcher,
     pub entries: Rc<RefCell<EntryMap>>,
@@ -154,17 +165,29 @@ pub struct FileSystem {
     wd_by_inode: HashMap<u64, WatchDescriptor>,
 
     master_rules: Rules,
+    multiline_rules: MultilineRules,
+    /// The files whose container log format is detected
+    container_log_rules: Rules,
     initial_dirs: Vec<DirPathBuf>,
     initial_dir_rules: Rules,
 
     initial_events: Vec<Event>,
     resume_events_recv: async_channel::Receiver<(u64, EventTimestamp)>,
     resume_events_send: async_channel::Sender<(u64, EventTimestamp)>,
+    /// The events of the entries dropped when the rules are replaced
+    rule_events_recv: async_channel::Receiver<Event>,
+    rule_events_send: async_channel::Sender<Event>,
 }
 
 impl FileSystem {
-    pub fn new(initial_dirs: Vec<DirPathBuf>, rules: Rules) -> Self {
+    pub fn new(
+        initial_dirs: Vec<DirPathBuf>,
+        rules: Rules,
+        multiline_rules: MultilineRules,
+        container_log_rules: Rules,
+    ) -> Self {
         let (resume_events_send, resume_events_recv) = async_channel::unbounded();
+        let (rule_events_send, rule_events_recv) = async_channel::unbounded();
 
         initial_dirs.iter().for_each(|path| {
             if !path.is_dir() {
@@ -194,12 +217,16 @@ impl FileSystem {
             watch_descriptors: WatchDescriptors::new(),
             wd_by_inode: HashMap::new(),
             master_rules: rules,
+            multiline_rules,
+            container_log_rules,
             initial_dirs: initial_dirs.clone(),
             initial_dir_rules,
             watcher,
             initial_events: Vec::new(),
             resume_events_recv,
             resume_events_send,
+            rule_events_recv,
+            rule_events_send,
         };
 
         let entries = fs.entries.clone();
@@ -278,6 +305,7 @@ impl FileSystem {
 
         let initial_events = get_initial_events(&fs);
         let resume_events_recv = get_resume_events(&fs);
+        let rule_events_recv = get_rule_events(&fs);
         let events = futures::stream::select(resume_events_recv, events_stream.into_stream())
             .map(|event_result| async { event_result })
             .buffered(EVENT_STREAM_BUFFER_COUNT)
@@ -286,6 +314,7 @@ impl FileSystem {
                 as_event_stream(fs, event, event_time)
             })
             .flatten();
+        let events = futures::stream::select(rule_events_recv, events);
 
         Ok(initial_events.chain(events))
     }
@@ -301,7 +330,7 @@ impl FileSystem {
             WatchEvent::Create { wd, name } | WatchEvent::MovedTo { wd, name, .. } => {
                 self.process_create(&wd, name, &mut _entries)
             }
-            WatchEvent::Modify { wd } => self.process_modify(&wd),
+            WatchEvent::Modify { wd } => self.process_modify(&wd, &_entries),
             WatchEvent::Delete { wd, name } | WatchEvent::MovedFrom { wd, name, .. } => {
                 self.process_delete(&wd, name, &mut _entries)
             }
@@ -373,7 +402,11 @@ impl FileSystem {
         Ok(events)
     }
 
-    fn process_modify(&mut self, watch_descriptor: &WatchDescriptor) -> FsResult<Vec<Event>> {
+    fn process_modify(
+        &mut self,
+        watch_descriptor: &WatchDescriptor,
+        _entries: &EntryMap,
+    ) -> FsResult<Vec<Event>> {
         let mut entry_ptrs_opt = None;
         if let Some(entries) = self.watch_descriptors.get_mut(watch_descriptor) {
             entry_ptrs_opt = Some(entries.clone())
@@ -382,6 +415,9 @@ impl FileSystem {
         if let Some(mut entry_ptrs) = entry_ptrs_opt {
             let mut events = Vec::new();
             for entry_ptr in entry_ptrs.iter_mut() {
+                if let Some(Entry::File { data, .. }) = _entries.get(*entry_ptr) {
+                    data.borrow().look_up_multiline_rule(&self.multiline_rules);
+                }
                 events.push(Event::Write(*entry_ptr));
             }
 
@@ -582,13 +618,23 @@ impl FileSystem {
 
                 let inode = path.metadata().map_err(Error::File)?.ino();
                 self.wd_by_inode.insert(inode, wd.clone());
+                let container = if self.container_log_rules.passes(path).is_ok() {
+                    ContainerLogDecoder::new()
+                } else {
+                    ContainerLogDecoder::plain()
+                };
                 let new_entry = Entry::File {
                     name: component,
                     parent: parent_ref,
                     wd,
                     data: RefCell::new(
-                        TailedFile::new(path, Some(self.resume_events_send.clone()))
-                            .map_err(Error::File)?,
+                        TailedFile::new(
+                            path,
+                            Some(self.resume_events_send.clone()),
+                            self.multiline_rules.find(path),
+                            container,
+                        )
+                        .map_err(Error::File)?,
                     ),
                 };
 
@@ -997,6 +1043,69 @@ impl FileSystem {
         false
     }
 
+    /// Replaces the inclusion/exclusion rules, the tracked files that don't pass the new rules
+    /// are dropped and the existing files that only pass the new rules are initialized like at
+    /// startup. Their events are sent through the event stream.
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.master_rules = rules;
+
+        let _entries = self.entries.clone();
+        let mut _entries = _entries.borrow_mut();
+
+        // Entries that were already dropped are not reachable from their parent anymore,
+        // removing them by path only affects the entries in use
+        let excluded: Vec<PathBuf> = _entries
+            .values()
+            .filter(|entry| matches!(entry, Entry::File { .. } | Entry::Symlink { .. }))
+            .map(|entry| self.resolve_direct_path(entry, &_entries))
+            .filter(|path| !self.passes(path, &_entries))
+            .collect();
+
+        let mut events = Vec::new();
+        for path in excluded {
+            info!("{:?} is excluded by the new rules", path);
+            // Ignore error, the entry may have been removed with a dropped symlink
+            self.remove(&path, &mut events, &mut _entries)
+                .unwrap_or_default();
+        }
+
+        for dir in self
+            .initial_dirs
+            .clone()
+            .into_iter()
+            .map(|path| -> PathBuf { path.into() })
+        {
+            for path in recursive_scan(&dir) {
+                if self.lookup(&path, &_entries).is_some() || !self.passes(&path, &_entries) {
+                    continue;
+                }
+                let mut inserted = Vec::new();
+                if let Err(e) = self.insert(&path, &mut inserted, &mut _entries) {
+                    debug!("insertion of {:?} for the new rules failed: {}", path, e);
+                    continue;
+                }
+                // The files are read from the lookback offset, as the files found at startup
+                for event in inserted {
+                    if let Event::New(entry_key) = event {
+                        if let Some(entry) = _entries.get(entry_key) {
+                            let path = self.resolve_direct_path(entry, &_entries);
+                            if self.is_initial_dir_target(&path) {
+                                info!("{:?} is included by the new rules", path);
+                                events.push(Event::Initialize(entry_key));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for event in events {
+            if let Err(e) = self.rule_events_send.try_send(event) {
+                error!("couldn't send a file event for the new rules: {}", e);
+            }
+        }
+    }
+
     /// Determines whether the path is within the initial dir
     /// and either passes the master rules (e.g. "*.log") or it's a directory
     pub(crate) fn is_initial_dir_target(&self, path: &Path) -> bool {
@@ -1136,6 +1245,7 @@ fn append_rules(rules: &mut Rules, mut path: PathBuf) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::container_log_rules;
     use crate::rule::{GlobRule, Rules};
     use crate::test::LOGGER;
     use std::convert::TryInto;
@@ -1189,6 +1299,8 @@ mod tests {
                 .try_into()
                 .unwrap_or_else(|_| panic!("{:?} is not a directory!", path))],
             rules,
+            MultilineRules::new(),
+            container_log_rules(),
         )
     }
 
@@ -1383,6 +1495,43 @@ mod tests {
         });
     }
 
+    // Replaces the rules after the initialization
+    #[test]
+    fn filesystem_set_rules() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**/*.log").unwrap());
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), Some(rules))));
+
+            File::create(path.join("insert.log")).unwrap();
+            File::create(path.join("before.txt")).unwrap();
+            take_events!(fs, 1);
+            assert!(lookup_entry!(fs, path.join("insert.log")).is_some());
+            assert!(lookup_entry!(fs, path.join("before.txt")).is_none());
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**/*.txt").unwrap());
+            fs.try_lock().expect("couldn't lock fs").set_rules(rules);
+            // Files that are already tracked are dropped when the new rules exclude them
+            assert!(lookup_entry!(fs, path.join("insert.log")).is_none());
+            // Existing files that only pass the new rules are tracked
+            assert!(lookup_entry!(fs, path.join("before.txt")).is_some());
+
+            File::create(path.join("after.txt")).unwrap();
+            let events = take_events!(fs, 3);
+            assert!(events
+                .iter()
+                .any(|event| matches!(event, Ok((Ok(Event::Delete(_)), _)))));
+            assert!(events
+                .iter()
+                .any(|event| matches!(event, Ok((Ok(Event::Initialize(_)), _)))));
+            assert!(lookup_entry!(fs, path.join("after.txt")).is_some());
+        });
+    }
+
     // Creates a symlink
     #[test]
     fn filesystem_create_symlink() {
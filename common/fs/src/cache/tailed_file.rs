@@ -5,6 +5,9 @@ use http::types::serialize::{
     SerializeUtf8, SerializeValue,
 };
 
+use crate::container::{ContainerLogDecoder, Decoded, RecordMeta};
+use crate::multiline::{MultilineBuffer, MultilineEvent, MultilineRule, MultilineRules};
+use middleware::LineMut;
 use state::GetOffset;
 
 use chrono::Utc;
@@ -19,7 +22,7 @@ use async_channel::Sender;
 use async_trait::async_trait;
 use pin_project_lite::pin_project;
 
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use bytes::Bytes;
 use std::collections::HashMap;
@@ -33,6 +36,7 @@ use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncSeekExt, BufReader, SeekFrom};
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
@@ -140,6 +144,8 @@ impl Stream for LineBuilderLines {
 pub struct LazyLines {
     reader: Arc<Mutex<TailedFileInner>>,
     current_offset: Option<(u64, u64)>,
+    current_event: Option<Bytes>,
+    current_meta: Option<RecordMeta>,
     read: usize,
     path: usize,
     total_read: usize,
@@ -182,6 +188,8 @@ impl LazyLines {
         Self {
             reader,
             current_offset: None,
+            current_event: None,
+            current_meta: None,
             read: 0,
             path: 0,
             total_read: 0,
@@ -203,6 +211,7 @@ pub struct LazyLineSerializer {
     meta: Option<Value>,
     path: String,
     line_buffer: Option<Bytes>,
+    timestamp: Option<i64>,
 
     file_offset: (u64, u64),
 
@@ -331,7 +340,9 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
     where
         S: SerializeI64 + std::marker::Send,
     {
-        writer.serialize_i64(&Utc::now().timestamp()).await?;
+        writer
+            .serialize_i64(&self.timestamp.unwrap_or_else(|| Utc::now().timestamp()))
+            .await?;
 
         Ok(())
     }
@@ -362,6 +373,7 @@ impl LazyLineSerializer {
             level: None,
             meta: None,
             line_buffer: None,
+            timestamp: None,
             file_offset: offset,
         }
     }
@@ -452,6 +464,17 @@ impl LineBufferMut for LazyLineSerializer {
     }
 }
 
+impl LineMut for LazyLineSerializer {
+    fn set_timestamp(&mut self, timestamp: i64) -> Result<(), LineMetaError> {
+        self.timestamp = Some(timestamp);
+        Ok(())
+    }
+
+    fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+}
+
 impl GetOffset for LazyLineSerializer {
     fn get_offset(&self) -> Option<u64> {
         Some(self.file_offset.1)
@@ -470,6 +493,8 @@ impl Stream for LazyLines {
             reader,
             ref mut read,
             ref mut current_offset,
+            ref mut current_event,
+            ref mut current_meta,
             ref mut path,
             ref mut total_read,
             ref target_read,
@@ -480,11 +505,18 @@ impl Stream for LazyLines {
         let rc_reader = reader;
         loop {
             if current_offset.is_some() && *path < paths.len() {
-                let ret = LazyLineSerializer::new(
+                let mut ret = LazyLineSerializer::new(
                     rc_reader.clone(),
                     paths[*path].clone(),
                     (*current_offset).unwrap(),
                 );
+                if let Some(event) = current_event {
+                    ret.line_buffer = Some(event.clone());
+                }
+                if let Some(RecordMeta { stream, timestamp }) = current_meta {
+                    ret.meta = Some(json!({ "stream": stream.as_str() }));
+                    ret.timestamp = *timestamp;
+                }
                 *path += 1;
                 Metrics::fs().increment_lines();
                 break Poll::Ready(Some(ret));
@@ -497,14 +529,21 @@ impl Stream for LazyLines {
                 ref mut buf,
                 ref mut offset,
                 ref inode,
+                ref mut container,
+                ref mut multiline,
                 ..
             } = borrow.deref_mut();
 
             if *path >= paths.len() {
-                debug_assert_eq!(*read, 0);
                 *current_offset = None;
+                *current_meta = None;
                 *path = 0;
-                buf.clear();
+                // Decoded and multi-line events are kept outside of the read buffer,
+                // which may already hold part of the next line when flushed on timeout
+                if current_event.take().is_none() {
+                    debug_assert_eq!(*read, 0);
+                    buf.clear();
+                }
             }
 
             // If we've read more than a 16 KB from this one event and reached the end
@@ -532,22 +571,104 @@ impl Stream for LazyLines {
                     *total_read += count.get();
                     // Got a line
                     debug_assert_eq!(*read, 0);
-                    debug!("tailer sendings lines for {:?}", &paths);
                     let count = TryInto::<u64>::try_into(count.get()).unwrap();
                     Metrics::fs().add_bytes(count);
                     *offset += count;
-                    *current_offset = Some((*inode, *offset))
+
+                    let line = &buf[..buf.len() - 1];
+                    let (record, meta) = match container.decode(line) {
+                        Decoded::Plain => (None, None),
+                        // The offset is not exposed until the runtime finishes the record
+                        Decoded::Partial => {
+                            buf.clear();
+                            continue;
+                        }
+                        Decoded::Record(record, meta) => (Some(record), Some(meta)),
+                    };
+
+                    match multiline {
+                        Some(multiline) => {
+                            // The offset of an event is only exposed once all of its
+                            // lines have been read
+                            let flushed =
+                                multiline.push(record.as_deref().unwrap_or(line), *offset, meta);
+                            buf.clear();
+                            if let Some(MultilineEvent {
+                                line,
+                                end_offset,
+                                meta,
+                            }) = flushed
+                            {
+                                debug!("tailer sendings multi-line event for {:?}", &paths);
+                                *current_event = Some(line);
+                                *current_meta = meta;
+                                *current_offset = Some((*inode, end_offset))
+                            }
+                        }
+                        None => {
+                            debug!("tailer sendings lines for {:?}", &paths);
+                            if record.is_some() {
+                                buf.clear();
+                            }
+                            *current_event = record;
+                            *current_meta = meta;
+                            *current_offset = Some((*inode, *offset))
+                        }
+                    }
                 }
                 // We got an error, should we propagate this up somehow? calls to TailedFile::tail
                 // will implicitly retry
                 Err(e) => warn!("{}", e),
                 // Reached the end of the file, but havn't hit a newline yet
-                Ok(None) => break Poll::Ready(None),
+                Ok(None) => {
+                    if let Some(multiline) = multiline {
+                        if let Some(MultilineEvent {
+                            line,
+                            end_offset,
+                            meta,
+                        }) = multiline.flush_expired()
+                        {
+                            debug!("flushing timed out multi-line event for {:?}", &paths);
+                            *current_event = Some(line);
+                            *current_meta = meta;
+                            *current_offset = Some((*inode, end_offset));
+                            continue;
+                        }
+
+                        // Nothing else might be written to the file, make sure the
+                        // pending event gets flushed once it times out
+                        if let (Some(delay), Some(sender)) =
+                            (multiline.schedule_flush(), resume_channel_send)
+                        {
+                            schedule_resume(sender.clone(), *inode, delay);
+                        }
+                    }
+                    break Poll::Ready(None);
+                }
             }
         }
     }
 }
 
+/// Sends a resume event for the file after the delay has elapsed
+fn schedule_resume(
+    sender: Sender<(u64, chrono::DateTime<chrono::Utc>)>,
+    inode: u64,
+    delay: Duration,
+) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn(async move {
+                tokio::time::sleep(delay).await;
+                if let Err(e) = sender.send((inode, chrono::offset::Utc::now())).await {
+                    warn!("Couldn't send multi-line flush event: {}", e);
+                }
+            });
+        }
+        Err(e) => warn!("Couldn't schedule multi-line flush: {}", e),
+    }
+}
+
 #[derive(Debug)]
 pub struct TailedFileInner {
     reader: Compat<tokio::io::BufReader<tokio::fs::File>>,
@@ -555,6 +676,17 @@ pub struct TailedFileInner {
     offset: u64,
     file_path: PathBuf,
     inode: u64,
+    container: ContainerLogDecoder,
+    multiline: Option<MultilineBuffer<Option<RecordMeta>>>,
+}
+
+impl TailedFileInner {
+    /// Returns true if there's a multi-line event waiting to be flushed
+    fn has_expired_event(&self) -> bool {
+        self.multiline
+            .as_ref()
+            .map_or(false, MultilineBuffer::is_expired)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -568,6 +700,8 @@ impl<T> TailedFile<T> {
     pub(crate) fn new(
         path: &Path,
         resume_events_sender: Option<Sender<(u64, chrono::DateTime<chrono::Utc>)>>,
+        multiline_rule: Option<Arc<MultilineRule>>,
+        container: ContainerLogDecoder,
     ) -> Result<Self, std::io::Error> {
         Ok(Self {
             inner: Arc::new(Mutex::new(TailedFileInner {
@@ -579,6 +713,8 @@ impl<T> TailedFile<T> {
                 offset: 0,
                 file_path: path.into(),
                 inode: path.metadata()?.ino(),
+                container,
+                multiline: multiline_rule.map(MultilineBuffer::new),
             })),
             resume_events_sender,
             _phantom: std::marker::PhantomData::<T>,
@@ -595,6 +731,16 @@ impl<T> TailedFile<T> {
             .await?;
         Ok(())
     }
+    /// Applies the multi-line rule of the lookup to the next lines of a file tailed without
+    /// one, such as the file of a pod whose annotations weren't known yet. The file is left
+    /// as is while it's being read.
+    pub(crate) fn look_up_multiline_rule(&self, rules: &MultilineRules) {
+        if let Some(mut inner) = self.inner.try_lock() {
+            if inner.multiline.is_none() {
+                inner.multiline = rules.look_up(&inner.file_path).map(MultilineBuffer::new);
+            }
+        }
+    }
     pub(crate) async fn get_inode(&self) -> u64 {
         let inner = self.inner.lock().await;
         inner.inode
@@ -704,8 +850,9 @@ impl TailedFile<LazyLineSerializer> {
                 }
             };
 
-            // if we are at the end of the file there's no work to do
-            if inner.offset == len {
+            // if we are at the end of the file there's no work to do,
+            // unless a multi-line event timed out waiting for more lines
+            if inner.offset == len && !inner.has_expired_event() {
                 return None;
             }
 
@@ -797,6 +944,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn multiline_events_keep_the_timestamp_of_their_first_record() {
+        let file_path = tempdir().unwrap().into_path().join("app.log");
+        std::fs::write(
+            &file_path,
+            "2021-05-01T12:00:00Z stdout F ERROR boom\n\
+             2021-05-01T12:00:05Z stdout F   at frame\n\
+             2021-05-01T12:00:10Z stdout F INFO next\n",
+        )
+        .unwrap();
+        let rule = MultilineRule::new(
+            "*.log",
+            None,
+            Some(r"^\s"),
+            10,
+            1024,
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        let mut file = TailedFile::<LazyLineSerializer>::new(
+            &file_path,
+            None,
+            Some(Arc::new(rule)),
+            ContainerLogDecoder::new(),
+        )
+        .unwrap();
+
+        let mut lines: Vec<_> = tokio_test::block_on(async {
+            file.tail(vec![file_path.clone()])
+                .await
+                .unwrap()
+                .collect()
+                .await
+        });
+        // The last event is held until a line that doesn't continue it is read
+        assert_eq!(lines.len(), 1);
+        let line = &mut lines[0];
+        assert_eq!(line.get_line_buffer().unwrap(), b"ERROR boom\n  at frame");
+        assert_eq!(line.get_timestamp(), Some(1_619_870_400));
+    }
+
     fn get_line() -> LazyLineSerializer {
         let file_path = tempdir().unwrap().into_path().join("test.log");
         let file_inner = Arc::new(Mutex::new(TailedFileInner {
@@ -813,6 +1001,8 @@ mod tests {
             offset: 0,
             file_path,
             inode: 0,
+            container: ContainerLogDecoder::new(),
+            multiline: None,
         }));
         LazyLineSerializer::new(file_inner, "file/path.log".to_owned(), (0, 0))
     }
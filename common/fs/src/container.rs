@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bytes::Bytes;
+use chrono::DateTime;
+use serde_json::Value;
+
+use crate::rule::{GlobRule, Rules};
+
+/// The globs of the files written by the container runtimes, the format of the other files
+/// isn't detected
+pub const CONTAINER_LOG_GLOBS: &[&str] = &["/var/log/containers/**", "/var/log/pods/**"];
+
+/// The most bytes of a record split by the runtime that are reassembled, the record is sent
+/// once it reaches this size and the rest of it is sent as further records
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+/// Returns the rules matching the files written by the container runtimes
+pub fn container_log_rules() -> Rules {
+    let mut rules = Rules::new();
+    for glob in CONTAINER_LOG_GLOBS {
+        rules.add_inclusion(GlobRule::new(*glob).expect("invalid container log glob"));
+    }
+    rules
+}
+
+/// The output stream of a container a record was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    fn from_bytes(value: &[u8]) -> Option<Self> {
+        match value {
+            b"stdout" => Some(LogStream::Stdout),
+            b"stderr" => Some(LogStream::Stderr),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+impl fmt::Display for LogStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Metadata taken from the wrapper of a container log record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordMeta {
+    pub stream: LogStream,
+    /// Unix timestamp in seconds
+    pub timestamp: Option<i64>,
+}
+
+/// The result of decoding a single line of a file
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// The line is not in a container log format and should be used as is
+    Plain,
+    /// The line is part of a record that is continued on the following line(s)
+    Partial,
+    /// A complete record, with the wrapper removed from the message
+    Record(Bytes, RecordMeta),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Plain,
+    /// `<timestamp> <stream> <P|F> <message>` as written by CRI runtimes such as containerd or CRI-O
+    Cri,
+    /// `{"log":"<message>","stream":"<stream>","time":"<timestamp>"}` as written by Docker's
+    /// json-file logging driver
+    Docker,
+}
+
+/// Decodes the lines of a file written by a container runtime
+///
+/// The format is detected from the first line decoded and applies to the rest of the file.
+/// Records split by the runtime are reassembled before being returned, separately for each
+/// stream as the records of stdout and stderr can be interleaved.
+#[derive(Debug, Default)]
+pub struct ContainerLogDecoder {
+    format: Option<Format>,
+    partial: HashMap<LogStream, (Vec<u8>, RecordMeta)>,
+}
+
+impl ContainerLogDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A decoder passing the lines through, for the files not written by a container runtime
+    pub fn plain() -> Self {
+        Self {
+            format: Some(Format::Plain),
+            ..Self::default()
+        }
+    }
+
+    /// Decodes a complete line, without the line terminator
+    pub fn decode(&mut self, line: &[u8]) -> Decoded {
+        let format = match self.format {
+            Some(format) => format,
+            None => {
+                let format = if parse_cri(line).is_some() {
+                    Format::Cri
+                } else if parse_docker(line).is_some() {
+                    Format::Docker
+                } else {
+                    Format::Plain
+                };
+                debug!("detected {:?} log format", format);
+                self.format = Some(format);
+                format
+            }
+        };
+
+        let (message, meta, is_partial) = match format {
+            Format::Plain => return Decoded::Plain,
+            Format::Cri => match parse_cri(line) {
+                Some((message, meta, is_partial)) => (message.to_vec(), meta, is_partial),
+                None => return Decoded::Plain,
+            },
+            Format::Docker => match parse_docker(line) {
+                Some(record) => record,
+                None => return Decoded::Plain,
+            },
+        };
+
+        let (mut buf, meta) = match self.partial.remove(&meta.stream) {
+            Some((mut buf, first_meta)) => {
+                buf.extend_from_slice(&message);
+                (buf, first_meta)
+            }
+            None => (message, meta),
+        };
+
+        if is_partial && buf.len() < MAX_RECORD_BYTES {
+            self.partial.insert(meta.stream, (buf, meta));
+            return Decoded::Partial;
+        }
+
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+        Decoded::Record(buf.into(), meta)
+    }
+}
+
+fn parse_timestamp(value: &[u8]) -> Option<i64> {
+    let value = std::str::from_utf8(value).ok()?;
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.timestamp())
+}
+
+fn parse_cri(line: &[u8]) -> Option<(&[u8], RecordMeta, bool)> {
+    let mut parts = line.splitn(4, |b| *b == b' ');
+    let timestamp = parse_timestamp(parts.next()?)?;
+    let stream = LogStream::from_bytes(parts.next()?)?;
+    // The tag can be followed by further attributes separated by colons
+    let is_partial = match parts.next()?.split(|b| *b == b':').next()? {
+        b"P" => true,
+        b"F" => false,
+        _ => return None,
+    };
+    let message = parts.next().unwrap_or_default();
+
+    Some((
+        message,
+        RecordMeta {
+            stream,
+            timestamp: Some(timestamp),
+        },
+        is_partial,
+    ))
+}
+
+fn parse_docker(line: &[u8]) -> Option<(Vec<u8>, RecordMeta, bool)> {
+    if !line.starts_with(b"{") {
+        return None;
+    }
+
+    let record: Value = serde_json::from_slice(line).ok()?;
+    let stream = LogStream::from_bytes(record.get("stream")?.as_str()?.as_bytes())?;
+    let timestamp = record
+        .get("time")
+        .and_then(Value::as_str)
+        .and_then(|time| parse_timestamp(time.as_bytes()));
+
+    // Messages are split into chunks by the runtime, only the last chunk ends with a new line
+    let mut message = record.get("log")?.as_str()?.as_bytes().to_vec();
+    let is_partial = !message.ends_with(b"\n");
+    if !is_partial {
+        message.pop();
+    }
+
+    Some((message, RecordMeta { stream, timestamp }, is_partial))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str, stream: LogStream, timestamp: i64) -> Decoded {
+        Decoded::Record(
+            Bytes::copy_from_slice(line.as_bytes()),
+            RecordMeta {
+                stream,
+                timestamp: Some(timestamp),
+            },
+        )
+    }
+
+    #[test]
+    fn decodes_cri_records() {
+        let mut decoder = ContainerLogDecoder::new();
+
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:00.123456789Z stdout F hello world"),
+            record("hello world", LogStream::Stdout, 1_619_870_400)
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:01.000000000+00:00 stderr F "),
+            record("", LogStream::Stderr, 1_619_870_401)
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:02Z stdout P first "),
+            Decoded::Partial
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:03Z stdout P second "),
+            Decoded::Partial
+        );
+        // The timestamp of the first partial record is kept
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:04Z stdout F third"),
+            record("first second third", LogStream::Stdout, 1_619_870_402)
+        );
+        // Malformed lines are passed through
+        assert_eq!(decoder.decode(b"not a cri line"), Decoded::Plain);
+    }
+
+    #[test]
+    fn flushes_records_reaching_the_max_size() {
+        let mut decoder = ContainerLogDecoder::new();
+        let chunk = "a".repeat(MAX_RECORD_BYTES / 2);
+        let partial = format!("2021-05-01T12:00:00Z stdout P {}", chunk);
+
+        assert_eq!(decoder.decode(partial.as_bytes()), Decoded::Partial);
+        assert_eq!(
+            decoder.decode(partial.as_bytes()),
+            record(&chunk.repeat(2), LogStream::Stdout, 1_619_870_400)
+        );
+        // The rest of the record is sent on its own
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:01Z stdout F end"),
+            record("end", LogStream::Stdout, 1_619_870_401)
+        );
+    }
+
+    #[test]
+    fn joins_records_by_stream() {
+        let mut decoder = ContainerLogDecoder::new();
+
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:00Z stdout P out "),
+            Decoded::Partial
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:01Z stderr P err "),
+            Decoded::Partial
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:02Z stderr F end"),
+            record("err end", LogStream::Stderr, 1_619_870_401)
+        );
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:03Z stdout F end"),
+            record("out end", LogStream::Stdout, 1_619_870_400)
+        );
+    }
+
+    #[test]
+    fn decodes_docker_records() {
+        let mut decoder = ContainerLogDecoder::new();
+
+        assert_eq!(
+            decoder.decode(
+                br#"{"log":"hello \"world\"\n","stream":"stdout","time":"2021-05-01T12:00:00.123Z"}"#
+            ),
+            record(r#"hello "world""#, LogStream::Stdout, 1_619_870_400)
+        );
+        assert_eq!(
+            decoder.decode(br#"{"log":"first ","stream":"stderr","time":"2021-05-01T12:00:01Z"}"#),
+            Decoded::Partial
+        );
+        assert_eq!(
+            decoder
+                .decode(br#"{"log":"second\r\n","stream":"stderr","time":"2021-05-01T12:00:02Z"}"#),
+            record("first second", LogStream::Stderr, 1_619_870_401)
+        );
+    }
+
+    #[test]
+    fn plain_files_are_not_decoded() {
+        let mut decoder = ContainerLogDecoder::new();
+
+        assert_eq!(decoder.decode(b"Some application log line"), Decoded::Plain);
+        // The format is only detected once per file
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:00Z stdout F hello world"),
+            Decoded::Plain
+        );
+
+        let mut decoder = ContainerLogDecoder::plain();
+        assert_eq!(
+            decoder.decode(b"2021-05-01T12:00:00Z stdout F hello world"),
+            Decoded::Plain
+        );
+    }
+}
@@ -6,8 +6,12 @@ extern crate lazy_static;
 
 /// Prototype
 pub mod cache;
+/// Decoding of the log formats written by container runtimes
+pub mod container;
 /// Contains the error type(s) for this crate
 pub mod error;
+/// Rules for aggregating consecutive lines into multi-line events
+pub mod multiline;
 /// Traits and types for defining exclusion and inclusion rules
 pub mod rule;
 /// Defines the source implementation for fs
@@ -0,0 +1,473 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use pcre2::bytes::Regex;
+
+use crate::rule::{GlobRule, Rule, RuleError};
+
+/// Default maximum number of lines aggregated into a single event
+pub const DEFAULT_MAX_LINES: usize = 500;
+/// Default maximum size in bytes of an aggregated event
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+/// Default time an incomplete event is held before being flushed
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Describes how consecutive lines of the files matching a glob are combined into a single event
+#[derive(Debug)]
+pub struct MultilineRule {
+    glob: GlobRule,
+    start: Option<Regex>,
+    continuation: Option<Regex>,
+    max_lines: usize,
+    max_bytes: usize,
+    flush_timeout: Duration,
+}
+
+impl MultilineRule {
+    /// Creates a new MultilineRule, at least one of the start or continuation patterns is required
+    pub fn new(
+        glob: &str,
+        start: Option<&str>,
+        continuation: Option<&str>,
+        max_lines: usize,
+        max_bytes: usize,
+        flush_timeout: Duration,
+    ) -> Result<Self, RuleError> {
+        if start.is_none() && continuation.is_none() {
+            return Err(RuleError::MultilinePattern(glob.into()));
+        }
+
+        Ok(Self {
+            glob: GlobRule::new(glob)?,
+            start: start
+                .map(Regex::new)
+                .transpose()
+                .map_err(RuleError::Regex)?,
+            continuation: continuation
+                .map(Regex::new)
+                .transpose()
+                .map_err(RuleError::Regex)?,
+            max_lines,
+            max_bytes,
+            flush_timeout,
+        })
+    }
+
+    /// Returns true if the rule applies to the file at path
+    pub fn matches(&self, path: &Path) -> bool {
+        self.glob.matches(path)
+    }
+
+    /// Returns true if the line belongs to the event that precedes it
+    ///
+    /// A line matching the start pattern always begins a new event, otherwise the line continues
+    /// the previous event when it matches the continuation pattern or, if there is none, when a
+    /// start pattern is defined.
+    pub fn is_continuation(&self, line: &[u8]) -> bool {
+        if let Some(start) = &self.start {
+            if start.is_match(line).unwrap_or(false) {
+                return false;
+            }
+        }
+
+        match &self.continuation {
+            Some(continuation) => continuation.is_match(line).unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+/// Finds the rule of a file outside of the configuration, such as from the annotations of the
+/// pod writing it
+pub type MultilineLookup = Box<dyn Fn(&Path) -> Option<MultilineRule> + Send + Sync>;
+
+/// A list of multi-line rules, the first rule matching a file is the one applied to it
+#[derive(Default)]
+pub struct MultilineRules {
+    rules: Vec<Arc<MultilineRule>>,
+    lookup: Option<MultilineLookup>,
+}
+
+impl fmt::Debug for MultilineRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultilineRules")
+            .field("rules", &self.rules)
+            .field("lookup", &self.lookup.is_some())
+            .finish()
+    }
+}
+
+impl MultilineRules {
+    /// Constructs an empty instance of MultilineRules
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            lookup: None,
+        }
+    }
+    /// Adds a rule to the end of the list
+    pub fn add(&mut self, rule: MultilineRule) {
+        self.rules.push(Arc::new(rule))
+    }
+    /// Sets the lookup consulted before the rules, when a file is first tailed
+    pub fn set_lookup(&mut self, lookup: MultilineLookup) {
+        self.lookup = Some(lookup)
+    }
+    /// Returns the rule of the lookup for the file at path, or the first rule that applies to it
+    pub fn find(&self, path: &Path) -> Option<Arc<MultilineRule>> {
+        if let Some(rule) = self.lookup.as_ref().and_then(|lookup| lookup(path)) {
+            return Some(Arc::new(rule));
+        }
+        self.rules.iter().find(|rule| rule.matches(path)).cloned()
+    }
+    /// Returns the rule of the lookup for the file at path, such as a rule found after the
+    /// file was first tailed
+    pub fn look_up(&self, path: &Path) -> Option<Arc<MultilineRule>> {
+        let lookup = self.lookup.as_ref()?;
+        lookup(path).map(Arc::new)
+    }
+    /// Returns true if there are no rules defined
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.lookup.is_none()
+    }
+}
+
+/// An event made of one or more lines, along with the metadata of its first line
+#[derive(Debug, PartialEq)]
+pub struct MultilineEvent<T> {
+    pub line: Bytes,
+    pub end_offset: u64,
+    pub meta: T,
+}
+
+#[derive(Debug)]
+struct PendingEvent<T> {
+    buf: Vec<u8>,
+    lines: usize,
+    end_offset: u64,
+    meta: T,
+    started: Instant,
+    flush_scheduled: bool,
+}
+
+impl<T> PendingEvent<T> {
+    fn new(line: &[u8], end_offset: u64, meta: T) -> Self {
+        Self {
+            buf: line.to_vec(),
+            lines: 1,
+            end_offset,
+            meta,
+            started: Instant::now(),
+            flush_scheduled: false,
+        }
+    }
+
+    fn append(&mut self, line: &[u8], end_offset: u64) {
+        self.buf.push(b'\n');
+        self.buf.extend_from_slice(line);
+        self.lines += 1;
+        self.end_offset = end_offset;
+    }
+
+    fn into_flushed(self) -> MultilineEvent<T> {
+        MultilineEvent {
+            line: self.buf.into(),
+            end_offset: self.end_offset,
+            meta: self.meta,
+        }
+    }
+}
+
+/// Aggregates the lines read from a single file into events according to a MultilineRule
+///
+/// Events are returned along with the file offset of the end of their last line, an offset
+/// is never returned for a line that is still part of an event that has not been flushed.
+#[derive(Debug)]
+pub struct MultilineBuffer<T = ()> {
+    rule: Arc<MultilineRule>,
+    pending: Option<PendingEvent<T>>,
+}
+
+impl<T> MultilineBuffer<T> {
+    pub fn new(rule: Arc<MultilineRule>) -> Self {
+        Self {
+            rule,
+            pending: None,
+        }
+    }
+
+    /// Adds a complete line, without the line terminator, ending at end_offset in the file
+    ///
+    /// Returns the previous event when the line does not belong to it.
+    pub fn push(&mut self, line: &[u8], end_offset: u64, meta: T) -> Option<MultilineEvent<T>> {
+        match self.pending.take() {
+            Some(mut event)
+                if self.rule.is_continuation(line)
+                    && event.lines < self.rule.max_lines
+                    && event.buf.len() + 1 + line.len() <= self.rule.max_bytes =>
+            {
+                event.append(line, end_offset);
+                self.pending = Some(event);
+                None
+            }
+            previous => {
+                self.pending = Some(PendingEvent::new(line, end_offset, meta));
+                previous.map(PendingEvent::into_flushed)
+            }
+        }
+    }
+
+    /// Returns true if the pending event has been held for longer than the flush timeout
+    pub fn is_expired(&self) -> bool {
+        self.pending.as_ref().map_or(false, |event| {
+            event.started.elapsed() >= self.rule.flush_timeout
+        })
+    }
+
+    /// Takes the pending event if it has been held for longer than the flush timeout
+    pub fn flush_expired(&mut self) -> Option<MultilineEvent<T>> {
+        if self.is_expired() {
+            self.pending.take().map(PendingEvent::into_flushed)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the time left until the pending event expires, only once per pending event
+    /// so a single flush is scheduled for it
+    pub fn schedule_flush(&mut self) -> Option<Duration> {
+        let flush_timeout = self.rule.flush_timeout;
+        let event = self
+            .pending
+            .as_mut()
+            .filter(|event| !event.flush_scheduled)?;
+        event.flush_scheduled = true;
+        Some(flush_timeout.saturating_sub(event.started.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(start: Option<&str>, continuation: Option<&str>) -> MultilineBuffer<()> {
+        MultilineBuffer::new(Arc::new(
+            MultilineRule::new(
+                "*.log",
+                start,
+                continuation,
+                DEFAULT_MAX_LINES,
+                DEFAULT_MAX_BYTES,
+                Duration::from_millis(0),
+            )
+            .unwrap(),
+        ))
+    }
+
+    #[test]
+    fn rule_requires_a_pattern() {
+        assert!(MultilineRule::new(
+            "*.log",
+            None,
+            None,
+            DEFAULT_MAX_LINES,
+            DEFAULT_MAX_BYTES,
+            DEFAULT_FLUSH_TIMEOUT
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rules_find_first_match() {
+        let mut rules = MultilineRules::new();
+        rules.add(
+            MultilineRule::new(
+                "/var/log/java/*.log",
+                Some(r"^\d{4}-"),
+                None,
+                10,
+                DEFAULT_MAX_BYTES,
+                DEFAULT_FLUSH_TIMEOUT,
+            )
+            .unwrap(),
+        );
+        rules.add(
+            MultilineRule::new(
+                "*.log",
+                None,
+                Some(r"^\s"),
+                20,
+                DEFAULT_MAX_BYTES,
+                DEFAULT_FLUSH_TIMEOUT,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            rules
+                .find(Path::new("/var/log/java/app.log"))
+                .unwrap()
+                .max_lines,
+            10
+        );
+        assert_eq!(
+            rules
+                .find(Path::new("/var/log/python.log"))
+                .unwrap()
+                .max_lines,
+            20
+        );
+        assert!(rules.find(Path::new("/var/log/syslog")).is_none());
+    }
+
+    #[test]
+    fn rules_use_the_lookup_first() {
+        let mut rules = MultilineRules::new();
+        rules.add(
+            MultilineRule::new(
+                "*.log",
+                None,
+                Some(r"^\s"),
+                20,
+                DEFAULT_MAX_BYTES,
+                DEFAULT_FLUSH_TIMEOUT,
+            )
+            .unwrap(),
+        );
+        rules.set_lookup(Box::new(|path| {
+            if !path.starts_with("/var/log/containers") {
+                return None;
+            }
+            MultilineRule::new(
+                path.to_str()?,
+                Some(r"^\d"),
+                None,
+                10,
+                DEFAULT_MAX_BYTES,
+                DEFAULT_FLUSH_TIMEOUT,
+            )
+            .ok()
+        }));
+
+        assert_eq!(
+            rules
+                .find(Path::new("/var/log/containers/app.log"))
+                .unwrap()
+                .max_lines,
+            10
+        );
+        assert_eq!(
+            rules
+                .find(Path::new("/var/log/python.log"))
+                .unwrap()
+                .max_lines,
+            20
+        );
+        // Only the lookup is consulted for the files tailed without a rule
+        assert!(rules.look_up(Path::new("/var/log/python.log")).is_none());
+        assert_eq!(
+            rules
+                .look_up(Path::new("/var/log/containers/app.log"))
+                .unwrap()
+                .max_lines,
+            10
+        );
+    }
+
+    #[test]
+    fn start_pattern_aggregates_stack_traces() {
+        let mut buf = buffer(Some(r"^\d{4}-\d{2}-\d{2}"), None);
+
+        assert_eq!(buf.push(b"2021-05-01 ERROR boom", 22, ()), None);
+        assert_eq!(
+            buf.push(b"java.lang.IllegalStateException: boom", 61, ()),
+            None
+        );
+        assert_eq!(
+            buf.push(b"\tat com.example.Main.run(Main.java:12)", 101, ()),
+            None
+        );
+
+        let MultilineEvent {
+            line: event,
+            end_offset: offset,
+            ..
+        } = buf.push(b"2021-05-01 INFO recovered", 127, ()).unwrap();
+        assert_eq!(
+            &event[..],
+            &b"2021-05-01 ERROR boom\njava.lang.IllegalStateException: boom\n\tat com.example.Main.run(Main.java:12)"[..]
+        );
+        // The offset only covers the flushed event, not the line that started the next one
+        assert_eq!(offset, 101);
+
+        let MultilineEvent {
+            line: event,
+            end_offset: offset,
+            ..
+        } = buf.flush_expired().unwrap();
+        assert_eq!(&event[..], &b"2021-05-01 INFO recovered"[..]);
+        assert_eq!(offset, 127);
+        assert!(buf.flush_expired().is_none());
+    }
+
+    #[test]
+    fn continuation_pattern_aggregates_indented_lines() {
+        let mut buf = buffer(None, Some(r"^(\s|Traceback|\w+Error:)"));
+
+        assert_eq!(buf.push(b"handling request", 17, ()), None);
+        assert_eq!(
+            buf.push(b"Traceback (most recent call last):", 52, ()),
+            None
+        );
+        assert_eq!(buf.push(b"  File \"app.py\", line 3", 76, ()), None);
+        assert_eq!(buf.push(b"ValueError: nope", 93, ()), None);
+
+        let MultilineEvent {
+            line: event,
+            end_offset: offset,
+            ..
+        } = buf.push(b"next request", 106, ()).unwrap();
+        assert_eq!(event.split(|b| *b == b'\n').count(), 4);
+        assert_eq!(offset, 93);
+    }
+
+    #[test]
+    fn limits_split_events() {
+        let mut buf = MultilineBuffer::new(Arc::new(
+            MultilineRule::new("*.log", Some("^start"), None, 2, 16, DEFAULT_FLUSH_TIMEOUT)
+                .unwrap(),
+        ));
+
+        assert_eq!(buf.push(b"start", 6, ()), None);
+        assert_eq!(buf.push(b"one", 10, ()), None);
+        // max_lines reached
+        let MultilineEvent {
+            line: event,
+            end_offset: offset,
+            ..
+        } = buf.push(b"two", 14, ()).unwrap();
+        assert_eq!(&event[..], &b"start\none"[..]);
+        assert_eq!(offset, 10);
+
+        assert_eq!(buf.push(b"three", 20, ()), None);
+        // max_bytes reached
+        let MultilineEvent { line: event, .. } = buf.push(b"some more text", 35, ()).unwrap();
+        assert_eq!(&event[..], &b"two\nthree"[..]);
+    }
+
+    #[test]
+    fn flush_is_scheduled_once_per_event() {
+        let mut buf = buffer(Some("^start"), None);
+        assert!(buf.schedule_flush().is_none());
+
+        buf.push(b"start", 6, ());
+        assert!(buf.schedule_flush().is_some());
+        assert!(buf.schedule_flush().is_none());
+
+        buf.push(b"start", 12, ());
+        assert!(buf.schedule_flush().is_some());
+    }
+}
@@ -32,6 +32,8 @@ pub enum RuleError {
     Regex(RegexError),
     #[error("{0}")]
     Pattern(PatternError),
+    #[error("multi-line rule for {0} requires a start or continuation pattern")]
+    MultilinePattern(String),
 }
 
 impl Status {
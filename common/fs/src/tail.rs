@@ -3,6 +3,7 @@ use crate::cache::event::Event;
 use crate::cache::tailed_file::LazyLineSerializer;
 pub use crate::cache::DirPathBuf;
 use crate::cache::{EntryKey, Error as CacheError, FileSystem, EVENT_STREAM_BUFFER_COUNT};
+use crate::multiline::MultilineRules;
 use crate::rule::Rules;
 use metrics::Metrics;
 use state::FileId;
@@ -71,6 +72,19 @@ impl Default for Lookback {
 
 type SyncHashMap<K, V> = Arc<Mutex<HashMap<K, V>>>;
 
+/// Replaces the inclusion/exclusion rules used by a Tailer
+#[derive(Clone)]
+pub struct RulesHandle {
+    fs_cache: Arc<Mutex<FileSystem>>,
+}
+
+impl RulesHandle {
+    /// Applies the rules to the files found from now on and stops tailing the excluded files
+    pub async fn set(&self, rules: Rules) {
+        self.fs_cache.lock().await.set_rules(rules)
+    }
+}
+
 /// Tails files on a filesystem by inheriting events from a Watcher
 pub struct Tailer {
     lookback_config: Lookback,
@@ -84,17 +98,31 @@ impl Tailer {
     pub fn new(
         watched_dirs: Vec<DirPathBuf>,
         rules: Rules,
+        multiline_rules: MultilineRules,
+        container_log_rules: Rules,
         lookback_config: Lookback,
         initial_offsets: Option<HashMap<FileId, u64>>,
     ) -> Self {
         Self {
             lookback_config,
-            fs_cache: Arc::new(Mutex::new(FileSystem::new(watched_dirs, rules))),
+            fs_cache: Arc::new(Mutex::new(FileSystem::new(
+                watched_dirs,
+                rules,
+                multiline_rules,
+                container_log_rules,
+            ))),
             initial_offsets,
             event_times: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Returns a handle to replace the rules of the files to tail while the tailer is running
+    pub fn rules_handle(&self) -> RulesHandle {
+        RulesHandle {
+            fs_cache: self.fs_cache.clone(),
+        }
+    }
+
     fn get_file_for_path(fs: &FileSystem, next_path: &std::path::Path) -> Option<EntryKey> {
         let entries = fs.entries.borrow();
         let mut next_path = next_path;
@@ -394,8 +422,12 @@ impl Tailer {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::container::container_log_rules;
+    use crate::multiline::{MultilineRule, DEFAULT_MAX_BYTES, DEFAULT_MAX_LINES};
     use crate::rule::{GlobRule, Rules};
     use crate::test::LOGGER;
+    use http::types::body::{LineBufferMut, LineMeta};
+    use state::GetOffset;
     use std::convert::TryInto;
     use std::fs::File;
     use std::io::Write;
@@ -448,6 +480,8 @@ mod test {
                         .try_into()
                         .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
                     rules,
+                    MultilineRules::new(),
+                    container_log_rules(),
                     Lookback::None,
                     None,
                 );
@@ -498,6 +532,8 @@ mod test {
                         .try_into()
                         .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
                     rules,
+                    MultilineRules::new(),
+                    container_log_rules(),
                     Lookback::SmallFiles,
                     None,
                 );
@@ -549,6 +585,8 @@ mod test {
                         .try_into()
                         .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
                     rules,
+                    MultilineRules::new(),
+                    container_log_rules(),
                     Lookback::Start,
                     None,
                 );
@@ -579,4 +617,151 @@ mod test {
             })
         })
     }
+
+    #[test]
+    fn multiline_start_lookback() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let mut multiline_rules = MultilineRules::new();
+                multiline_rules.add(
+                    MultilineRule::new(
+                        "*.log",
+                        Some(r"^\d{4}-\d{2}-\d{2}"),
+                        None,
+                        DEFAULT_MAX_LINES,
+                        DEFAULT_MAX_BYTES,
+                        std::time::Duration::from_millis(100),
+                    )
+                    .unwrap(),
+                );
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+                let stack_trace = vec![
+                    "2021-05-01 12:00:00 ERROR request failed",
+                    "java.lang.IllegalStateException: boom",
+                    "\tat com.example.Handler.handle(Handler.java:42)",
+                    "\tat com.example.Main.main(Main.java:7)",
+                ];
+                for line in stack_trace.iter() {
+                    writeln!(file, "{}", line).expect("Couldn't write to temp log file...");
+                }
+                writeln!(file, "2021-05-01 12:00:01 INFO recovered")
+                    .expect("Couldn't write to temp log file...");
+                file.sync_all().expect("Failed to sync file");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    multiline_rules,
+                    container_log_rules(),
+                    Lookback::Start,
+                    None,
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let events = take_events!(stream, 2).await;
+                let mut events = events.into_iter().flatten().flatten().collect::<Vec<_>>();
+                assert_eq!(events.len(), 2);
+
+                let expected = stack_trace.join("\n");
+                assert_eq!(
+                    std::str::from_utf8(events[0].get_line_buffer().unwrap()).unwrap(),
+                    expected
+                );
+                // The stored offset covers the whole event and nothing past it
+                assert_eq!(events[0].get_offset(), Some(expected.len() as u64 + 1));
+
+                // The last event is flushed once the timeout elapses
+                assert_eq!(
+                    std::str::from_utf8(events[1].get_line_buffer().unwrap()).unwrap(),
+                    "2021-05-01 12:00:01 INFO recovered"
+                );
+                assert_eq!(
+                    events[1].get_offset(),
+                    Some(file_path.metadata().unwrap().len())
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn cri_start_lookback() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+                let mut container_rules = Rules::new();
+                container_rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+                for line in &[
+                    "2021-05-01T12:00:00.000000001Z stdout F first line",
+                    "2021-05-01T12:00:01.000000001Z stderr P second ",
+                    "2021-05-01T12:00:01.000000002Z stderr F line",
+                ] {
+                    writeln!(file, "{}", line).expect("Couldn't write to temp log file...");
+                }
+                file.sync_all().expect("Failed to sync file");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    MultilineRules::new(),
+                    // The format is only detected in the container log directories
+                    container_rules,
+                    Lookback::Start,
+                    None,
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let events = take_events!(stream, 2).await;
+                let mut events = events.into_iter().flatten().flatten().collect::<Vec<_>>();
+                assert_eq!(events.len(), 2);
+
+                assert_eq!(
+                    std::str::from_utf8(events[0].get_line_buffer().unwrap()).unwrap(),
+                    "first line"
+                );
+                assert_eq!(
+                    events[0].get_meta(),
+                    Some(&serde_json::json!({"stream": "stdout"}))
+                );
+                assert_eq!(
+                    std::str::from_utf8(events[1].get_line_buffer().unwrap()).unwrap(),
+                    "second line"
+                );
+                assert_eq!(
+                    events[1].get_meta(),
+                    Some(&serde_json::json!({"stream": "stderr"}))
+                );
+                assert_eq!(
+                    events[1].get_offset(),
+                    Some(file_path.metadata().unwrap().len())
+                );
+            });
+        });
+    }
 }
@@ -1,9 +1,11 @@
 use std::convert::TryInto;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 use crate::limit::RateLimiter;
-use crate::offsets::Offset;
-use crate::retry::{self, RetrySender};
+use crate::offsets::{BatchId, Offset, OffsetTracker};
+use crate::retry::{self, RetryItem, RetrySender};
+use crate::sink::{self, Sink};
 use crate::types::body::IngestBodyBuffer;
 use crate::types::client::Client as HttpClient;
 use crate::types::error::HttpError;
@@ -15,9 +17,12 @@ use state::{FileOffsetFlushHandle, FileOffsetWriteHandle};
 
 /// Http(s) client used to send logs to the Ingest API
 pub struct Client {
-    inner: HttpClient,
+    inner: RwLock<Arc<HttpClient>>,
+    timeout: Option<Duration>,
     limiter: RateLimiter,
     retry: RetrySender,
+    sink: Option<Box<dyn Sink>>,
+    offsets: Mutex<OffsetTracker>,
     state_write: Option<FileOffsetWriteHandle>,
     state_flush: Option<FileOffsetFlushHandle>,
 }
@@ -41,6 +46,8 @@ where
     Retry(#[from] retry::Error),
     #[error("{0}")]
     State(#[from] state::FileOffsetStateError),
+    #[error("{0}")]
+    Sink(#[from] sink::Error),
 }
 
 impl Client {
@@ -55,71 +62,239 @@ impl Client {
             .map(|(sw, sf)| (Some(sw), Some(sf)))
             .unwrap_or((None, None));
         Self {
-            inner: HttpClient::new(template),
+            inner: RwLock::new(Arc::new(HttpClient::new(template))),
+            timeout: None,
             limiter: RateLimiter::new(10),
             retry,
+            sink: None,
+            offsets: Mutex::new(OffsetTracker::new()),
             state_write,
             state_flush,
         }
     }
 
+    /// Registers the offsets of a batch about to be sent, when they are to be committed
+    fn register(&self, file_offsets: Option<&[Offset]>) -> Option<BatchId> {
+        match (self.state_write.as_ref(), file_offsets) {
+            (Some(_), Some(offsets)) => Some(
+                self.offsets
+                    .lock()
+                    .expect("offsets lock poisoned")
+                    .register(offsets),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Marks the batch as done and writes the offsets no pending batch precedes
+    async fn acknowledge(&self, batch: Option<BatchId>) -> Result<(), state::FileOffsetStateError> {
+        if let (Some(wh), Some(sf)) = (self.state_write.as_ref(), self.state_flush.as_ref()) {
+            let committable = {
+                let mut offsets = self.offsets.lock().expect("offsets lock poisoned");
+                // Batches whose retry file was evicted or couldn't be read won't come back
+                self.retry
+                    .take_dropped()
+                    .into_iter()
+                    .chain(batch)
+                    .flat_map(|batch| offsets.acknowledge(batch))
+                    .collect::<Vec<_>>()
+            };
+            if committable.is_empty() {
+                return Ok(());
+            }
+            for (key, offset) in committable {
+                trace!("Updating offset for {:?} to {}", key, offset);
+                if let Err(e) = wh.update(&key, offset).await {
+                    error!("Unable to write offsets. error: {}", e);
+                }
+            }
+            // Flush the state
+            sf.flush().await?
+        }
+        Ok(())
+    }
+
+    /// Marks a batch that won't be sent as done, so that it doesn't hold back the offsets
+    async fn discard(&self, batch: Option<BatchId>) {
+        if let Err(e) = self.acknowledge(batch).await {
+            error!("Unable to flush state to disk. error: {}", e);
+        }
+    }
+
     pub async fn send<T>(
         &self,
         body: IngestBodyBuffer,
         file_offsets: Option<&[Offset]>,
     ) -> Result<SendStatus, ClientError<T>>
+    where
+        T: Send + 'static,
+        ClientError<T>: From<HttpError<IngestBodyBuffer>>,
+    {
+        // Offsets are written once the batch and the batches before it are acknowledged
+        let batch = self.register(file_offsets);
+        self.send_batch(body, file_offsets, batch).await
+    }
+
+    async fn send_batch<T>(
+        &self,
+        body: IngestBodyBuffer,
+        file_offsets: Option<&[Offset]>,
+        batch: Option<BatchId>,
+    ) -> Result<SendStatus, ClientError<T>>
     where
         T: Send + 'static,
         ClientError<T>: From<HttpError<IngestBodyBuffer>>,
     {
         Metrics::http().add_request_size(body.len().try_into().unwrap());
-        if let (Some(wh), Some(offsets)) = (self.state_write.as_ref(), file_offsets) {
-            for (key, offset) in offsets {
-                trace!("Updating offset for {:?} to {}", key, offset);
-                if let Err(e) = wh.update(key, *offset).await {
-                    error!("Unable to write offsets. error: {}", e);
+        if let Some(sink) = self.sink.as_ref() {
+            return match sink.write(&body).await {
+                Ok(()) => {
+                    self.acknowledge(batch).await?;
+                    Ok(SendStatus::Sent)
                 }
-            }
+                Err(e) => {
+                    self.discard(batch).await;
+                    Err(e.into())
+                }
+            };
         }
-        let sf = self.state_flush.as_ref();
         let start = Instant::now();
-        match self
-            .inner
+        let inner = self.inner.read().expect("client lock poisoned").clone();
+        match inner
             .send(self.limiter.get_slot(body).as_ref().clone())
             .await
         {
             Ok(Response::Failed(_, s, r)) => {
                 Metrics::http().add_request_failure(start);
                 debug!("Failed request: {}", r);
+                self.discard(batch).await;
                 Err(ClientError::BadRequest(s))
             }
             Err(HttpError::Send(body, e)) => {
                 Metrics::http().add_request_failure(start);
                 warn!("failed sending http request, retrying: {}", e);
-                self.retry.retry(file_offsets, &body).await?;
+                self.retry_later(file_offsets, batch, &body).await?;
                 Ok(SendStatus::Retry(e))
             }
             Err(HttpError::Timeout(body)) => {
                 Metrics::http().add_request_timeout(start);
-                self.retry.retry(file_offsets, &body).await?;
+                self.retry_later(file_offsets, batch, &body).await?;
                 Ok(SendStatus::RetryTimeout)
             }
             Err(e) => {
                 Metrics::http().add_request_failure(start);
+                self.discard(batch).await;
                 Err(e.into())
             }
             Ok(Response::Sent) => {
                 Metrics::http().add_request_success(start);
-                if let Some(sf) = sf {
-                    // Flush the state
-                    sf.flush().await?
-                }
+                self.acknowledge(batch).await?;
                 Ok(SendStatus::Sent)
             } //success
         }
     }
 
+    /// Stores the body to be retried, its offsets stay pending until the retry is sent
+    async fn retry_later(
+        &self,
+        file_offsets: Option<&[Offset]>,
+        batch: Option<BatchId>,
+        body: &IngestBodyBuffer,
+    ) -> Result<(), retry::Error> {
+        let result = self.retry.retry(file_offsets, batch, body).await;
+        if result.is_err() {
+            self.discard(batch).await;
+        }
+        result
+    }
+
+    /// Sends the body unless the deadline is reached first, in which case it's stored to be
+    /// retried by the next run of the agent
+    pub async fn send_until<T>(
+        &self,
+        body: IngestBodyBuffer,
+        file_offsets: Option<&[Offset]>,
+        deadline: Instant,
+    ) -> Result<SendStatus, ClientError<T>>
+    where
+        T: Send + 'static,
+        ClientError<T>: From<HttpError<IngestBodyBuffer>>,
+    {
+        let batch = self.register(file_offsets);
+        self.send_batch_until(body, file_offsets, batch, deadline)
+            .await
+    }
+
+    async fn send_batch_until<T>(
+        &self,
+        body: IngestBodyBuffer,
+        file_offsets: Option<&[Offset]>,
+        batch: Option<BatchId>,
+        deadline: Instant,
+    ) -> Result<SendStatus, ClientError<T>>
+    where
+        T: Send + 'static,
+        ClientError<T>: From<HttpError<IngestBodyBuffer>>,
+    {
+        let pending = body.clone();
+        let send = self.send_batch(body, file_offsets, batch);
+        match tokio::time::timeout_at(deadline.into(), send).await {
+            Ok(status) => status,
+            Err(_) => {
+                self.retry_later(file_offsets, batch, &pending).await?;
+                Ok(SendStatus::RetryTimeout)
+            }
+        }
+    }
+
+    /// Sends a body read back from a retry file, optionally before a deadline. Only the bodies
+    /// stored by this run hold back offsets, the offsets of a body stored by a previous run
+    /// were never committed and its lines are read again from the committed offsets
+    pub async fn send_retry<T>(
+        &self,
+        item: RetryItem,
+        deadline: Option<Instant>,
+    ) -> Result<SendStatus, ClientError<T>>
+    where
+        T: Send + 'static,
+        ClientError<T>: From<HttpError<IngestBodyBuffer>>,
+    {
+        let RetryItem {
+            body_buffer,
+            offsets,
+            batch,
+            ..
+        } = item;
+        let offsets = offsets.as_deref();
+        match deadline {
+            Some(deadline) => {
+                self.send_batch_until(body_buffer, offsets, batch, deadline)
+                    .await
+            }
+            None => self.send_batch(body_buffer, offsets, batch).await,
+        }
+    }
+
+    /// Writes the bodies to the sink instead of sending them to the ingestion API
+    pub fn set_sink(&mut self, sink: Box<dyn Sink>) {
+        self.sink = Some(sink);
+    }
+
     pub fn set_timeout(&mut self, timeout: Duration) {
-        self.inner.set_timeout(timeout)
+        self.timeout = Some(timeout);
+        // No request can be in flight while the client is borrowed mutably
+        Arc::get_mut(self.inner.get_mut().expect("client lock poisoned"))
+            .expect("client in use")
+            .set_timeout(timeout)
+    }
+
+    /// Replaces the template used to build the requests, requests in flight complete with
+    /// the previous template
+    pub fn set_template(&self, template: RequestTemplate) {
+        let mut inner = HttpClient::new(template);
+        if let Some(timeout) = self.timeout {
+            inner.set_timeout(timeout);
+        }
+        *self.inner.write().expect("client lock poisoned") = Arc::new(inner);
     }
 }
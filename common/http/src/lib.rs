@@ -7,6 +7,7 @@ pub mod limit;
 pub mod metrics_endpoint;
 pub mod offsets;
 pub mod retry;
+pub mod sink;
 
 pub mod types {
     pub use logdna_client::*;
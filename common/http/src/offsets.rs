@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashMap};
 use std::iter::Iterator;
 
 pub type Offset = (u64, u64);
@@ -50,3 +51,120 @@ impl std::default::Default for OffsetMap {
         Self::new()
     }
 }
+
+/// Identifies a batch registered with an [`OffsetTracker`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BatchId(u64);
+
+/// Tracks the offsets of the batches being sent, the offset of a file is only committed once
+/// every batch registered before it for that file is acknowledged
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    next_id: u64,
+    // The files covered by each pending batch
+    batches: HashMap<u64, Vec<u64>>,
+    // The pending batches of each file in registration order, with their end offset and whether
+    // they were acknowledged. Offsets aren't ordered once a file is truncated in place
+    files: HashMap<u64, BTreeMap<u64, (u64, bool)>>,
+}
+
+impl OffsetTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the offsets of a batch before it's sent
+    pub fn register(&mut self, offsets: &[Offset]) -> BatchId {
+        let id = self.next_id;
+        self.next_id += 1;
+        for (key, offset) in offsets {
+            self.files
+                .entry(*key)
+                .or_default()
+                .insert(id, (*offset, false));
+        }
+        self.batches
+            .insert(id, offsets.iter().map(|(key, _)| *key).collect());
+        BatchId(id)
+    }
+
+    /// Marks a batch as done, whether it was sent or dropped, and returns the offsets that are
+    /// safe to commit
+    pub fn acknowledge(&mut self, batch: BatchId) -> Vec<Offset> {
+        let mut committable = Vec::new();
+        for key in self.batches.remove(&batch.0).unwrap_or_default() {
+            let pending = match self.files.get_mut(&key) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            if let Some((_, acknowledged)) = pending.get_mut(&batch.0) {
+                *acknowledged = true;
+            }
+
+            let mut last = None;
+            while let Some((id, offset, true)) =
+                pending.iter().next().map(|(i, (o, a))| (*i, *o, *a))
+            {
+                pending.remove(&id);
+                last = Some(offset);
+            }
+            if pending.is_empty() {
+                self.files.remove(&key);
+            }
+            if let Some(offset) = last {
+                committable.push((key, offset));
+            }
+        }
+        committable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commits_after_earlier_batches() {
+        let mut tracker = OffsetTracker::new();
+        let first = tracker.register(&[(1, 100), (2, 50)]);
+        let second = tracker.register(&[(1, 200)]);
+        let third = tracker.register(&[(1, 300), (2, 80)]);
+
+        // The first batch is waiting to be retried
+        assert_eq!(tracker.acknowledge(second), vec![]);
+        assert_eq!(tracker.acknowledge(third), vec![]);
+        assert_eq!(tracker.acknowledge(first), vec![(1, 300), (2, 80)]);
+        assert!(tracker.files.is_empty());
+        assert!(tracker.batches.is_empty());
+    }
+
+    #[test]
+    fn batches_keep_their_registration_order() {
+        let mut tracker = OffsetTracker::new();
+        let before = tracker.register(&[(1, 500)]);
+        // The file was truncated in place
+        let after = tracker.register(&[(1, 100)]);
+        assert_eq!(tracker.acknowledge(after), vec![]);
+        assert_eq!(tracker.acknowledge(before), vec![(1, 100)]);
+
+        let empty = tracker.register(&[]);
+        assert_eq!(tracker.acknowledge(empty), vec![]);
+    }
+
+    #[test]
+    fn commits_after_evicted_batches() {
+        let mut tracker = OffsetTracker::new();
+        let evicted = tracker.register(&[(1, 100)]);
+        let sent = tracker.register(&[(1, 200)]);
+        let later = tracker.register(&[(1, 300)]);
+        assert_eq!(tracker.acknowledge(sent), vec![]);
+
+        // The retry file of the first batch was evicted, it won't come back
+        assert_eq!(tracker.acknowledge(evicted), vec![(1, 200)]);
+        assert_eq!(tracker.acknowledge(later), vec![(1, 300)]);
+        assert!(tracker.files.is_empty());
+
+        // Acknowledging a batch twice is harmless
+        assert_eq!(tracker.acknowledge(evicted), vec![]);
+    }
+}
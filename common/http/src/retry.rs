@@ -1,14 +1,19 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::Write;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use async_compat::CompatExt;
-
 use chrono::prelude::Utc;
 use crossbeam::queue::SegQueue;
 
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
 use futures::stream::{self, Stream};
 use futures_timer::Delay;
 
@@ -17,12 +22,11 @@ use metrics::Metrics;
 use serde::Deserialize;
 use thiserror::Error;
 
-use tokio::fs::{read_dir, remove_file, rename, File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::fs::{read, read_dir, remove_file, rename};
 
 use uuid::Uuid;
 
-use crate::offsets::Offset;
+use crate::offsets::{BatchId, Offset};
 use crate::types::body::{IngestBody, IngestBodyBuffer, IntoIngestBodyBuffer};
 
 #[derive(Debug, Error)]
@@ -39,12 +43,61 @@ pub enum Error {
     NonUtf8(std::path::PathBuf),
     #[error("{0} is not a valid file name")]
     InvalidFileName(std::string::String),
+    #[error("{0:?} is not a valid retry file")]
+    InvalidFile(std::path::PathBuf),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+}
+
+/// Identifies the files holding a body and its offsets in the compact format
+const RETRY_FILE_HEADER: &[u8; 4] = b"LDR1";
+
+/// Bounds the disk space used by the bodies waiting to be retried, the oldest files are
+/// removed first when a limit is exceeded
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RetryLimits {
+    /// The maximum total size in bytes of the retry files
+    pub disk_limit: Option<u64>,
+    /// The maximum age of a retry file
+    pub max_age: Option<Duration>,
+}
+
+/// The batches of this run waiting in retry files, and the batches whose retry file was
+/// evicted or couldn't be read
+#[derive(Default)]
+struct RetryBatches {
+    files: Mutex<HashMap<PathBuf, BatchId>>,
+    dropped: SegQueue<BatchId>,
+}
+
+impl RetryBatches {
+    fn insert(&self, path: PathBuf, batch: BatchId) {
+        self.files
+            .lock()
+            .expect("retry batches lock poisoned")
+            .insert(path, batch);
+    }
+
+    fn take(&self, path: &Path) -> Option<BatchId> {
+        self.files
+            .lock()
+            .expect("retry batches lock poisoned")
+            .remove(path)
+    }
+
+    fn drop_file(&self, path: &Path) {
+        if let Some(batch) = self.take(path) {
+            self.dropped.push(batch);
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct Retry {
     directory: PathBuf,
+    limits: RetryLimits,
     waiting: SegQueue<PathBuf>,
+    batches: Arc<RetryBatches>,
     retry_base_delay_secs: i64,
     retry_step_delay: Duration,
 }
@@ -59,21 +112,179 @@ pub struct RetryItem {
     pub body_buffer: IngestBodyBuffer,
     pub offsets: Option<Vec<Offset>>,
     pub path: PathBuf,
+    /// The batch registered when the body was first sent, if it was stored by this run
+    pub batch: Option<BatchId>,
 }
 
 impl RetryItem {
-    fn new(body_buffer: IngestBodyBuffer, offsets: Option<Vec<Offset>>, path: PathBuf) -> Self {
+    fn new(
+        body_buffer: IngestBodyBuffer,
+        offsets: Option<Vec<Offset>>,
+        path: PathBuf,
+        batch: Option<BatchId>,
+    ) -> Self {
         Self {
             body_buffer,
             offsets,
             path,
+            batch,
+        }
+    }
+}
+
+fn file_timestamp(path: &Path) -> Result<i64, Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .ok_or_else(|| Error::NonUtf8(path.to_path_buf()))?;
+
+    file_name
+        .split('_')
+        .next()
+        .and_then(|s| FromStr::from_str(s).ok())
+        .ok_or_else(|| Error::InvalidFileName(file_name.clone()))
+}
+
+fn is_retry_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("retry"))
+}
+
+/// Removes the oldest retry files until the directory is within the limits, returns the
+/// removed files
+async fn evict(directory: &Path, limits: &RetryLimits) -> Result<Vec<PathBuf>, Error> {
+    let mut evicted = Vec::new();
+    if limits.disk_limit.is_none() && limits.max_age.is_none() {
+        return Ok(evicted);
+    }
+
+    let mut retry_files = Vec::new();
+    let mut files = read_dir(directory).await?;
+    while let Some(file) = files.next_entry().await? {
+        let path = file.path();
+        if !is_retry_file(&path) {
+            continue;
+        }
+        let timestamp = match file_timestamp(&path) {
+            Ok(timestamp) => timestamp,
+            Err(e) => {
+                warn!("ignoring retry file: {}", e);
+                continue;
+            }
+        };
+        // The file may have been taken by the retry stream in the meantime
+        if let Ok(metadata) = file.metadata().await {
+            retry_files.push((timestamp, path, metadata.len()));
+        }
+    }
+    retry_files.sort();
+
+    let now = Utc::now().timestamp();
+    let mut total_size: u64 = retry_files.iter().map(|(_, _, size)| size).sum();
+    for (timestamp, path, size) in retry_files {
+        let expired = limits
+            .max_age
+            .map_or(false, |max_age| now - timestamp > max_age.as_secs() as i64);
+        let oversized = limits.disk_limit.map_or(false, |limit| total_size > limit);
+        if !expired && !oversized {
+            break;
         }
+
+        match remove_file(&path).await {
+            Ok(_) => evicted.push(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("unable to remove retry file {:?}: {}", path, e);
+                continue;
+            }
+        }
+        total_size -= size;
+    }
+
+    if !evicted.is_empty() {
+        warn!(
+            "discarded {} retry files to stay within the retry limits",
+            evicted.len()
+        );
+        Metrics::http().add_retry_evictions(evicted.len() as u64);
+    }
+    Ok(evicted)
+}
+
+fn read_u32(data: &mut &[u8]) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    data.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(data: &mut &[u8]) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    data.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Writes the header, the offsets and the gzipped body of a retry file
+fn encode(
+    writer: impl Write,
+    offsets: Option<&[Offset]>,
+    body: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut writer = std::io::BufWriter::new(writer);
+    writer.write_all(RETRY_FILE_HEADER)?;
+    match offsets {
+        Some(offsets) => {
+            writer.write_all(&[1])?;
+            writer.write_all(&(offsets.len() as u32).to_be_bytes())?;
+            for (key, offset) in offsets {
+                writer.write_all(&key.to_be_bytes())?;
+                writer.write_all(&offset.to_be_bytes())?;
+            }
+        }
+        None => writer.write_all(&[0])?,
+    }
+
+    let mut encoder = GzEncoder::new(writer, Compression::fast());
+    encoder.write_all(body)?;
+    encoder.finish()?.flush()
+}
+
+fn decode(path: &Path, mut data: &[u8]) -> Result<(Option<Vec<Offset>>, IngestBody), Error> {
+    // Files written by previous versions of the agent hold the body and offsets as json
+    if data.starts_with(b"{") {
+        let DiskRead { offsets, body } = serde_json::from_slice(data)?;
+        return Ok((offsets, body));
+    }
+
+    let mut header = [0; 4];
+    data.read_exact(&mut header)?;
+    if &header != RETRY_FILE_HEADER {
+        return Err(Error::InvalidFile(path.to_path_buf()));
     }
+
+    let mut has_offsets = [0; 1];
+    data.read_exact(&mut has_offsets)?;
+    let offsets = match has_offsets[0] {
+        0 => None,
+        1 => {
+            let len = read_u32(&mut data)?;
+            let mut offsets = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                offsets.push((read_u64(&mut data)?, read_u64(&mut data)?));
+            }
+            Some(offsets)
+        }
+        _ => return Err(Error::InvalidFile(path.to_path_buf())),
+    };
+
+    let body = serde_json::from_reader(GzDecoder::new(data))?;
+    Ok((offsets, body))
 }
 
 impl Retry {
-    pub fn new(
+    fn new(
         directory: PathBuf,
+        limits: RetryLimits,
+        batches: Arc<RetryBatches>,
         retry_base_delay: Duration,
         retry_step_delay: Duration,
     ) -> Retry {
@@ -81,13 +292,20 @@ impl Retry {
             .unwrap_or_else(|_| panic!("can't create {:#?}", &directory));
         Retry {
             directory,
+            limits,
             waiting: SegQueue::new(),
+            batches,
             retry_base_delay_secs: retry_base_delay.as_secs() as i64,
             retry_step_delay,
         }
     }
 
     async fn fill_waiting(&self) -> Result<(), Error> {
+        // Expired files are removed rather than sent
+        for path in evict(&self.directory, &self.limits).await? {
+            self.batches.drop_file(&path);
+        }
+
         let mut files = read_dir(&self.directory).await?;
         while let Some(file) = files.next_entry().await? {
             let path = file.path();
@@ -95,20 +313,14 @@ impl Retry {
                 continue;
             }
 
-            if path.extension() == Some(std::ffi::OsStr::new("retry")) {
-                let file_name = path
-                    .file_name()
-                    .and_then(|s| s.to_str())
-                    .map(|s| s.to_string())
-                    .ok_or_else(|| Error::NonUtf8(path.clone()))?;
-
-                let timestamp: i64 = file_name
-                    .split('_')
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>()
-                    .get(0)
-                    .and_then(|s| FromStr::from_str(s).ok())
-                    .ok_or_else(|| Error::InvalidFileName(file_name.clone()))?;
+            if is_retry_file(&path) {
+                let timestamp = match file_timestamp(&path) {
+                    Ok(timestamp) => timestamp,
+                    Err(e) => {
+                        warn!("ignoring retry file: {}", e);
+                        continue;
+                    }
+                };
 
                 if Utc::now().timestamp() - timestamp < self.retry_base_delay_secs {
                     continue;
@@ -121,12 +333,9 @@ impl Retry {
     }
 
     async fn read_from_disk(path: &Path) -> Result<(Option<Vec<Offset>>, IngestBody), Error> {
-        let mut file = BufReader::new(File::open(path).await?);
-        let mut data = String::new();
-        file.read_to_string(&mut data).await?;
+        let data = read(path).await?;
         remove_file(&path).await?;
-        let DiskRead { offsets, body } = serde_json::from_str(&data)?;
-        Ok((offsets, body))
+        decode(path, &data)
     }
 
     pub fn into_stream(self) -> impl Stream<Item = Result<RetryItem, Error>> {
@@ -150,20 +359,26 @@ impl Retry {
                 if let Some(path) = state.waiting.pop() {
                     // Step delay
                     Delay::new(state.retry_step_delay).await;
-                    match Retry::read_from_disk(&path).await {
+                    let result = match Retry::read_from_disk(&path).await {
                         Ok((offsets, ingest_body)) => {
                             match IntoIngestBodyBuffer::into(ingest_body).await {
                                 Ok(body_buffer) => {
+                                    let batch = state.batches.take(&path);
                                     return Some((
-                                        Ok(RetryItem::new(body_buffer, offsets, path)),
+                                        Ok(RetryItem::new(body_buffer, offsets, path, batch)),
                                         state,
-                                    ))
+                                    ));
                                 }
-                                Err(e) => return Some((Err(e.into()), state)),
+                                Err(e) => Err(e.into()),
                             }
                         }
-                        Err(e) => return Some((Err(e), state)),
+                        Err(e) => Err(e),
+                    };
+                    // The body won't be sent unless the file is still there to be read again
+                    if !path.exists() {
+                        state.batches.drop_file(&path);
                     }
+                    return Some((result, state));
                 }
             }
         })
@@ -172,16 +387,30 @@ impl Retry {
 
 pub struct RetrySender {
     directory: PathBuf,
+    limits: RetryLimits,
+    batches: Arc<RetryBatches>,
 }
 
 impl RetrySender {
-    pub fn new(directory: PathBuf) -> Self {
-        Self { directory }
+    fn new(directory: PathBuf, limits: RetryLimits, batches: Arc<RetryBatches>) -> Self {
+        Self {
+            directory,
+            limits,
+            batches,
+        }
     }
 
+    /// Takes the batches whose retry file was evicted or couldn't be read, they won't be sent
+    pub fn take_dropped(&self) -> Vec<BatchId> {
+        std::iter::from_fn(|| self.batches.dropped.pop()).collect()
+    }
+
+    /// Writes the body and its offsets to a retry file, the batch is handed back with the body
+    /// when it's read from the file
     pub async fn retry(
         &self,
         offsets: Option<&[Offset]>,
+        batch: Option<BatchId>,
         body: &IngestBodyBuffer,
     ) -> Result<(), Error> {
         Metrics::http().increment_retries();
@@ -193,61 +422,47 @@ impl RetrySender {
         let mut file_name = self.directory.clone();
         file_name.push(format!("{}_{}.retry.partial", fn_ts, fn_uuid));
 
-        let mut file = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(&file_name)
-                .await?,
-        );
+        let mut data = Vec::with_capacity(body.len());
+        futures::io::copy(&mut body.reader(), &mut data).await?;
 
-        // Manually serialize the body and offsets
-        file.write_all(b"{").await?;
-        let mut file = if let Some(offsets) = offsets {
-            file.write_all(b"\"offsets\":").await?;
-
-            // Serde can't write to async_write, so offload this to a threadpool
-            let std_file = tokio::task::spawn_blocking(
-                {
-                    // Get the std::fs::File out of the tokio BufWriter
-                    file.flush().await?;
-                    let mut std_file = std::io::BufWriter::new(file.into_inner().into_std().await);
-                    let offsets = offsets.to_vec();
-                    move || -> Result<std::fs::File, std::io::Error> {
-                        // Serialise the offsets to the std::io::BufWriter
-                        serde_json::to_writer(&mut std_file, &offsets)?;
-                        std_file.flush()?;
-                        Ok(std_file.into_inner()?)
-                    }
-                }
-            ).await.unwrap(/*FIXME handle properly*/)?;
-            let mut file = BufWriter::new(File::from_std(std_file));
-            file.write_all(b",").await?;
-            file
-        } else {
-            file
-        };
-        file.write_all(b"\"body\":").await?;
-        let mut reader = body.reader();
-        let _bytes_written = futures::io::copy(&mut reader, &mut file.compat_mut()).await?;
-        file.write_all(b"}").await?;
-        file.flush().await?;
+        // Compression is cpu bound, so offload this to a threadpool
+        tokio::task::spawn_blocking({
+            let file_name = file_name.clone();
+            let offsets = offsets.map(|offsets| offsets.to_vec());
+            move || encode(std::fs::File::create(file_name)?, offsets.as_deref(), &data)
+        })
+        .await??;
 
         let mut new_file_name = self.directory.clone();
         new_file_name.push(format!("{}_{}.retry", fn_ts, fn_uuid));
+        // The batch is known before the file can be read or evicted
+        if let Some(batch) = batch {
+            self.batches.insert(new_file_name.clone(), batch);
+        }
+        if let Err(e) = rename(file_name, &new_file_name).await {
+            self.batches.take(&new_file_name);
+            return Err(e.into());
+        }
 
-        Ok(rename(file_name, new_file_name).await?)
+        // The body was written, failing to make room for it doesn't fail the retry
+        match evict(&self.directory, &self.limits).await {
+            Ok(evicted) => evicted.iter().for_each(|path| self.batches.drop_file(path)),
+            Err(e) => error!("unable to evict retry files: {}", e),
+        }
+        Ok(())
     }
 }
 
 pub fn retry(
     dir: PathBuf,
+    limits: RetryLimits,
     retry_base_delay: Duration,
     retry_step_delay: Duration,
 ) -> (RetrySender, Retry) {
+    let batches = Arc::new(RetryBatches::default());
     (
-        RetrySender::new(dir.clone()),
-        Retry::new(dir, retry_base_delay, retry_step_delay),
+        RetrySender::new(dir.clone(), limits, batches.clone()),
+        Retry::new(dir, limits, batches, retry_base_delay, retry_step_delay),
     )
 }
 
@@ -289,7 +504,7 @@ mod tests {
             let dir_path = format!("{}/", dir.path().to_str().unwrap());
 
             let (size, lines) = inp;
-            let (retrier, retry_stream) = retry(dir_path.clone().into(), Duration::from_millis(1000), Duration::from_millis(0));
+            let (retrier, retry_stream) = retry(dir_path.clone().into(), RetryLimits::default(), Duration::from_millis(1000), Duration::from_millis(0));
             let (results, retry_results) =
                 tokio_test::block_on({
                     let dir_path = dir_path.clone();
@@ -303,7 +518,7 @@ mod tests {
                         // Retry all the results and assert they come off the stream
                         for (idx, body_offsets) in results.iter().enumerate() {
                             let (body, offsets) = body_offsets.as_ref().unwrap();
-                            retrier.retry(Some(offsets.items_as_ref()), body).await.unwrap();
+                            retrier.retry(Some(offsets.items_as_ref()), None, body).await.unwrap();
                             // Check there are the right number of retry files
                             assert_eq!(std::fs::read_dir(&dir_path).unwrap().count(), idx + 1);
                         }
@@ -370,4 +585,134 @@ mod tests {
             assert_eq!(lines_set, r);
         }
     }
+
+    #[test]
+    fn evicts_oldest_files() {
+        let dir = tempdir().expect("Couldn't create temp dir...");
+        let now = Utc::now().timestamp();
+        for (age, name) in &[(30, "a"), (20, "b"), (10, "c")] {
+            let path = dir.path().join(format!("{}_{}.retry", now - age, name));
+            std::fs::write(path, vec![0; 100]).unwrap();
+        }
+        std::fs::write(dir.path().join("other.txt"), vec![0; 1000]).unwrap();
+        std::fs::write(dir.path().join("invalid.retry"), Vec::new()).unwrap();
+
+        let remaining = || {
+            let mut names = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|f| f.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.ends_with(".retry") && name != "invalid.retry")
+                .map(|name| {
+                    name.split(|c| c == '_' || c == '.')
+                        .nth(1)
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let limits = RetryLimits {
+            disk_limit: Some(250),
+            max_age: None,
+        };
+        let evicted = tokio_test::block_on(evict(dir.path(), &limits)).unwrap();
+        assert_eq!(
+            evicted,
+            vec![dir.path().join(format!("{}_a.retry", now - 30))]
+        );
+        assert_eq!(remaining(), vec!["b", "c"]);
+
+        let limits = RetryLimits {
+            disk_limit: None,
+            max_age: Some(Duration::from_secs(15)),
+        };
+        tokio_test::block_on(evict(dir.path(), &limits)).unwrap();
+        assert_eq!(remaining(), vec!["c"]);
+        // Files with an invalid name are skipped
+        assert!(dir.path().join("invalid.retry").exists());
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_file_names() {
+        let dir = tempdir().expect("Couldn't create temp dir...");
+        let now = Utc::now().timestamp();
+        std::fs::write(dir.path().join("invalid.retry"), Vec::new()).unwrap();
+        for name in &["a", "b"] {
+            let path = dir.path().join(format!("{}_{}.retry", now - 10, name));
+            std::fs::write(path, Vec::new()).unwrap();
+        }
+
+        let (_, retry_stream) = retry(
+            dir.path().to_path_buf(),
+            RetryLimits::default(),
+            Duration::from_secs(1),
+            Duration::from_millis(0),
+        );
+        // The invalid file doesn't keep the other files from being retried
+        retry_stream.fill_waiting().await.unwrap();
+        let mut waiting = Vec::new();
+        while let Some(path) = retry_stream.waiting.pop() {
+            waiting.push(path);
+        }
+        waiting.sort();
+        assert_eq!(
+            waiting,
+            vec![
+                dir.path().join(format!("{}_a.retry", now - 10)),
+                dir.path().join(format!("{}_b.retry", now - 10)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_dropped_batches() {
+        let dir = tempdir().expect("Couldn't create temp dir...");
+        let limits = RetryLimits {
+            disk_limit: Some(1),
+            max_age: None,
+        };
+        let (retrier, _) = retry(
+            dir.path().to_path_buf(),
+            limits,
+            Duration::from_millis(1000),
+            Duration::from_millis(0),
+        );
+        let body: IngestBody =
+            serde_json::from_str(r#"{"lines": [{"line": "hello", "timestamp": 1}]}"#).unwrap();
+        let body = IntoIngestBodyBuffer::into(body).await.unwrap();
+
+        let mut tracker = crate::offsets::OffsetTracker::new();
+        let batch = tracker.register(&[(1, 10)]);
+        // The file exceeds the disk limit on its own and is evicted right away
+        retrier
+            .retry(Some(&[(1, 10)]), Some(batch), &body)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert_eq!(retrier.take_dropped(), vec![batch]);
+        assert_eq!(retrier.take_dropped(), vec![]);
+    }
+
+    #[test]
+    fn decodes_offsets() {
+        let body = br#"{"lines":[{"line":"hello","timestamp":0}]}"#;
+        let mut data = Vec::new();
+        encode(&mut data, Some(&[(1, 10), (2, 20)][..]), body).unwrap();
+        assert!(data.starts_with(RETRY_FILE_HEADER));
+
+        let (offsets, _) = decode(Path::new("test.retry"), &data).unwrap();
+        assert_eq!(offsets, Some(vec![(1, 10), (2, 20)]));
+
+        let mut data = Vec::new();
+        encode(&mut data, None, body).unwrap();
+        let (offsets, _) = decode(Path::new("test.retry"), &data).unwrap();
+        assert_eq!(offsets, None);
+
+        assert!(matches!(
+            decode(Path::new("test.retry"), b"not a retry file"),
+            Err(Error::InvalidFile(_))
+        ));
+    }
 }
@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::fs::{rename, File, OpenOptions};
+use tokio::io::{AsyncWriteExt, Stdout};
+use tokio::sync::Mutex;
+
+use crate::types::body::IngestBodyBuffer;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A local output for the bodies that would otherwise be sent to the ingestion API
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn write(&self, body: &IngestBodyBuffer) -> Result<(), Error>;
+}
+
+/// Serializes the body as a single line of JSON, exactly as it would be sent
+async fn to_ndjson(body: &IngestBodyBuffer) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(body.len() + 1);
+    futures::io::copy(&mut body.reader(), &mut data).await?;
+    // Line breaks in JSON are either escaped or whitespace between tokens
+    data.retain(|b| *b != b'\n' && *b != b'\r');
+    data.push(b'\n');
+    Ok(data)
+}
+
+/// Writes the bodies to the standard output
+#[derive(Debug)]
+pub struct StdoutSink {
+    /// Held while a body is written so that the bodies aren't interleaved
+    stdout: Mutex<Stdout>,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {
+            stdout: Mutex::new(tokio::io::stdout()),
+        }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write(&self, body: &IngestBodyBuffer) -> Result<(), Error> {
+        let data = to_ndjson(body).await?;
+        let mut stdout = self.stdout.lock().await;
+        stdout.write_all(&data).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
+
+/// Appends the bodies to a file, rotated once it reaches the maximum size
+///
+/// Rotated files are renamed with a numeric suffix, `.1` being the most recent one, and the
+/// oldest file is removed when there are more than `max_files` of them.
+pub struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    current: Mutex<Option<(File, u64)>>,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, max_size: u64, max_files: usize) -> Self {
+        Self {
+            path,
+            max_size,
+            max_files,
+            current: Mutex::new(None),
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    async fn open(path: &Path) -> Result<(File, u64), Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok((file, size))
+    }
+
+    async fn rotate(&self) -> Result<(), Error> {
+        if self.max_files == 0 {
+            // There's nothing to keep, start over
+            File::create(&self.path).await?;
+            return Ok(());
+        }
+
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                rename(from, self.rotated_path(index + 1)).await?;
+            }
+        }
+        rename(&self.path, self.rotated_path(1)).await?;
+        Ok(())
+    }
+
+    async fn write_line(&self, data: &[u8]) -> Result<(), Error> {
+        let mut current = self.current.lock().await;
+        if current.is_none() {
+            *current = Some(Self::open(&self.path).await?);
+        }
+
+        let size = current.as_ref().map_or(0, |(_, size)| *size);
+        if size > 0 && size + data.len() as u64 > self.max_size {
+            // Close the file before it's renamed
+            *current = None;
+            self.rotate().await?;
+            *current = Some(Self::open(&self.path).await?);
+        }
+
+        let (file, size) = current.as_mut().expect("file opened above");
+        file.write_all(data).await?;
+        file.flush().await?;
+        *size += data.len() as u64;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn write(&self, body: &IngestBodyBuffer) -> Result<(), Error> {
+        let data = to_ndjson(body).await?;
+        self.write_line(&data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::body::{IngestBody, IntoIngestBodyBuffer};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn writes_bodies_as_ndjson() {
+        let body: IngestBody = serde_json::from_str(
+            r#"{"lines": [{"line": "first\nline", "timestamp": 1}, {"line": "second", "timestamp": 2}]}"#,
+        )
+        .unwrap();
+        let body = IntoIngestBodyBuffer::into(body).await.unwrap();
+
+        let data = to_ndjson(&body).await.unwrap();
+        assert_eq!(data.iter().filter(|b| **b == b'\n').count(), 1);
+        assert_eq!(data.last(), Some(&b'\n'));
+        let written: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(written["lines"][0]["line"], "first\nline");
+        assert_eq!(written["lines"][1]["line"], "second");
+    }
+
+    #[tokio::test]
+    async fn rotates_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("agent.ndjson");
+        let sink = FileSink::new(path, 10, 2);
+
+        for line in &["aaaaaa\n", "bbbbbb\n", "cccccc\n", "dddddd\n"] {
+            sink.write_line(line.as_bytes()).await.unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("agent.ndjson"), "dddddd\n");
+        assert_eq!(read("agent.ndjson.1"), "cccccc\n");
+        assert_eq!(read("agent.ndjson.2"), "bbbbbb\n");
+        assert!(!dir.path().join("agent.ndjson.3").exists());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
+const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
+const KEY_TRANSPORT: &str = "_TRANSPORT";
+const KEY_PRIORITY: &str = "PRIORITY";
+
+/// The syslog priority names, as accepted by `journalctl --priority`
+const PRIORITY_NAMES: [&[&str]; 8] = [
+    &["emerg", "emergency"],
+    &["alert"],
+    &["crit", "critical"],
+    &["err", "error"],
+    &["warning", "warn"],
+    &["notice"],
+    &["info"],
+    &["debug"],
+];
+
+/// Parses a syslog priority from its number or name
+pub fn parse_priority(priority: &str) -> Option<u8> {
+    let priority = priority.trim().to_ascii_lowercase();
+    match priority.parse::<u8>() {
+        Ok(n) if (n as usize) < PRIORITY_NAMES.len() => Some(n),
+        Ok(_) => None,
+        Err(_) => PRIORITY_NAMES
+            .iter()
+            .position(|names| names.contains(&priority.as_str()))
+            .map(|n| n as u8),
+    }
+}
+
+/// Selects journal entries by the values of their fields
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JournalMatch {
+    /// The values of `_SYSTEMD_UNIT`
+    pub units: Vec<String>,
+    /// The values of `SYSLOG_IDENTIFIER`
+    pub identifiers: Vec<String>,
+    /// The values of `_TRANSPORT`
+    pub transports: Vec<String>,
+    /// The values of any other fields
+    pub fields: BTreeMap<String, String>,
+}
+
+impl JournalMatch {
+    fn is_empty(&self) -> bool {
+        self.units.is_empty()
+            && self.identifiers.is_empty()
+            && self.transports.is_empty()
+            && self.fields.is_empty()
+    }
+
+    fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.units
+            .iter()
+            .map(|value| (KEY_SYSTEMD_UNIT, value.as_str()))
+            .chain(
+                self.identifiers
+                    .iter()
+                    .map(|value| (KEY_SYSLOG_IDENTIFIER, value.as_str())),
+            )
+            .chain(
+                self.transports
+                    .iter()
+                    .map(|value| (KEY_TRANSPORT, value.as_str())),
+            )
+            .chain(
+                self.fields
+                    .iter()
+                    .map(|(field, value)| (field.as_str(), value.as_str())),
+            )
+    }
+}
+
+/// Decides which journal entries are sent. An entry is kept when each field of the inclusion
+/// has one of its values, its priority is at most `max_priority` and none of its fields has an
+/// excluded value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JournalFilter {
+    pub include: JournalMatch,
+    pub exclude: JournalMatch,
+    pub max_priority: Option<u8>,
+}
+
+impl JournalFilter {
+    /// The matches applied by journald itself, the values of a field are alternatives and
+    /// all the fields must match, the same as the matches of `journalctl`
+    pub fn matches(&self) -> Vec<(String, String)> {
+        let priorities = self
+            .max_priority
+            .into_iter()
+            .flat_map(|max| (0..=max).map(|priority| priority.to_string()))
+            .map(|priority| (KEY_PRIORITY.to_string(), priority));
+        self.include
+            .pairs()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .chain(priorities)
+            .collect()
+    }
+
+    /// Whether an entry passes the journald matches, for the readers that couldn't apply them.
+    /// `field` returns the value of a field of the entry.
+    pub fn includes(&self, field: impl Fn(&str) -> Option<String>) -> bool {
+        let matches = self.matches();
+        matches.iter().all(|(name, _)| {
+            let value = field(name);
+            matches
+                .iter()
+                .any(|(other, alternative)| other == name && value.as_ref() == Some(alternative))
+        })
+    }
+
+    /// Whether an entry has an excluded value, `field` returns the value of a field of the
+    /// entry. The inclusions are applied through the journald matches.
+    pub fn excludes(&self, field: impl Fn(&str) -> Option<String>) -> bool {
+        if self.exclude.is_empty() {
+            return false;
+        }
+        self.exclude
+            .pairs()
+            .any(|(name, value)| field(name).as_deref() == Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(parse_priority("4"), Some(4));
+        assert_eq!(parse_priority("Warning"), Some(4));
+        assert_eq!(parse_priority("err"), Some(3));
+        assert_eq!(parse_priority("8"), None);
+        assert_eq!(parse_priority("verbose"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = JournalFilter {
+            include: JournalMatch {
+                units: vec!["nginx.service".into(), "sshd.service".into()],
+                transports: vec!["journal".into()],
+                ..JournalMatch::default()
+            },
+            exclude: JournalMatch {
+                identifiers: vec!["CRON".into()],
+                fields: vec![("_COMM".to_string(), "healthcheck".to_string())]
+                    .into_iter()
+                    .collect(),
+                ..JournalMatch::default()
+            },
+            max_priority: Some(1),
+        };
+        let matches = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            filter.matches(),
+            matches(&[
+                ("_SYSTEMD_UNIT", "nginx.service"),
+                ("_SYSTEMD_UNIT", "sshd.service"),
+                ("_TRANSPORT", "journal"),
+                ("PRIORITY", "0"),
+                ("PRIORITY", "1"),
+            ])
+        );
+
+        let record: HashMap<&str, &str> = vec![
+            ("_SYSTEMD_UNIT", "cron.service"),
+            ("SYSLOG_IDENTIFIER", "CRON"),
+        ]
+        .into_iter()
+        .collect();
+        assert!(filter.excludes(|field| record.get(field).map(|v| v.to_string())));
+        let record: HashMap<&str, &str> = vec![("_COMM", "nginx")].into_iter().collect();
+        assert!(!filter.excludes(|field| record.get(field).map(|v| v.to_string())));
+
+        let record: HashMap<&str, &str> = vec![
+            ("_SYSTEMD_UNIT", "sshd.service"),
+            ("_TRANSPORT", "journal"),
+            ("PRIORITY", "1"),
+        ]
+        .into_iter()
+        .collect();
+        assert!(filter.includes(|field| record.get(field).map(|v| v.to_string())));
+        let record: HashMap<&str, &str> = vec![
+            ("_SYSTEMD_UNIT", "sshd.service"),
+            ("_TRANSPORT", "journal"),
+            ("PRIORITY", "4"),
+        ]
+        .into_iter()
+        .collect();
+        assert!(!filter.includes(|field| record.get(field).map(|v| v.to_string())));
+
+        assert!(JournalFilter::default().matches().is_empty());
+        assert!(JournalFilter::default().includes(|_| None));
+        assert!(!JournalFilter::default().excludes(|_| Some("CRON".into())));
+    }
+}
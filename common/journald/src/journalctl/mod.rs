@@ -1,9 +1,10 @@
 mod error;
+use crate::filter::JournalFilter;
 use crate::journalctl::error::JournalCtlError;
+use crate::record::{entry_line, KEY_MESSAGE};
+use crate::{JournalCursor, JournalLine, JournalOrigin};
 use bytes::{Buf, BytesMut};
 
-use http::types::body::LineBuilder;
-
 use combine::{
     error::{ParseError, StreamError},
     many1, none_of, one_of,
@@ -18,19 +19,18 @@ use combine::{
     token, Parser,
 };
 
+use futures::stream::select_all;
 use futures::{Stream, StreamExt};
 use log::{info, warn};
 use tokio_util::codec::{Decoder, FramedRead};
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::process::Stdio;
 
 const JOURNALCTL_CMD: &str = "journalctl";
-const KEY_MESSAGE: &str = "MESSAGE";
-const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
-const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
-const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
-const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
+const KEY_CURSOR: &str = "__CURSOR";
+const KEY_REALTIME_TIMESTAMP: &str = "__REALTIME_TIMESTAMP";
 
 pub struct JournaldExportDecoder {
     state: AnyPartialState,
@@ -152,35 +152,49 @@ where
 impl JournaldExportDecoder {
     fn process_default_record(
         record: &JournalRecord,
-    ) -> Result<Option<LineBuilder>, JournalCtlError> {
-        let message = match record.get(KEY_MESSAGE) {
-            Some(message) => message,
+        origin: &str,
+        filter: &JournalFilter,
+        meta_fields: &[String],
+    ) -> Result<Option<JournalLine>, JournalCtlError> {
+        if filter.excludes(|field| record.get(field).map(FieldValue::to_string_lossy)) {
+            return Ok(None);
+        }
+
+        let line = match entry_line(
+            |field| record.get(field).map(FieldValue::to_string_lossy),
+            meta_fields,
+            origin,
+        ) {
+            Some(line) => line,
             None => {
                 warn!("unable to get message of journald record");
                 return Err(JournalCtlError::RecordMissingField(KEY_MESSAGE.into()));
             }
         };
 
-        let default_app = String::from(DEFAULT_APP);
-        let app = record
-            .get(KEY_CONTAINER_NAME)
-            .map(FieldValue::to_string_lossy)
-            .or_else(|| {
-                record
-                    .get(KEY_SYSTEMD_UNIT)
-                    .map(FieldValue::to_string_lossy)
-            })
-            .or_else(|| {
-                record
-                    .get(KEY_SYSLOG_IDENTIFIER)
-                    .map(FieldValue::to_string_lossy)
-            })
-            .unwrap_or(default_app);
-
         //Metrics::journald().add_bytes(message.len());
-        Ok(Some(
-            LineBuilder::new().line(message.to_string_lossy()).file(app),
-        ))
+        Ok(Some(JournalLine {
+            line,
+            timestamp: Self::timestamp(record),
+            cursor: Self::cursor(record, origin),
+        }))
+    }
+
+    /// The realtime timestamp of the entry, in seconds
+    fn timestamp(record: &JournalRecord) -> Option<i64> {
+        record
+            .get(KEY_REALTIME_TIMESTAMP)?
+            .to_string_lossy()
+            .parse::<i64>()
+            .ok()
+            .map(|micros| micros / 1_000_000)
+    }
+
+    fn cursor(record: &JournalRecord, origin: &str) -> Option<JournalCursor> {
+        record.get(KEY_CURSOR).map(|cursor| JournalCursor {
+            source: origin.into(),
+            cursor: cursor.to_string_lossy(),
+        })
     }
 }
 
@@ -257,15 +271,56 @@ impl Decoder for JournaldExportDecoder {
     }
 }
 
-pub fn create_journalctl_source() -> Result<impl Stream<Item = LineBuilder>, std::io::Error> {
-    let mut journalctl_process = tokio::process::Command::new(JOURNALCTL_CMD)
+/// The journalctl arguments reading the `origin` journal from the entry after `cursor`, when
+/// there is one, or from the current boot of the machine or the end of the other journals
+fn journalctl_args(origin: &JournalOrigin, cursor: Option<&str>) -> Vec<String> {
+    let mut args = match origin {
+        JournalOrigin::Local { merge: true } => vec!["--merge".into()],
+        JournalOrigin::Local { merge: false } => Vec::new(),
+        JournalOrigin::Directory(path) => vec![format!("--directory={}", path.display())],
+        JournalOrigin::Files(paths) => paths
+            .iter()
+            .map(|path| format!("--file={}", path.display()))
+            .collect(),
+        JournalOrigin::Namespace(namespace) => vec![format!("--namespace={}", namespace)],
+    };
+    match (cursor, origin) {
+        // Resume across boots, so that the entries of the previous one are not lost
+        (Some(cursor), _) => args.push(format!("--after-cursor={}", cursor)),
+        // The boots of the other journals are not the one of the machine
+        (None, JournalOrigin::Directory(_)) | (None, JournalOrigin::Files(_)) => {
+            args.push("--lines=0".into())
+        }
         // The current boot
-        .arg("-b")
+        (None, _) => args.push("-b".into()),
+    }
+    args
+}
+
+/// Follows the `origin` journal with its own journalctl process
+fn journalctl_stream(
+    origin: &JournalOrigin,
+    cursor: Option<&str>,
+    filter: &JournalFilter,
+    meta_fields: &[String],
+) -> Result<impl Stream<Item = JournalLine>, std::io::Error> {
+    let name = origin.name();
+    if let Some(cursor) = cursor {
+        info!("Resuming journalctl for {} after cursor {}", name, cursor);
+    }
+    let mut journalctl_process = tokio::process::Command::new(JOURNALCTL_CMD)
+        .args(journalctl_args(origin, cursor))
         // follow
         .arg("-f")
         // set export format
         .arg("-o")
         .arg("export")
+        .args(
+            filter
+                .matches()
+                .into_iter()
+                .map(|(field, value)| format!("{}={}", field, value)),
+        )
         .stdout(Stdio::piped())
         .spawn()?;
 
@@ -278,29 +333,56 @@ pub fn create_journalctl_source() -> Result<impl Stream<Item = LineBuilder>, std
         )
     })?;
 
-    info!("Listening to journalctl");
+    info!("Listening to journalctl for {}", name);
+    let filter = filter.clone();
+    let meta_fields = meta_fields.to_vec();
     Ok(
-        FramedRead::new(journalctl_stdout, decoder).filter_map(|r| async move {
-            match r {
-                Ok(record) => match JournaldExportDecoder::process_default_record(&record) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        warn!("Encountered error in journald record: {}", e);
-                        None
-                    }
-                },
+        FramedRead::new(journalctl_stdout, decoder).filter_map(move |r| {
+            let line = match r {
+                Ok(record) => JournaldExportDecoder::process_default_record(
+                    &record,
+                    &name,
+                    &filter,
+                    &meta_fields,
+                )
+                .unwrap_or_else(|e| {
+                    warn!("Encountered error in journald record: {}", e);
+                    None
+                }),
                 Err(e) => {
                     warn!("Encountered error while parsing journalctl output: {}", e);
                     None
                 }
-            }
+            };
+            futures::future::ready(line)
         }),
     )
 }
 
+/// Follows each of the `origins` journals from the entry after its cursor in `cursors`. The
+/// inclusions of the filter are passed to journalctl as matches, and the `meta_fields` of the
+/// entries are put into the meta of their lines.
+pub fn create_journalctl_source(
+    origins: &[JournalOrigin],
+    cursors: &HashMap<String, String>,
+    filter: &JournalFilter,
+    meta_fields: &[String],
+) -> Result<impl Stream<Item = JournalLine>, std::io::Error> {
+    let streams = origins
+        .iter()
+        .map(|origin| {
+            let cursor = cursors.get(&origin.name()).map(String::as_str);
+            journalctl_stream(origin, cursor, filter, meta_fields)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(select_all(streams))
+}
+
 #[cfg(test)]
 mod test {
-    use super::JournaldExportDecoder;
+    use super::{journalctl_args, FieldValue, JournaldExportDecoder};
+    use crate::filter::{JournalFilter, JournalMatch};
+    use crate::{JournalCursor, JournalOrigin};
     use futures::prelude::*;
     use partial_io::{PartialAsyncRead, PartialOp};
     use std::io::Cursor;
@@ -491,4 +573,96 @@ _SOURCE_REALTIME_TIMESTAMP=1623323451155218
             String::new()
         );
     }
+
+    #[test]
+    fn test_cursor() {
+        let mut record = std::collections::HashMap::new();
+        assert_eq!(JournaldExportDecoder::cursor(&record, "local"), None);
+        record.insert(
+            "__CURSOR".to_string(),
+            FieldValue::Utf8("s=6c8ff0351a1d415b955bb9fc81e5977c;i=1".into()),
+        );
+        assert_eq!(
+            JournaldExportDecoder::cursor(&record, "local"),
+            Some(JournalCursor {
+                source: "local".into(),
+                cursor: "s=6c8ff0351a1d415b955bb9fc81e5977c;i=1".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let mut record = std::collections::HashMap::new();
+        record.insert(
+            "__REALTIME_TIMESTAMP".to_string(),
+            FieldValue::Utf8("1622124170808359".into()),
+        );
+        assert_eq!(JournaldExportDecoder::timestamp(&record), Some(1622124170));
+    }
+
+    #[test]
+    fn test_exclude_records() {
+        let record = vec![
+            ("MESSAGE", "pam_unix(cron:session): session opened"),
+            ("SYSLOG_IDENTIFIER", "CRON"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), FieldValue::Utf8(v.to_string())))
+        .collect();
+        let filter = JournalFilter {
+            exclude: JournalMatch {
+                identifiers: vec!["CRON".into()],
+                ..JournalMatch::default()
+            },
+            ..JournalFilter::default()
+        };
+        assert!(matches!(
+            JournaldExportDecoder::process_default_record(&record, "local", &filter, &[]),
+            Ok(None)
+        ));
+        assert!(matches!(
+            JournaldExportDecoder::process_default_record(
+                &record,
+                "local",
+                &JournalFilter::default(),
+                &[]
+            ),
+            Ok(Some(_))
+        ));
+    }
+
+    #[test]
+    fn test_journalctl_args() {
+        assert_eq!(
+            journalctl_args(&JournalOrigin::Local { merge: false }, None),
+            vec!["-b".to_string()]
+        );
+        assert_eq!(
+            journalctl_args(&JournalOrigin::Local { merge: true }, Some("s=1;i=2")),
+            vec!["--merge".to_string(), "--after-cursor=s=1;i=2".to_string()]
+        );
+        assert_eq!(
+            journalctl_args(&JournalOrigin::Directory("/host/journal".into()), None),
+            vec![
+                "--directory=/host/journal".to_string(),
+                "--lines=0".to_string()
+            ]
+        );
+        assert_eq!(
+            journalctl_args(
+                &JournalOrigin::Files(vec!["/a.journal".into(), "/b.journal".into()]),
+                None
+            ),
+            vec![
+                "--file=/a.journal".to_string(),
+                "--file=/b.journal".to_string(),
+                "--lines=0".to_string()
+            ]
+        );
+        assert_eq!(
+            journalctl_args(&JournalOrigin::Namespace("audit".into()), None),
+            vec!["--namespace=audit".to_string(), "-b".to_string()]
+        );
+    }
 }
@@ -1,4 +1,123 @@
 #[cfg(feature = "libjournald")]
 pub mod libjournald;
 
+pub mod filter;
 pub mod journalctl;
+mod record;
+
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use std::path::PathBuf;
+
+/// A journal the entries are read from
+#[derive(Clone, Debug, PartialEq)]
+pub enum JournalOrigin {
+    /// The journals of the machine, merged with the remote journals when `merge` is set
+    Local {
+        merge: bool,
+    },
+    Directory(PathBuf),
+    Files(Vec<PathBuf>),
+    /// The journal of a systemd namespace
+    Namespace(String),
+}
+
+impl JournalOrigin {
+    /// The name of the origin, the lines are labeled with it and their cursors stored under it
+    pub fn name(&self) -> String {
+        match self {
+            JournalOrigin::Local { .. } => "local".into(),
+            JournalOrigin::Directory(path) => path.to_string_lossy().into_owned(),
+            JournalOrigin::Files(paths) => paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(","),
+            JournalOrigin::Namespace(namespace) => format!("namespace:{}", namespace),
+        }
+    }
+}
+
+/// The journals to read: each directory of `paths`, its files together and each namespace.
+/// The journals of the machine are read when there are neither paths nor namespaces.
+pub fn journal_origins(
+    paths: &[PathBuf],
+    namespaces: &[String],
+    merge: bool,
+) -> Vec<JournalOrigin> {
+    let mut origins = Vec::new();
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            origins.push(JournalOrigin::Directory(path.to_path_buf()));
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            warn!("journald path {:?} does not exist", path);
+            continue;
+        }
+
+        info!("monitoring journald path {:?}", path);
+    }
+    if !files.is_empty() {
+        origins.push(JournalOrigin::Files(files));
+    }
+    origins.extend(
+        namespaces
+            .iter()
+            .map(|namespace| JournalOrigin::Namespace(namespace.clone())),
+    );
+    if paths.is_empty() && namespaces.is_empty() {
+        origins.push(JournalOrigin::Local { merge });
+    }
+    origins
+}
+
+/// The position of a journal entry, the sources resume after the last stored one
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalCursor {
+    /// The journal the entry was read from, a cursor is stored for each
+    pub source: String,
+    pub cursor: String,
+}
+
+/// A line read from the journal, with the parts of its entry that the line builder can't hold
+pub struct JournalLine {
+    pub line: LineBuilder,
+    /// The time the entry was received by journald, in seconds since the epoch
+    pub timestamp: Option<i64>,
+    pub cursor: Option<JournalCursor>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_origins() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("system.journal");
+        std::fs::write(&file, "").unwrap();
+        let paths = vec![
+            dir.path().to_path_buf(),
+            file.clone(),
+            dir.path().join("missing"),
+        ];
+        let origins = journal_origins(&paths, &["audit".to_string()], true);
+        assert_eq!(
+            origins,
+            vec![
+                JournalOrigin::Directory(dir.path().to_path_buf()),
+                JournalOrigin::Files(vec![file.clone()]),
+                JournalOrigin::Namespace("audit".into()),
+            ]
+        );
+        assert_eq!(origins[1].name(), file.to_string_lossy());
+        assert_eq!(origins[2].name(), "namespace:audit");
+
+        assert_eq!(
+            journal_origins(&[], &[], true),
+            vec![JournalOrigin::Local { merge: true }]
+        );
+    }
+}
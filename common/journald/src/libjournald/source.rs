@@ -1,32 +1,31 @@
-use crate::libjournald::stream::{Path, Stream};
+use crate::filter::JournalFilter;
+use crate::libjournald::stream::{namespace_directory, Stream};
+use crate::{JournalLine, JournalOrigin};
 use futures::stream::{select_all, SelectAll, Stream as FutureStream};
-use http::types::body::LineBuilder;
-use log::{info, warn};
-use std::path::PathBuf;
+use log::warn;
+use std::collections::HashMap;
 
-pub fn create_source(paths: &[PathBuf]) -> impl FutureStream<Item = LineBuilder> {
-    let mut journal_files: Vec<PathBuf> = Vec::new();
-    let mut journal_directories: Vec<PathBuf> = Vec::new();
-    for path in paths {
-        if path.is_dir() {
-            journal_directories.push(path.to_path_buf());
-        } else if path.is_file() {
-            journal_files.push(path.to_path_buf());
-        } else {
-            warn!("journald path {:?} does not exist", path);
-            continue;
-        }
-
-        info!("monitoring journald path {:?}", path);
-    }
-
-    let mut streams: Vec<Stream> = journal_directories
-        .into_iter()
-        .map(|dir| Stream::new(Path::Directory(dir)))
+/// Reads the `origins` journals, each resuming after its cursor in `cursors` when there is one
+pub fn create_source(
+    origins: &[JournalOrigin],
+    cursors: &HashMap<String, String>,
+    filter: &JournalFilter,
+    meta_fields: &[String],
+) -> impl FutureStream<Item = JournalLine> {
+    let streams: Vec<Stream> = origins
+        .iter()
+        .filter(|origin| match origin {
+            JournalOrigin::Namespace(namespace) if namespace_directory(namespace).is_none() => {
+                warn!("journald namespace {} does not exist", namespace);
+                false
+            }
+            _ => true,
+        })
+        .map(|origin| {
+            let cursor = cursors.get(&origin.name()).cloned();
+            Stream::new(origin.clone(), cursor, filter.clone(), meta_fields.to_vec())
+        })
         .collect();
-    if !journal_files.is_empty() {
-        streams.push(Stream::new(Path::Files(journal_files)));
-    }
 
     let combined_stream: SelectAll<<Vec<Stream> as IntoIterator>::Item> = select_all(streams);
     combined_stream
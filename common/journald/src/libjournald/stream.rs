@@ -1,4 +1,7 @@
+use crate::filter::JournalFilter;
 use crate::libjournald::error::JournalError;
+use crate::record::{entry_line, KEY_MESSAGE};
+use crate::{JournalCursor, JournalLine, JournalOrigin};
 use futures::{channel::oneshot, stream::Stream as FutureStream};
 use http::types::body::LineBuilder;
 use log::{info, warn};
@@ -14,22 +17,30 @@ use std::{
     },
     task::{Context, Poll, Waker},
     thread::{self, JoinHandle},
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use systemd::journal::{Journal, JournalFiles, JournalRecord, JournalSeek};
 
-const KEY_MESSAGE: &str = "MESSAGE";
-const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
-const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
-const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
-const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
-
 static WARN_INTERVAL: Duration = Duration::from_secs(300);
 
-#[derive(Clone)]
-pub enum Path {
-    Directory(PathBuf),
-    Files(Vec<PathBuf>),
+/// The directories of the persistent and volatile journals, the journals of the namespaces
+/// are in their `<machine-id>.<namespace>` subdirectories
+const JOURNAL_DIRECTORIES: [&str; 2] = ["/var/log/journal", "/run/log/journal"];
+
+/// The directory of the journal of a namespace, the persistent one when both exist
+pub(crate) fn namespace_directory(namespace: &str) -> Option<PathBuf> {
+    let suffix = format!(".{}", namespace);
+    JOURNAL_DIRECTORIES
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .map_or(false, |name| name.to_string_lossy().ends_with(&suffix))
+        })
 }
 
 struct SharedState {
@@ -38,19 +49,33 @@ struct SharedState {
 
 pub struct Stream {
     thread: Option<JoinHandle<()>>,
-    receiver: Option<Receiver<LineBuilder>>,
+    receiver: Option<Receiver<JournalLine>>,
     shared_state: Arc<Mutex<SharedState>>,
-    path: Path,
+    origin: JournalOrigin,
+    // The cursor of the last entry read, a restarted thread resumes after it
+    cursor: Option<String>,
+    filter: JournalFilter,
+    meta_fields: Vec<String>,
     thread_stop_chan: Option<oneshot::Sender<()>>,
 }
 
 impl Stream {
-    pub fn new(path: Path) -> Self {
+    /// Reads the entries of the `origin` journal kept by the filter, from the entry after
+    /// `cursor` or from its tail. The `meta_fields` of the entries are put into the meta.
+    pub fn new(
+        origin: JournalOrigin,
+        cursor: Option<String>,
+        filter: JournalFilter,
+        meta_fields: Vec<String>,
+    ) -> Self {
         let mut stream = Self {
             thread: None,
             receiver: None,
             shared_state: Arc::new(Mutex::new(SharedState { waker: None })),
-            path,
+            origin,
+            cursor,
+            filter,
+            meta_fields,
             thread_stop_chan: None,
         };
 
@@ -66,9 +91,12 @@ impl Stream {
 
         let (sender, receiver) = sync_channel(100);
         let thread_shared_state = self.shared_state.clone();
-        let path = self.path.clone();
+        let origin = self.origin.clone();
+        let cursor = self.cursor.clone();
+        let filter = self.filter.clone();
+        let meta_fields = self.meta_fields.clone();
         let thread = thread::spawn(move || {
-            let mut journal = Reader::new(path);
+            let mut journal = Reader::new(origin, cursor, filter, meta_fields);
 
             let call_waker = || {
                 let mut shared_state = match thread_shared_state.lock() {
@@ -145,7 +173,7 @@ impl Stream {
 }
 
 impl FutureStream for Stream {
-    type Item = LineBuilder;
+    type Item = JournalLine;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let mut self_ = self.as_mut();
@@ -154,6 +182,9 @@ impl FutureStream for Stream {
             match receiver.try_recv() {
                 // TODO: Find a way to reuse vectors or just generally make this more efficient
                 Ok(line) => {
+                    if let Some(cursor) = line.cursor.as_ref() {
+                        self_.cursor = Some(cursor.cursor.clone());
+                    }
                     return Poll::Ready(Some(line));
                 }
                 Err(TryRecvError::Disconnected) => {
@@ -184,43 +215,128 @@ impl Drop for Stream {
 
 struct Reader {
     reader: Journal,
+    source: String,
+    filter: JournalFilter,
+    meta_fields: Vec<String>,
+    // Set while catching up from a cursor, when the entries are expected to be old
+    resuming: bool,
+    // The entry read after seeking to a cursor that turned out not to be the cursor's own
+    pending: Option<JournalRecord>,
+    // Whether journald applies the inclusions of the filter
+    matched: bool,
     last_warn: Instant,
 }
 
 impl Reader {
-    fn new(path: Path) -> Self {
-        let mut reader = match path {
-            Path::Directory(path) => Journal::open_directory(&path, JournalFiles::All, false)
-                .expect("Could not open journald reader for directory"),
-            Path::Files(paths) => {
+    fn new(
+        origin: JournalOrigin,
+        cursor: Option<String>,
+        filter: JournalFilter,
+        meta_fields: Vec<String>,
+    ) -> Self {
+        let source = origin.name();
+        let mut reader = match origin {
+            JournalOrigin::Local { merge } => Journal::open(JournalFiles::All, false, !merge)
+                .expect("Could not open journald reader"),
+            JournalOrigin::Directory(path) => {
+                Journal::open_directory(&path, JournalFiles::All, false)
+                    .expect("Could not open journald reader for directory")
+            }
+            JournalOrigin::Files(paths) => {
                 let paths: Vec<&std::path::Path> = paths.iter().map(PathBuf::as_path).collect();
                 Journal::open_files(&paths).expect("Could not open journald reader for paths")
             }
+            JournalOrigin::Namespace(namespace) => {
+                let path = namespace_directory(&namespace)
+                    .expect("Could not find the journald directory of namespace");
+                Journal::open_directory(&path, JournalFiles::All, false)
+                    .expect("Could not open journald reader for namespace")
+            }
+        };
+        // The entries are filtered by the reader when journald can't apply the matches
+        let mut matched = true;
+        for (field, value) in filter.matches() {
+            if let Err(e) = reader.match_add(&field, value) {
+                warn!(
+                    "unable to add journald match on {}, filtering the entries instead: {}",
+                    field, e
+                );
+                matched = false;
+                break;
+            }
+        }
+        if !matched {
+            if let Err(e) = reader.match_flush() {
+                warn!("unable to remove the journald matches: {}", e);
+            }
+        }
+
+        // Seeking to a cursor positions the reader on its entry, which was already sent, or on
+        // the closest entry when it's gone, which is kept to be sent first
+        let mut pending = None;
+        let resuming = match cursor {
+            Some(cursor) => match reader.seek(JournalSeek::Cursor {
+                cursor: cursor.clone(),
+            }) {
+                Ok(_) => {
+                    info!("Resuming journal {} after cursor {}", source, cursor);
+                    match reader.next_entry() {
+                        Ok(Some(record)) => {
+                            if !reader.test_cursor(cursor.as_str()).unwrap_or(false) {
+                                pending = Some(record);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => warn!(
+                            "unable to read the journald entry of cursor {}: {}",
+                            cursor, e
+                        ),
+                    }
+                    true
+                }
+                Err(e) => {
+                    warn!("unable to seek to journald cursor {}: {}", cursor, e);
+                    false
+                }
+            },
+            None => false,
         };
-        reader
-            .seek(JournalSeek::Tail)
-            .expect("Could not seek to tail of journald logs");
+        if !resuming {
+            reader
+                .seek(JournalSeek::Tail)
+                .expect("Could not seek to tail of journald logs");
+        }
 
         Self {
             reader,
+            source,
+            filter,
+            meta_fields,
+            resuming,
+            pending,
+            matched,
             last_warn: Instant::now(),
         }
     }
 
-    fn process_next_record(&mut self) -> Result<Option<LineBuilder>, JournalError> {
-        let record = match self.reader.next_entry() {
-            Ok(Some(record)) => record,
-            Ok(None) => return Ok(None),
-            Err(e) => return Err(JournalError::BadRead(e)),
+    fn process_next_record(&mut self) -> Result<Option<JournalLine>, JournalError> {
+        let record = match self.pending.take() {
+            Some(record) => record,
+            None => match self.reader.next_entry() {
+                Ok(Some(record)) => record,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(JournalError::BadRead(e)),
+            },
         };
 
-        match self
-            .reader
-            .timestamp()
-            .ok()
-            .map(|timestamp| SystemTime::now().duration_since(timestamp).ok())
-            .flatten()
-        {
+        let timestamp = self.reader.timestamp().ok();
+        match timestamp.and_then(|timestamp| SystemTime::now().duration_since(timestamp).ok()) {
+            // The entries after the stored cursor are sent however old they are
+            Some(duration) if self.resuming => {
+                if duration < Duration::from_secs(30) {
+                    self.resuming = false;
+                }
+            }
             Some(duration) => {
                 // Reject any records with a timestamp older than 30 seconds
                 if duration >= Duration::from_secs(30) {
@@ -238,32 +354,54 @@ impl Reader {
             None => {
                 warn!("Unable to read timestamp associated with journald record");
             }
-        } //TODO: Actually bake the timestamp into the outgoing line
+        }
 
-        self.process_default_record(&record)
+        let line = match self.process_default_record(&record)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let cursor = match self.reader.cursor() {
+            Ok(cursor) => Some(JournalCursor {
+                source: self.source.clone(),
+                cursor,
+            }),
+            Err(e) => {
+                warn!("unable to read the cursor of journald record: {}", e);
+                None
+            }
+        };
+        Ok(Some(JournalLine {
+            line,
+            timestamp: timestamp
+                .and_then(|timestamp| timestamp.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_secs() as i64),
+            cursor,
+        }))
     }
 
     fn process_default_record(
         &self,
         record: &JournalRecord,
     ) -> Result<Option<LineBuilder>, JournalError> {
-        let message = match record.get(KEY_MESSAGE) {
-            Some(message) => message,
+        let value = |field: &str| record.get(field).cloned();
+        if (!self.matched && !self.filter.includes(value)) || self.filter.excludes(value) {
+            return Ok(None);
+        }
+
+        let line = match entry_line(
+            |field| record.get(field).cloned(),
+            &self.meta_fields,
+            &self.source,
+        ) {
+            Some(line) => line,
             None => {
                 warn!("unable to get message of journald record");
                 return Err(JournalError::RecordMissingField(KEY_MESSAGE.into()));
             }
         };
 
-        let default_app = String::from(DEFAULT_APP);
-        let app = record
-            .get(KEY_CONTAINER_NAME)
-            .or_else(|| record.get(KEY_SYSTEMD_UNIT))
-            .or_else(|| record.get(KEY_SYSLOG_IDENTIFIER))
-            .unwrap_or(&default_app);
-
-        Metrics::journald().add_bytes(message.len());
-        Ok(Some(LineBuilder::new().line(message).file(app)))
+        Metrics::journald().add_bytes(line.line.as_ref().map_or(0, String::len));
+        Ok(Some(line))
     }
 }
 
@@ -286,10 +424,16 @@ mod tests {
     async fn reader_gets_new_logs() {
         journal::print(1, "Reader got the correct line!");
         sleep(Duration::from_millis(50));
-        let mut reader = Reader::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let mut reader = Reader::new(
+            JournalOrigin::Directory(JOURNALD_LOG_PATH.into()),
+            None,
+            JournalFilter::default(),
+            Vec::new(),
+        );
 
         let record_status = reader.process_next_record();
-        if let Ok(Some(line)) = record_status {
+        if let Ok(Some(JournalLine { line, cursor, .. })) = record_status {
+            assert!(cursor.is_some());
             assert!(line.line.is_some());
             if let Some(line_str) = line.line {
                 assert_eq!(line_str, "Reader got the correct line!");
@@ -299,12 +443,54 @@ mod tests {
         assert!(matches!(reader.process_next_record(), Ok(None)));
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn reader_resumes_after_cursor() {
+        journal::print(1, "Reader sent this line before stopping");
+        sleep(Duration::from_millis(50));
+        let mut reader = Reader::new(
+            JournalOrigin::Directory(JOURNALD_LOG_PATH.into()),
+            None,
+            JournalFilter::default(),
+            Vec::new(),
+        );
+        let cursor = match reader.process_next_record() {
+            Ok(Some(JournalLine {
+                cursor: Some(cursor),
+                ..
+            })) => cursor.cursor,
+            _ => return,
+        };
+
+        journal::print(1, "Reader resumed on this line");
+        sleep(Duration::from_millis(50));
+        let mut reader = Reader::new(
+            JournalOrigin::Directory(JOURNALD_LOG_PATH.into()),
+            Some(cursor),
+            JournalFilter::default(),
+            Vec::new(),
+        );
+
+        // The entry of the cursor isn't sent again
+        match reader.process_next_record() {
+            Ok(Some(JournalLine { line, .. })) => {
+                assert_eq!(line.line.as_deref(), Some("Reader resumed on this line"))
+            }
+            _ => panic!("should have read the entry after the cursor"),
+        }
+    }
+
     #[tokio::test]
     #[serial]
     async fn stream_gets_new_logs() {
         journal::print(1, "Reader got the correct line 1!");
         sleep(Duration::from_millis(50));
-        let mut stream = Stream::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let mut stream = Stream::new(
+            JournalOrigin::Directory(JOURNALD_LOG_PATH.into()),
+            None,
+            JournalFilter::default(),
+            Vec::new(),
+        );
         sleep(Duration::from_millis(50));
         journal::print(1, "Reader got the correct line 2!");
 
@@ -315,7 +501,7 @@ mod tests {
             Ok(None) => {
                 panic!("expected to get a line from journald stream");
             }
-            Ok(Some(batch)) => batch,
+            Ok(Some(JournalLine { line, .. })) => line,
         };
 
         assert!(first_line.line.is_some());
@@ -330,7 +516,7 @@ mod tests {
             Ok(None) => {
                 panic!("expected to get a line from journald stream");
             }
-            Ok(Some(batch)) => batch,
+            Ok(Some(JournalLine { line, .. })) => line,
         };
 
         assert!(second_line.line.is_some());
@@ -0,0 +1,112 @@
+use http::types::body::{KeyValueMap, LineBuilder, LineMetaMut};
+use log::warn;
+use serde_json::{Map, Value};
+
+pub(crate) const KEY_MESSAGE: &str = "MESSAGE";
+const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
+const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
+const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
+const KEY_PRIORITY: &str = "PRIORITY";
+const KEY_HOSTNAME: &str = "_HOSTNAME";
+const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
+/// The label holding the name of the journal of the entry
+const ORIGIN_LABEL: &str = "journal";
+
+/// The levels of the syslog priorities, the same names as the syslog source uses
+const LEVELS: [&str; 8] = [
+    "EMERGENCY",
+    "ALERT",
+    "CRITICAL",
+    "ERROR",
+    "WARNING",
+    "NOTICE",
+    "INFO",
+    "DEBUG",
+];
+
+/// Maps a journal entry to a line, `field` returns the value of a field of the entry.
+///
+/// The app is the container name, the systemd unit or the syslog identifier, the level comes
+/// from the priority and the host from the hostname. The `meta_fields` found in the entry are
+/// put into the meta and the line is labeled with the `origin` journal. Returns None when the
+/// entry has no message.
+pub(crate) fn entry_line(
+    field: impl Fn(&str) -> Option<String>,
+    meta_fields: &[String],
+    origin: &str,
+) -> Option<LineBuilder> {
+    let message = field(KEY_MESSAGE)?;
+    let app = field(KEY_CONTAINER_NAME)
+        .or_else(|| field(KEY_SYSTEMD_UNIT))
+        .or_else(|| field(KEY_SYSLOG_IDENTIFIER))
+        .unwrap_or_else(|| DEFAULT_APP.into());
+
+    let mut line = LineBuilder::new().line(message).file(app);
+    if let Some(level) = field(KEY_PRIORITY)
+        .and_then(|priority| priority.trim().parse::<usize>().ok())
+        .and_then(|priority| LEVELS.get(priority))
+    {
+        line = line.level(*level);
+    }
+    if let Some(host) = field(KEY_HOSTNAME) {
+        line = line.host(host);
+    }
+    let meta: Map<String, Value> = meta_fields
+        .iter()
+        .filter_map(|name| Some((name.clone(), Value::String(field(name)?))))
+        .collect();
+    if !meta.is_empty() {
+        if let Err(e) = line.set_meta(Value::Object(meta)) {
+            warn!("unable to set the journald entry meta: {:?}", e);
+        }
+    }
+    if let Err(e) = line.set_labels(KeyValueMap::new().add(ORIGIN_LABEL, origin)) {
+        warn!("unable to set the journald entry labels: {:?}", e);
+    }
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineMeta;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_entry_line() {
+        let entry: HashMap<&str, &str> = vec![
+            ("MESSAGE", "Started Session 1"),
+            ("PRIORITY", "3"),
+            ("_HOSTNAME", "node-1"),
+            ("_SYSTEMD_UNIT", "session-1.scope"),
+            ("SYSLOG_IDENTIFIER", "systemd"),
+            ("_PID", "1"),
+        ]
+        .into_iter()
+        .collect();
+        let line = entry_line(
+            |name| entry.get(name).map(|value| value.to_string()),
+            &["_PID".to_string(), "CONTAINER_ID".to_string()],
+            "local",
+        )
+        .unwrap();
+        assert_eq!(line.line.as_deref(), Some("Started Session 1"));
+        assert_eq!(line.get_app(), None);
+        assert_eq!(line.get_file(), Some("session-1.scope"));
+        assert_eq!(line.get_level(), Some("ERROR"));
+        assert_eq!(line.get_host(), Some("node-1"));
+        assert_eq!(line.get_meta(), Some(&json!({"_PID": "1"})));
+        assert!(line.get_labels().is_some());
+
+        let line = entry_line(
+            |name| entry.get(name).map(|value| value.to_string()),
+            &[],
+            "local",
+        )
+        .unwrap();
+        assert_eq!(line.get_meta(), None);
+
+        assert!(entry_line(|_| None, &[], "local").is_none());
+    }
+}
@@ -22,7 +22,7 @@ use pin_utils::pin_mut;
 
 use serde::Serialize;
 
-use http::types::body::LineBuilder;
+use http::types::body::{LineBuilder, LineMetaMut};
 
 use metrics::Metrics;
 
@@ -43,6 +43,34 @@ lazy_static! {
     };
 }
 
+/// Selects the events by the namespace and kind of their involved object, their type and their
+/// reason. Each list that isn't empty must hold the value of the event.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFilter {
+    pub namespaces: Vec<String>,
+    pub kinds: Vec<String>,
+    /// `Normal` or `Warning`
+    pub types: Vec<String>,
+    pub reasons: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn allows(&self, event: &Event) -> bool {
+        let allows = |values: &[String], value: Option<&String>| {
+            values.is_empty() || value.map_or(false, |value| values.contains(value))
+        };
+        let namespace = event
+            .involved_object
+            .namespace
+            .as_ref()
+            .or_else(|| event.metadata.namespace.as_ref());
+        allows(&self.namespaces, namespace)
+            && allows(&self.kinds, event.involved_object.kind.as_ref())
+            && allows(&self.types, event.type_.as_ref())
+            && allows(&self.reasons, event.reason.as_ref())
+    }
+}
+
 impl From<Event> for EventLog {
     // Replicate the Reporter's formatting
     fn from(event: Event) -> Self {
@@ -201,11 +229,17 @@ impl TryFrom<EventLog> for LineBuilder {
     type Error = K8sEventStreamError;
 
     fn try_from(value: EventLog) -> Result<Self, Self::Error> {
+        // The event is also sent as the meta of the line, so that its fields can be searched
+        let meta =
+            serde_json::to_value(&value.line).map_err(K8sEventStreamError::SerializationError)?;
         serde_json::to_string(&value.line)
             .map_err(K8sEventStreamError::SerializationError)
             .map(|e| {
                 debug!("logging event: {}", e);
                 let mut line = LineBuilder::new().line(e);
+                if let Err(e) = line.set_meta(meta) {
+                    warn!("unable to set the meta of k8s event: {:?}", e);
+                }
                 if let Some(host) = &value.host {
                     line = line.host(host);
                 }
@@ -225,6 +259,7 @@ pub struct K8sEventStream {
     pod_name: String,
     namespace: String,
     pod_label: String,
+    filter: EventFilter,
 }
 
 pub enum StreamElem<T> {
@@ -238,12 +273,14 @@ impl K8sEventStream {
         pod_name: String,
         namespace: String,
         pod_label: String,
+        filter: EventFilter,
     ) -> Result<Self, K8sError> {
         Ok(Self {
             client: Client::try_from(config)?,
             pod_name,
             namespace,
             pod_label,
+            filter,
         })
     }
 
@@ -251,6 +288,7 @@ impl K8sEventStream {
         pod_name: String,
         namespace: String,
         pod_label: String,
+        filter: EventFilter,
     ) -> Result<Self, K8sError> {
         let config = match Config::from_cluster_env() {
             Ok(v) => v,
@@ -261,7 +299,7 @@ impl K8sEventStream {
                 )))
             }
         };
-        Self::new(config, pod_name, namespace, pod_label)
+        Self::new(config, pod_name, namespace, pod_label, filter)
     }
 
     async fn get_oldest_pod(
@@ -414,6 +452,7 @@ impl K8sEventStream {
         client: Arc<Client>,
         latest_event_time: Arc<AtomicCell<Option<NonZeroI64>>>,
         previous_event_logger_delete_time: Arc<AtomicCell<Option<NonZeroI64>>>,
+        filter: EventFilter,
     ) -> impl Stream<Item = Result<StreamElem<LineBuilder>, K8sEventStreamError>> {
         let events: Api<Event> = Api::all(client.as_ref().clone());
         let params = ListParams::default();
@@ -423,6 +462,7 @@ impl K8sEventStream {
             .map_err(K8sEventStreamError::WatcherError)
             .filter({
                 move |event| {
+                    let allowed = event.as_ref().map_or(true, |e| filter.allows(e));
                     let latest_event_time = latest_event_time.clone();
                     let earliest = previous_event_logger_delete_time.clone();
                     let ret = latest_event_time
@@ -437,7 +477,7 @@ impl K8sEventStream {
                                     .map(|l| earliest < l.0.naive_utc())
                             })
                         });
-                    async move { ret.unwrap_or(true) }
+                    async move { allowed && ret.unwrap_or(true) }
                 }
             })
             .map(move |event| {
@@ -470,6 +510,7 @@ impl K8sEventStream {
         pod_label: impl Into<String>,
         client: Arc<Client>,
         latest_event_time: Arc<AtomicCell<Option<NonZeroI64>>>,
+        filter: EventFilter,
     ) -> impl Stream<Item = Result<LineBuilder, K8sEventStreamError>> {
         let pod_name = pod_name.into();
         let namespace = namespace.into();
@@ -489,6 +530,7 @@ impl K8sEventStream {
             client,
             latest_event_time,
             previous_event_logger_delete_time,
+            filter,
         );
 
         waiting_stream.chain(event_stream).filter_map(|e| async {
@@ -508,6 +550,7 @@ impl K8sEventStream {
         let pod_name = self.pod_name.clone();
         let namespace = self.namespace.clone();
         let pod_label = self.pod_label.clone();
+        let filter = self.filter.clone();
 
         let _latest_event_time = latest_event_time.clone();
         let _client = client.clone();
@@ -518,6 +561,7 @@ impl K8sEventStream {
                 pod_label.clone(),
                 _client.clone(),
                 _latest_event_time.clone(),
+                filter.clone(),
             )
         };
 
@@ -552,3 +596,62 @@ fn get_pod_started_at(p: &Pod) -> Option<&Time> {
         })
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineMeta;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+    fn event(namespace: &str, kind: &str, type_: &str, reason: &str) -> Event {
+        Event {
+            metadata: ObjectMeta {
+                namespace: Some(namespace.into()),
+                ..ObjectMeta::default()
+            },
+            involved_object: ObjectReference {
+                kind: Some(kind.into()),
+                name: Some("web-1".into()),
+                namespace: Some(namespace.into()),
+                ..ObjectReference::default()
+            },
+            type_: Some(type_.into()),
+            reason: Some(reason.into()),
+            message: Some("Container web was OOM killed".into()),
+            ..Event::default()
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let filter = EventFilter {
+            namespaces: vec!["prod".into()],
+            types: vec!["Warning".into()],
+            reasons: vec!["OOMKilled".into(), "FailedScheduling".into()],
+            ..EventFilter::default()
+        };
+        assert!(filter.allows(&event("prod", "Pod", "Warning", "OOMKilled")));
+        assert!(!filter.allows(&event("staging", "Pod", "Warning", "OOMKilled")));
+        assert!(!filter.allows(&event("prod", "Pod", "Normal", "OOMKilled")));
+        assert!(!filter.allows(&event("prod", "Pod", "Warning", "BackOff")));
+        assert!(EventFilter::default().allows(&event("prod", "Node", "Normal", "Starting")));
+
+        let filter = EventFilter {
+            kinds: vec!["Node".into()],
+            ..EventFilter::default()
+        };
+        assert!(!filter.allows(&event("prod", "Pod", "Normal", "Starting")));
+    }
+
+    #[test]
+    fn test_event_meta() {
+        let line =
+            LineBuilder::try_from(EventLog::from(event("prod", "Pod", "Warning", "OOMKilled")))
+                .unwrap();
+        assert_eq!(line.get_level(), Some("Warning"));
+        let meta = line.get_meta().unwrap();
+        assert_eq!(meta["kube"]["reason"], "OOMKilled");
+        assert_eq!(meta["kube"]["resource"], "Pod");
+        assert_eq!(meta["kube"]["namespace"], "prod");
+    }
+}
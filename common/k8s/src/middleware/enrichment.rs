@@ -0,0 +1,60 @@
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("unknown k8s metadata field: {0}")]
+pub struct ParseEnrichmentField(String);
+
+/// The pod metadata that can be added to the `k8s` meta field of the container lines, besides
+/// the pod labels and annotations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnrichmentField {
+    /// The name of the node running the pod
+    Node,
+    /// The name of the container
+    Container,
+    /// The image of the container
+    Image,
+    /// The kind and name of the controller of the pod, such as the Deployment or the Job
+    Owner,
+    /// The IP address of the pod
+    PodIp,
+    /// The labels of the namespace of the pod
+    NamespaceLabels,
+}
+
+impl EnrichmentField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnrichmentField::Node => "node",
+            EnrichmentField::Container => "container",
+            EnrichmentField::Image => "image",
+            EnrichmentField::Owner => "owner",
+            EnrichmentField::PodIp => "pod_ip",
+            EnrichmentField::NamespaceLabels => "namespace_labels",
+        }
+    }
+}
+
+impl fmt::Display for EnrichmentField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for EnrichmentField {
+    type Err = ParseEnrichmentField;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "node" => Ok(EnrichmentField::Node),
+            "container" => Ok(EnrichmentField::Container),
+            "image" => Ok(EnrichmentField::Image),
+            "owner" => Ok(EnrichmentField::Owner),
+            "pod_ip" => Ok(EnrichmentField::PodIp),
+            "namespace_labels" => Ok(EnrichmentField::NamespaceLabels),
+            _ => Err(ParseEnrichmentField(s.to_string())),
+        }
+    }
+}
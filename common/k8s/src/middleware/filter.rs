@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+
+/// The pod annotation opting all the containers of a pod out of log collection
+pub const EXCLUDE_ANNOTATION: &str = "logdna.com/exclude";
+
+/// Selects the containers by their namespace, pod, name, and the labels and annotations of
+/// their pod. All the conditions that are set must match.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PodSelector {
+    pub namespace: Option<String>,
+    pub pod: Option<String>,
+    pub container: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// The container of a line, the labels and annotations are `None` when the pod isn't known
+pub struct ContainerInfo<'a> {
+    pub namespace: &'a str,
+    pub pod: &'a str,
+    pub container: &'a str,
+    pub labels: Option<&'a BTreeMap<String, String>>,
+    pub annotations: Option<&'a BTreeMap<String, String>>,
+}
+
+impl PodSelector {
+    /// Returns `None` when the selector depends on the labels or annotations of a pod that
+    /// isn't known
+    fn matches(&self, info: &ContainerInfo) -> Option<bool> {
+        let matches_name =
+            |name: &Option<String>, value: &str| name.as_ref().map_or(true, |name| name == value);
+        let matches_map = |selected: &BTreeMap<String, String>,
+                           map: Option<&BTreeMap<String, String>>| {
+            if selected.is_empty() {
+                return Some(true);
+            }
+            let map = map?;
+            Some(
+                selected
+                    .iter()
+                    .all(|(key, value)| map.get(key) == Some(value)),
+            )
+        };
+        if !(matches_name(&self.namespace, info.namespace)
+            && matches_name(&self.pod, info.pod)
+            && matches_name(&self.container, info.container))
+        {
+            return Some(false);
+        }
+        match (
+            matches_map(&self.labels, info.labels),
+            matches_map(&self.annotations, info.annotations),
+        ) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether any of the selectors matches, `None` when it depends on the labels or
+/// annotations of a pod that isn't known
+fn matches_any(selectors: &[PodSelector], info: &ContainerInfo) -> Option<bool> {
+    let mut matches = Some(false);
+    for selector in selectors {
+        match selector.matches(info) {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => matches = None,
+        }
+    }
+    matches
+}
+
+/// Decides which containers have their lines sent. Containers whose pod is annotated with
+/// `logdna.com/exclude: "true"` or matching an exclusion are dropped, and when there are
+/// inclusions, only the containers matching one of them are kept.
+///
+/// The selectors on labels and annotations can't decide for the containers of a pod that isn't
+/// known: the exclusions keep its lines, while the inclusions can't tell whether they are sent.
+/// The pods with the `logdna.com/exclude` annotation are only known to be excluded once their
+/// annotations are known.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PodFilter {
+    pub include: Vec<PodSelector>,
+    pub exclude: Vec<PodSelector>,
+}
+
+impl PodFilter {
+    /// Returns whether the lines of the container are sent, `None` when an inclusion depends on
+    /// the labels or annotations of a pod that isn't known
+    pub fn allows(&self, info: &ContainerInfo) -> Option<bool> {
+        if info
+            .annotations
+            .and_then(|annotations| annotations.get(EXCLUDE_ANNOTATION))
+            .map_or(false, |value| value.eq_ignore_ascii_case("true"))
+        {
+            return Some(false);
+        }
+        // The exclusions that can't be decided keep the line
+        if matches_any(&self.exclude, info) == Some(true) {
+            return Some(false);
+        }
+        if self.include.is_empty() {
+            return Some(true);
+        }
+        matches_any(&self.include, info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let labels = map(&[("app", "web"), ("tier", "frontend")]);
+        let annotations = BTreeMap::new();
+        let info = |namespace, container| ContainerInfo {
+            namespace,
+            pod: "web-1",
+            container,
+            labels: Some(&labels),
+            annotations: Some(&annotations),
+        };
+
+        let filter = PodFilter {
+            include: vec![
+                PodSelector {
+                    namespace: Some("prod".into()),
+                    ..PodSelector::default()
+                },
+                PodSelector {
+                    labels: map(&[("tier", "frontend")]),
+                    ..PodSelector::default()
+                },
+            ],
+            exclude: vec![PodSelector {
+                container: Some("istio-proxy".into()),
+                ..PodSelector::default()
+            }],
+        };
+        assert_eq!(filter.allows(&info("prod", "web")), Some(true));
+        assert_eq!(filter.allows(&info("staging", "web")), Some(true));
+        assert_eq!(filter.allows(&info("prod", "istio-proxy")), Some(false));
+
+        let filter = PodFilter {
+            include: vec![PodSelector {
+                namespace: Some("prod".into()),
+                labels: map(&[("app", "api")]),
+                ..PodSelector::default()
+            }],
+            exclude: Vec::new(),
+        };
+        assert_eq!(filter.allows(&info("prod", "web")), Some(false));
+        assert_eq!(
+            PodFilter::default().allows(&info("prod", "web")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_unknown_pod() {
+        let info = |namespace| ContainerInfo {
+            namespace,
+            pod: "web-1",
+            container: "web",
+            labels: None,
+            annotations: None,
+        };
+        let by_label = PodSelector {
+            namespace: Some("prod".into()),
+            labels: map(&[("tier", "frontend")]),
+            ..PodSelector::default()
+        };
+
+        let filter = PodFilter {
+            include: Vec::new(),
+            exclude: vec![by_label.clone()],
+        };
+        assert_eq!(filter.allows(&info("prod")), Some(true));
+        assert_eq!(filter.allows(&info("staging")), Some(true));
+
+        let filter = PodFilter {
+            include: vec![by_label.clone()],
+            exclude: vec![PodSelector {
+                container: Some("web".into()),
+                ..PodSelector::default()
+            }],
+        };
+        assert_eq!(filter.allows(&info("prod")), Some(false));
+
+        let filter = PodFilter {
+            include: vec![by_label],
+            exclude: Vec::new(),
+        };
+        assert_eq!(filter.allows(&info("prod")), None);
+        assert_eq!(filter.allows(&info("staging")), Some(false));
+        assert_eq!(PodFilter::default().allows(&info("prod")), Some(true));
+    }
+
+    #[test]
+    fn test_exclude_annotation() {
+        let labels = BTreeMap::new();
+        let annotations = map(&[(EXCLUDE_ANNOTATION, "true")]);
+        let info = ContainerInfo {
+            namespace: "prod",
+            pod: "web-1",
+            container: "web",
+            labels: Some(&labels),
+            annotations: Some(&annotations),
+        };
+        assert_eq!(PodFilter::default().allows(&info), Some(false));
+        let annotations = map(&[(EXCLUDE_ANNOTATION, "false")]);
+        assert_eq!(
+            PodFilter::default().allows(&ContainerInfo {
+                annotations: Some(&annotations),
+                ..info
+            }),
+            Some(true)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use middleware::json_fields::{JsonFieldNames, JsonFields};
+use middleware::line_rules;
+use middleware::{LineMut, Middleware, Status};
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+
+/// The pod annotation selecting the parser of the lines, only `json` is supported
+pub const PARSER_ANNOTATION: &str = "logdna.com/parser";
+/// The pod annotation holding the regex matching the first line of multi-line events
+pub const MULTILINE_PATTERN_ANNOTATION: &str = "logdna.com/multiline-pattern";
+/// The pod annotation setting the app of the lines
+pub const APP_ANNOTATION: &str = "logdna.com/app";
+/// The pod annotation holding the regexes of the values to redact, one per line
+pub const REDACT_ANNOTATION: &str = "logdna.com/redact";
+
+/// The processing requested by the annotations of a pod for the lines of its containers.
+///
+/// The hints of a file are read from the annotations when it's first tailed and kept until
+/// its pod is deleted, invalid values are logged and ignored.
+pub struct FileHints {
+    json: Option<JsonFields>,
+    multiline_pattern: Option<String>,
+    app: Option<String>,
+    redact: Vec<Regex>,
+}
+
+impl FileHints {
+    pub fn from_annotations(annotations: &BTreeMap<String, String>) -> Self {
+        let json = match annotations.get(PARSER_ANNOTATION).map(|p| p.trim()) {
+            Some("json") => Some(JsonFields::new(
+                JsonFieldNames {
+                    level: Some("level".into()),
+                    app: None,
+                    meta: None,
+                    timestamp: Some("timestamp".into()),
+                    message: Some("message".into()),
+                },
+                false,
+            )),
+            Some(parser) => {
+                warn!(
+                    "ignoring unknown {} annotation: {}",
+                    PARSER_ANNOTATION, parser
+                );
+                None
+            }
+            None => None,
+        };
+        let redact = annotations
+            .get(REDACT_ANNOTATION)
+            .map(|patterns| {
+                patterns
+                    .lines()
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .filter_map(|pattern| match Regex::new(pattern) {
+                        Ok(regex) => Some(regex),
+                        Err(e) => {
+                            warn!("ignoring invalid {} annotation: {}", REDACT_ANNOTATION, e);
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        FileHints {
+            json,
+            multiline_pattern: annotations
+                .get(MULTILINE_PATTERN_ANNOTATION)
+                .filter(|pattern| !pattern.is_empty())
+                .cloned(),
+            app: annotations
+                .get(APP_ANNOTATION)
+                .filter(|app| !app.is_empty())
+                .cloned(),
+            redact,
+        }
+    }
+
+    /// The regex matching the first line of the multi-line events of the file
+    pub fn multiline_pattern(&self) -> Option<&str> {
+        self.multiline_pattern.as_deref()
+    }
+
+    pub fn apply<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        if let Some(app) = self.app.as_ref() {
+            if line.set_app(app.clone()).is_err() {
+                return Status::Skip;
+            }
+        }
+        if !self.redact.is_empty() {
+            let redacted = line
+                .get_line_buffer()
+                .and_then(|buf| line_rules::redact(&self.redact, buf));
+            if let Some(redacted) = redacted {
+                if line.set_line_buffer(redacted).is_err() {
+                    return Status::Skip;
+                }
+            }
+        }
+        match self.json.as_ref() {
+            Some(json) => json.process(line),
+            None => Status::Ok(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta};
+
+    fn hints(annotations: &[(&str, &str)]) -> FileHints {
+        FileHints::from_annotations(
+            &annotations
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_apply_hints() {
+        let hints = hints(&[
+            (PARSER_ANNOTATION, "json"),
+            (APP_ANNOTATION, "checkout"),
+            (REDACT_ANNOTATION, "card=\\d+\ntoken=\\w+"),
+            (MULTILINE_PATTERN_ANNOTATION, "^\\S"),
+        ]);
+        assert_eq!(hints.multiline_pattern(), Some("^\\S"));
+
+        let mut line =
+            LineBuilder::new().line(r#"{"level":"error","message":"card=4242 token=abc"}"#);
+        assert!(matches!(hints.apply(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_app(), Some("checkout"));
+        assert_eq!(line.get_level(), Some("error"));
+        assert_eq!(
+            line.line.as_deref(),
+            Some(r#"{"level":"error","message":"[REDACTED] [REDACTED]"}"#)
+        );
+    }
+
+    #[test]
+    fn test_ignore_invalid_hints() {
+        let hints = hints(&[(PARSER_ANNOTATION, "xml"), (REDACT_ANNOTATION, "(")]);
+        assert!(hints.json.is_none());
+        assert!(hints.redact.is_empty());
+        assert_eq!(hints.multiline_pattern(), None);
+
+        let mut line = LineBuilder::new().line("abc");
+        assert!(matches!(hints.apply(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_app(), None);
+    }
+}
@@ -1,10 +1,13 @@
 use crate::errors::K8sError;
-use crate::middleware::parse_container_path;
+use crate::middleware::{
+    parse_container_path, ContainerInfo, EnrichmentField, FileHints, PodFilter,
+};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::stream::TryStreamExt;
 use futures::StreamExt;
-use http::types::body::{KeyValueMap, LineBufferMut};
-use k8s_openapi::api::core::v1::Pod;
-use kube::{api::ListParams, config::Config, Api, Client};
+use k8s_openapi::api::core::v1::{Namespace, Pod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{api::ListParams, config::Config, Api, Client, Resource};
 
 use kube_runtime::watcher;
 use kube_runtime::watcher::Event as WatcherEvent;
@@ -12,14 +15,18 @@ use kube_runtime::watcher::Event as WatcherEvent;
 use backoff::backoff::Backoff;
 use backoff::ExponentialBackoff;
 use metrics::Metrics;
-use middleware::{Middleware, Status};
-use parking_lot::Mutex;
+use middleware::{merge_meta, LineMut, Middleware, Status};
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::env;
+use std::fmt::Debug;
 use std::rc::Rc;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::runtime::Builder;
 
@@ -27,6 +34,15 @@ use tokio::runtime::Builder;
 /// before error out.
 static MAX_INIT_TIME: Duration = Duration::from_millis(20_000);
 
+/// The time before a pod missing from the cache is looked up again
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A pod to look up, by its name and namespace
+type LookupRequest = (String, String);
+
+/// The label holding the hash suffixed to the names of the ReplicaSets of a Deployment
+const POD_TEMPLATE_HASH_LABEL: &str = "pod-template-hash";
+
 #[derive(Error, Debug)]
 enum Error {
     #[error(transparent)]
@@ -40,11 +56,25 @@ enum Error {
 pub struct K8sMetadata {
     metadata: Mutex<HashMap<(String, String), PodMetadata>>,
     api: Api<Pod>,
+    client: Client,
+    /// The node running the agent, only its pods are watched when it's known
+    node_name: Option<String>,
+    /// The pods missing from the cache, looked up in the background
+    lookups: Mutex<HashMap<(String, String), Instant>>,
+    lookup_sender: UnboundedSender<LookupRequest>,
+    lookup_receiver: Mutex<Option<UnboundedReceiver<LookupRequest>>>,
+    filter: PodFilter,
+    fields: Vec<EnrichmentField>,
+    /// The labels of the namespaces, only tracked when they are added to the lines
+    namespaces: Mutex<HashMap<String, BTreeMap<String, String>>>,
+    namespace_api: Api<Namespace>,
+    /// The hints of the container files, read from the pod annotations when first tailed
+    hints: Mutex<HashMap<String, Arc<FileHints>>>,
 }
 
 // TODO refactor to use kube-rs Reflector instead of manually managing hashmap
 impl K8sMetadata {
-    pub async fn new() -> Result<Self, K8sError> {
+    pub async fn new(filter: PodFilter, fields: Vec<EnrichmentField>) -> Result<Self, K8sError> {
         let config = match Config::from_cluster_env() {
             Ok(v) => v,
             Err(e) => {
@@ -55,16 +85,31 @@ impl K8sMetadata {
             }
         };
         let client = Client::try_from(config)?;
-        let metadata = K8sMetadata::initialize(&client, MAX_INIT_TIME).await?;
+        let node_name = env::var("NODE_NAME").ok().filter(|node| !node.is_empty());
+        let metadata =
+            K8sMetadata::initialize(&client, &pod_params(node_name.as_deref()), MAX_INIT_TIME)
+                .await?;
+        let (lookup_sender, lookup_receiver) = unbounded();
 
         Ok(K8sMetadata {
             metadata: Mutex::new(metadata),
-            api: Api::<Pod>::all(client),
+            api: Api::<Pod>::all(client.clone()),
+            client: client.clone(),
+            node_name,
+            lookups: Mutex::new(HashMap::new()),
+            lookup_sender,
+            lookup_receiver: Mutex::new(Some(lookup_receiver)),
+            filter,
+            fields,
+            namespaces: Mutex::new(HashMap::new()),
+            namespace_api: Api::<Namespace>::all(client),
+            hints: Mutex::new(HashMap::new()),
         })
     }
 
     async fn initialize(
         client: &Client,
+        params: &ListParams,
         max_elapsed_time: Duration,
     ) -> Result<HashMap<(String, String), PodMetadata>, K8sError> {
         let mut metadata = HashMap::new();
@@ -77,13 +122,8 @@ impl K8sMetadata {
             max_elapsed_time: Some(max_elapsed_time),
             ..ExponentialBackoff::default()
         };
-        let mut params = ListParams::default();
-        if let Ok(node) = env::var("NODE_NAME") {
-            params = ListParams::default().fields(&format!("spec.nodeName={}", node));
-        }
-
         loop {
-            match Api::<Pod>::all(client.clone()).list(&params).await {
+            match Api::<Pod>::all(client.clone()).list(params).await {
                 Ok(pods) => {
                     for pod in pods {
                         let pod_meta_data = match PodMetadata::try_from(pod) {
@@ -135,9 +175,13 @@ impl K8sMetadata {
             } // insert or update
             WatcherEvent::Deleted(pod) => {
                 let pod_meta_data = PodMetadata::try_from(pod)?;
-                self.metadata
-                    .lock()
-                    .remove(&(pod_meta_data.name, pod_meta_data.namespace));
+                let key = (pod_meta_data.name, pod_meta_data.namespace);
+                self.metadata.lock().remove(&key);
+                self.hints.lock().retain(|file, _| {
+                    parse_container_path(file).map_or(true, |(pod, namespace, _)| {
+                        pod != key.0 || namespace != key.1
+                    })
+                });
                 Metrics::k8s().increment_deletes();
             } // remove
             WatcherEvent::Restarted(pods) => {
@@ -160,6 +204,188 @@ impl K8sMetadata {
         Ok(())
     }
 
+    fn handle_namespace(&self, event: WatcherEvent<Namespace>) -> Result<(), K8sError> {
+        let name_and_labels = |namespace: Namespace| match namespace.metadata.name {
+            Some(name) => Ok((name, namespace.metadata.labels.unwrap_or_default())),
+            None => Err(K8sError::PodMissingMetaError("namespace metadata.name")),
+        };
+        let mut namespaces = self.namespaces.lock();
+        match event {
+            WatcherEvent::Applied(namespace) => {
+                let (name, labels) = name_and_labels(namespace)?;
+                namespaces.insert(name, labels);
+            }
+            WatcherEvent::Deleted(namespace) => {
+                let (name, _) = name_and_labels(namespace)?;
+                namespaces.remove(&name);
+            }
+            WatcherEvent::Restarted(list) => {
+                namespaces.clear();
+                for namespace in list {
+                    let (name, labels) = name_and_labels(namespace)?;
+                    namespaces.insert(name, labels);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Watches the resources of the api, retrying with a delay when the stream fails
+    async fn watch<K, F>(&self, api: Api<K>, params: ListParams, handle: F)
+    where
+        K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+        F: Fn(WatcherEvent<K>) -> Result<(), K8sError>,
+    {
+        let backoff = Rc::new(RefCell::new(ExponentialBackoff::default()));
+        let watcher = watcher(api, params);
+
+        watcher
+            .into_stream()
+            .filter_map(|r| async {
+                let mut backoff = backoff.borrow_mut();
+                match r {
+                    Ok(event) => {
+                        backoff.reset();
+                        Some(event)
+                    }
+                    Err(e) => {
+                        log::warn!("k8s watch stream error: {}", e);
+                        // When polled after a some errors, the watcher will try to recover.
+                        // We should avoid eagerly polling in those cases.
+                        self.add_delay(&mut backoff).await;
+                        None
+                    }
+                }
+            })
+            .for_each(|event| async {
+                handle(event).unwrap_or_else(|e| log::warn!("unable to process k8s event: {}", e));
+            })
+            .await;
+    }
+
+    /// Requests the lookup of a pod missing from the cache, such as a pod that was scheduled
+    /// before the watch caught up with it. Nothing is requested when the pod was looked up
+    /// recently.
+    fn request_lookup(&self, key: &(String, String)) {
+        let now = Instant::now();
+        let mut lookups = self.lookups.lock();
+        if lookups.get(key).map_or(false, |requested| {
+            now.duration_since(*requested) < LOOKUP_INTERVAL
+        }) {
+            return;
+        }
+        lookups.retain(|_, requested| now.duration_since(*requested) < LOOKUP_INTERVAL);
+        lookups.insert(key.clone(), now);
+        if let Err(e) = self.lookup_sender.unbounded_send(key.clone()) {
+            log::debug!(
+                "unable to request the lookup of pod {}/{}: {}",
+                key.1,
+                key.0,
+                e
+            );
+        }
+    }
+
+    /// Returns the metadata of a pod. A pod missing from the cache is looked up in the
+    /// background without waiting for it, its metadata applies to its lines once it's cached.
+    /// The pods that can't be found are looked up again after `LOOKUP_INTERVAL`.
+    fn pod_metadata(&self, key: &(String, String)) -> Option<MappedMutexGuard<'_, PodMetadata>> {
+        let metadata = MutexGuard::try_map(self.metadata.lock(), |m| m.get_mut(key)).ok();
+        if metadata.is_none() {
+            self.request_lookup(key);
+        }
+        metadata
+    }
+
+    /// Fetches the pods missing from the cache, keeping the metadata of the watch when the
+    /// pod was received in the meantime
+    async fn look_up_pods(&self) {
+        let receiver = match self.lookup_receiver.lock().take() {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        receiver
+            .for_each(|(name, namespace)| async move {
+                let pod = match Api::<Pod>::namespaced(self.client.clone(), &namespace)
+                    .get(&name)
+                    .await
+                {
+                    Ok(pod) => pod,
+                    Err(kube::Error::Api(e)) if e.code == 404 => {
+                        log::debug!("pod {}/{} not found", namespace, name);
+                        return;
+                    }
+                    Err(e) => {
+                        log::warn!("unable to look up pod {}/{}: {}", namespace, name, e);
+                        return;
+                    }
+                };
+                match PodMetadata::try_from(pod) {
+                    Ok(pod_meta_data) => {
+                        self.metadata
+                            .lock()
+                            .entry((name, namespace))
+                            .or_insert_with(|| {
+                                Metrics::k8s().increment_creates();
+                                pod_meta_data
+                            });
+                    }
+                    Err(e) => log::warn!("ignoring looked up pod: {}", e),
+                }
+            })
+            .await;
+    }
+
+    /// Returns the hints of a container file from the annotations of its pod, they are read
+    /// the first time the file is seen and kept while the pod exists. `None` is returned while
+    /// the pod is missing from the cache, its lookup is requested in the meantime.
+    pub fn file_hints(&self, file: &str) -> Option<Arc<FileHints>> {
+        if let Some(hints) = self.hints.lock().get(file) {
+            return Some(hints.clone());
+        }
+        let (pod, namespace, _) = parse_container_path(file)?;
+        let pod_meta_data = self.pod_metadata(&(pod, namespace))?;
+        Some(self.hints_for(file, &pod_meta_data.annotations))
+    }
+
+    fn hints_for(&self, file: &str, annotations: &BTreeMap<String, String>) -> Arc<FileHints> {
+        self.hints
+            .lock()
+            .entry(file.to_string())
+            .or_insert_with(|| Arc::new(FileHints::from_annotations(annotations)))
+            .clone()
+    }
+
+    /// Builds the `k8s` meta field of the lines of a container
+    fn enrichment(&self, pod_meta_data: &PodMetadata, container: &str) -> Map<String, Value> {
+        let mut k8s = Map::new();
+        for field in &self.fields {
+            let value = match field {
+                EnrichmentField::Node => pod_meta_data.node.clone().map(Value::String),
+                EnrichmentField::Container => Some(Value::String(container.to_string())),
+                EnrichmentField::Image => pod_meta_data
+                    .images
+                    .get(container)
+                    .cloned()
+                    .map(Value::String),
+                EnrichmentField::Owner => pod_meta_data
+                    .owner
+                    .as_ref()
+                    .map(|(kind, name)| json!({"kind": kind, "name": name})),
+                EnrichmentField::PodIp => pod_meta_data.pod_ip.clone().map(Value::String),
+                EnrichmentField::NamespaceLabels => self
+                    .namespaces
+                    .lock()
+                    .get(&pod_meta_data.namespace)
+                    .map(|labels| json!(labels)),
+            };
+            if let Some(value) = value {
+                k8s.insert(field.name().to_string(), value);
+            }
+        }
+        k8s
+    }
+
     async fn add_delay(&self, backoff: &mut ExponentialBackoff) {
         let mut interval = backoff.next_backoff();
         if interval.is_none() {
@@ -176,52 +402,93 @@ impl Middleware for K8sMetadata {
         // Start parsing k8s events in the background
         let runtime = Builder::new_multi_thread().build().unwrap();
         runtime.block_on(async move {
-            let backoff = Rc::new(RefCell::new(ExponentialBackoff::default()));
-            let watcher = watcher(self.api.clone(), ListParams::default());
-
-            watcher
-                .into_stream()
-                .filter_map(|r| async {
-                    let mut backoff = backoff.borrow_mut();
-                    match r {
-                        Ok(event) => {
-                            backoff.reset();
-                            Some(event)
-                        }
-                        Err(e) => {
-                            log::warn!("k8s watch stream error: {}", e);
-                            // When polled after a some errors, the watcher will try to recover.
-                            // We should avoid eagerly polling in those cases.
-                            self.add_delay(&mut backoff).await;
-                            None
-                        }
-                    }
-                })
-                .for_each(|p| async {
-                    self.handle_pod(p)
-                        .unwrap_or_else(|e| log::warn!("unable to process pod event: {}", e));
-                })
-                .await;
+            let pods = self.watch(
+                self.api.clone(),
+                pod_params(self.node_name.as_deref()),
+                |event| self.handle_pod(event),
+            );
+            let namespaces = async {
+                if self.fields.contains(&EnrichmentField::NamespaceLabels) {
+                    self.watch(self.namespace_api.clone(), ListParams::default(), |event| {
+                        self.handle_namespace(event)
+                    })
+                    .await;
+                }
+            };
+            futures::future::join3(pods, namespaces, self.look_up_pods()).await;
         });
     }
 
-    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
-        if let Some(file_name) = line.get_file() {
-            if let Some(key) = parse_container_path(file_name) {
-                if let Some(pod_meta_data) = self.metadata.lock().get(&key) {
-                    if line
-                        .set_annotations(pod_meta_data.annotations.clone())
-                        .is_err()
-                    {
-                        return Status::Skip;
-                    };
-                    if line.set_labels(pod_meta_data.labels.clone()).is_err() {
-                        return Status::Skip;
-                    };
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        let file = match line.get_file() {
+            Some(file) => file.to_string(),
+            None => return Status::Ok(line),
+        };
+        let (pod, namespace, container) = match parse_container_path(&file) {
+            Some(v) => v,
+            None => return Status::Ok(line),
+        };
+        let key = (pod, namespace);
+        let metadata = self.pod_metadata(&key);
+        let pod_meta_data = metadata.as_deref();
+
+        let info = ContainerInfo {
+            namespace: &key.1,
+            pod: &key.0,
+            container: &container,
+            labels: pod_meta_data.map(|p| &p.labels),
+            annotations: pod_meta_data.map(|p| &p.annotations),
+        };
+        match self.filter.allows(&info) {
+            Some(true) => {}
+            Some(false) => {
+                Metrics::middleware().increment_k8s_filtered();
+                return Status::Skip;
+            }
+            // The pod isn't cached yet, the inclusions on its labels or annotations can't tell
+            // whether its lines are to be sent
+            None => {
+                log::debug!(
+                    "dropping line of pod {}/{} missing from the cache",
+                    key.1,
+                    key.0
+                );
+                Metrics::middleware().increment_k8s_filtered();
+                return Status::Skip;
+            }
+        }
+
+        if let Some(pod_meta_data) = pod_meta_data {
+            if line
+                .set_annotations(pod_meta_data.annotations.clone().into())
+                .is_err()
+            {
+                return Status::Skip;
+            };
+            if line
+                .set_labels(pod_meta_data.labels.clone().into())
+                .is_err()
+            {
+                return Status::Skip;
+            };
+            if !self.fields.is_empty() {
+                let mut meta = Map::new();
+                meta.insert(
+                    "k8s".into(),
+                    Value::Object(self.enrichment(pod_meta_data, &container)),
+                );
+                if merge_meta(line, meta).is_err() {
+                    return Status::Skip;
                 }
             }
         }
-        Status::Ok(line)
+
+        let hints = pod_meta_data.map(|p| self.hints_for(&file, &p.annotations));
+        drop(metadata);
+        match hints {
+            Some(hints) => hints.apply(line),
+            None => Status::Ok(line),
+        }
     }
 }
 
@@ -229,6 +496,7 @@ impl TryFrom<k8s_openapi::api::core::v1::Pod> for PodMetadata {
     type Error = K8sError;
 
     fn try_from(value: k8s_openapi::api::core::v1::Pod) -> Result<Self, Self::Error> {
+        let owner = owner(&value.metadata);
         let real_pod_meta = value.metadata;
 
         let name = match real_pod_meta.name {
@@ -244,32 +512,81 @@ impl TryFrom<k8s_openapi::api::core::v1::Pod> for PodMetadata {
             }
         };
 
+        let spec = value.spec.unwrap_or_default();
+        let images = spec
+            .containers
+            .into_iter()
+            .chain(spec.init_containers.unwrap_or_default())
+            .filter_map(|container| Some((container.name, container.image?)))
+            .collect();
+
         Ok(PodMetadata {
             name,
             namespace,
-            labels: real_pod_meta
-                .labels
-                .map_or_else(KeyValueMap::new, |v| v.into()),
-            annotations: real_pod_meta
-                .annotations
-                .map_or_else(KeyValueMap::new, |v| v.into()),
+            labels: real_pod_meta.labels.unwrap_or_default(),
+            annotations: real_pod_meta.annotations.unwrap_or_default(),
+            node: spec.node_name,
+            pod_ip: value.status.and_then(|status| status.pod_ip),
+            images,
+            owner,
         })
     }
 }
 
+/// The parameters selecting the pods of the node, or of the whole cluster when the node
+/// isn't known
+fn pod_params(node_name: Option<&str>) -> ListParams {
+    match node_name {
+        Some(node) => ListParams::default().fields(&format!("spec.nodeName={}", node)),
+        None => ListParams::default(),
+    }
+}
+
+/// Returns the kind and name of the controller of the pod, the Deployment when the pod belongs
+/// to one of its ReplicaSets
+fn owner(meta: &ObjectMeta) -> Option<(String, String)> {
+    let owner = meta
+        .owner_references
+        .as_ref()?
+        .iter()
+        .find(|owner| owner.controller == Some(true))?;
+    if owner.kind == "ReplicaSet" {
+        let deployment = meta
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(POD_TEMPLATE_HASH_LABEL))
+            .and_then(|hash| owner.name.strip_suffix(&format!("-{}", hash)));
+        if let Some(deployment) = deployment {
+            return Some(("Deployment".to_string(), deployment.to_string()));
+        }
+    }
+    Some((owner.kind.clone(), owner.name.clone()))
+}
+
 #[derive(Clone)]
 struct PodMetadata {
     name: String,
     namespace: String,
-    labels: KeyValueMap,
-    annotations: KeyValueMap,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+    node: Option<String>,
+    pod_ip: Option<String>,
+    /// The images of the containers by their name
+    images: BTreeMap<String, String>,
+    owner: Option<(String, String)>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::middleware::{
+        PodSelector, APP_ANNOTATION, EXCLUDE_ANNOTATION, MULTILINE_PATTERN_ANNOTATION,
+        REDACT_ANNOTATION,
+    };
     use http::types::body::{LineBuilder, LineMeta};
     use hyper_http::Uri;
+    use k8s_openapi::api::core::v1::{Container, PodSpec, PodStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
     use std::time::Instant;
 
     #[tokio::test]
@@ -322,6 +639,234 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_process_filters_containers() {
+        let file = |pod: &str, container: &str| {
+            format!(
+                "/var/log/containers/{}_prod_{}-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log",
+                pod, container
+            )
+        };
+        let mut excluded = get_pod_metadata();
+        excluded
+            .annotations
+            .insert(EXCLUDE_ANNOTATION.into(), "true".into());
+        let mut map = HashMap::new();
+        map.insert(("web".into(), "prod".into()), get_pod_metadata());
+        map.insert(("batch".into(), "prod".into()), excluded);
+        let mut k8s_meta = get_instance(map);
+        k8s_meta.filter.exclude.push(PodSelector {
+            container: Some("istio-proxy".into()),
+            ..PodSelector::default()
+        });
+
+        let kept = |pod, container| {
+            let mut line = LineBuilder::new().line("abc").file(file(pod, container));
+            matches!(k8s_meta.process(&mut line), Status::Ok(_))
+        };
+        assert!(kept("web", "app"));
+        assert!(!kept("web", "istio-proxy"));
+        assert!(!kept("batch", "app"));
+        // Pods that are not known are filtered by their names only
+        assert!(kept("api", "app"));
+        assert!(!kept("api", "istio-proxy"));
+
+        // An exclusion on their labels keeps their lines until their labels are known
+        let mut labels = BTreeMap::new();
+        labels.insert("tier".to_string(), "batch".to_string());
+        k8s_meta.filter.exclude.push(PodSelector {
+            labels: labels.clone(),
+            ..PodSelector::default()
+        });
+        let kept = |pod, container| {
+            let mut line = LineBuilder::new().line("abc").file(file(pod, container));
+            matches!(k8s_meta.process(&mut line), Status::Ok(_))
+        };
+        assert!(kept("web", "app"));
+        assert!(kept("api", "app"));
+        assert!(!kept("api", "istio-proxy"));
+
+        // An inclusion on their labels drops their lines until their labels are known
+        k8s_meta.filter.include.push(PodSelector {
+            labels,
+            ..PodSelector::default()
+        });
+        let kept = |pod, container| {
+            let mut line = LineBuilder::new().line("abc").file(file(pod, container));
+            matches!(k8s_meta.process(&mut line), Status::Ok(_))
+        };
+        assert!(!kept("api", "app"));
+    }
+
+    #[tokio::test]
+    async fn test_process_enrichment() {
+        let file = "/var/log/containers/web-5d4f8c7b9-x2x7q_prod_app-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log";
+        let pod = Pod {
+            metadata: ObjectMeta {
+                name: Some("web-5d4f8c7b9-x2x7q".into()),
+                namespace: Some("prod".into()),
+                labels: Some(
+                    vec![(POD_TEMPLATE_HASH_LABEL.to_string(), "5d4f8c7b9".to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                owner_references: Some(vec![OwnerReference {
+                    kind: "ReplicaSet".into(),
+                    name: "web-5d4f8c7b9".into(),
+                    controller: Some(true),
+                    ..OwnerReference::default()
+                }]),
+                ..ObjectMeta::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some("node-1".into()),
+                containers: vec![Container {
+                    name: "app".into(),
+                    image: Some("web:1.0".into()),
+                    ..Container::default()
+                }],
+                ..PodSpec::default()
+            }),
+            status: Some(PodStatus {
+                pod_ip: Some("10.0.0.5".into()),
+                ..PodStatus::default()
+            }),
+        };
+        let mut map = HashMap::new();
+        map.insert(
+            ("web-5d4f8c7b9-x2x7q".into(), "prod".into()),
+            PodMetadata::try_from(pod).unwrap(),
+        );
+        let mut k8s_meta = get_instance(map);
+        k8s_meta.namespaces.lock().insert(
+            "prod".into(),
+            vec![("team".to_string(), "web".to_string())]
+                .into_iter()
+                .collect(),
+        );
+
+        let mut line = LineBuilder::new().line("abc").file(file);
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_meta(), None);
+
+        k8s_meta.fields = vec![
+            EnrichmentField::Node,
+            EnrichmentField::Container,
+            EnrichmentField::Image,
+            EnrichmentField::Owner,
+            EnrichmentField::PodIp,
+            EnrichmentField::NamespaceLabels,
+        ];
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+        assert_eq!(
+            line.get_meta(),
+            Some(&json!({
+                "k8s": {
+                    "node": "node-1",
+                    "container": "app",
+                    "image": "web:1.0",
+                    "owner": {"kind": "Deployment", "name": "web"},
+                    "pod_ip": "10.0.0.5",
+                    "namespace_labels": {"team": "web"},
+                }
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_requests_lookups() {
+        let file = |pod: &str| {
+            format!(
+                "/var/log/containers/{}_prod_app-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log",
+                pod
+            )
+        };
+        let mut map = HashMap::new();
+        map.insert(("api".into(), "prod".into()), get_pod_metadata());
+        let mut k8s_meta = get_instance(map);
+        let (lookup_sender, mut receiver) = unbounded();
+        k8s_meta.lookup_sender = lookup_sender;
+
+        // The line of a missing pod is passed through without waiting for its lookup
+        let mut line = LineBuilder::new().line("a secret").file(file("web"));
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.line.as_deref(), Some("a secret"));
+        assert!(line.get_annotations().is_none());
+        assert_eq!(
+            receiver.try_next().unwrap(),
+            Some(("web".to_string(), "prod".to_string()))
+        );
+
+        // Its metadata applies to its lines once the lookup fills the cache
+        let mut pod_meta_data = get_pod_metadata();
+        pod_meta_data
+            .annotations
+            .insert(REDACT_ANNOTATION.into(), "secret".into());
+        k8s_meta
+            .metadata
+            .lock()
+            .insert(("web".into(), "prod".into()), pod_meta_data);
+        let mut line = LineBuilder::new().line("a secret").file(file("web"));
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.line.as_deref(), Some("a [REDACTED]"));
+        assert!(line.get_annotations().is_some());
+
+        // The missing pod is looked up once
+        for _ in 0..3 {
+            let mut line = LineBuilder::new().line("abc").file(file("db"));
+            assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+            assert!(line.get_annotations().is_none());
+        }
+        let mut line = LineBuilder::new().line("abc").file(file("api"));
+        k8s_meta.process(&mut line);
+
+        assert_eq!(
+            receiver.try_next().unwrap(),
+            Some(("db".to_string(), "prod".to_string()))
+        );
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn test_pod_params() {
+        assert_eq!(pod_params(None).field_selector, None);
+        assert_eq!(
+            pod_params(Some("node-1")).field_selector.as_deref(),
+            Some("spec.nodeName=node-1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_applies_hints() {
+        let file = "/var/log/containers/web_prod_app-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log";
+        let mut pod_meta_data = get_pod_metadata();
+        pod_meta_data
+            .annotations
+            .insert(APP_ANNOTATION.into(), "storefront".into());
+        pod_meta_data
+            .annotations
+            .insert(MULTILINE_PATTERN_ANNOTATION.into(), "^\\d".into());
+        let mut map = HashMap::new();
+        map.insert(("web".into(), "prod".into()), pod_meta_data);
+        let k8s_meta = get_instance(map);
+
+        let hints = k8s_meta.file_hints(file).unwrap();
+        assert_eq!(hints.multiline_pattern(), Some("^\\d"));
+        assert!(k8s_meta.file_hints("/var/log/syslog").is_none());
+
+        // The hints read when the file was first tailed are kept
+        k8s_meta
+            .metadata
+            .lock()
+            .get_mut(&("web".to_string(), "prod".to_string()))
+            .unwrap()
+            .annotations
+            .clear();
+        let mut line = LineBuilder::new().line("abc").file(file);
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_app(), Some("storefront"));
+    }
+
     #[tokio::test]
     async fn test_init_max_elapsed_time() {
         let config = Config::new("https://127.0.0.10/".parse::<Uri>().unwrap());
@@ -331,7 +876,7 @@ mod tests {
 
         // It error out
         assert!(matches!(
-            K8sMetadata::initialize(&client, max_time).await,
+            K8sMetadata::initialize(&client, &ListParams::default(), max_time).await,
             Err(_)
         ));
 
@@ -342,9 +887,22 @@ mod tests {
 
     fn get_instance(map: HashMap<(String, String), PodMetadata>) -> K8sMetadata {
         let config = Config::new("https://sample.url/".parse::<Uri>().unwrap());
+        let client = Client::try_from(config).unwrap();
+        // Pods missing from the cache are not looked up
+        let (lookup_sender, _) = unbounded();
         K8sMetadata {
             metadata: Mutex::new(map),
-            api: Api::<Pod>::all(Client::try_from(config).unwrap()),
+            api: Api::<Pod>::all(client.clone()),
+            client: client.clone(),
+            node_name: None,
+            lookups: Mutex::new(HashMap::new()),
+            lookup_sender,
+            lookup_receiver: Mutex::new(None),
+            filter: PodFilter::default(),
+            fields: Vec::new(),
+            namespaces: Mutex::new(HashMap::new()),
+            namespace_api: Api::<Namespace>::all(client),
+            hints: Mutex::new(HashMap::new()),
         }
     }
 
@@ -354,6 +912,10 @@ mod tests {
             namespace: "sample ns".to_string(),
             labels: Default::default(),
             annotations: Default::default(),
+            node: None,
+            pod_ip: None,
+            images: Default::default(),
+            owner: None,
         }
     }
 }
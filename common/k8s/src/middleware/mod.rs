@@ -1,7 +1,13 @@
 use regex::Regex;
 
+mod enrichment;
+mod filter;
+mod hints;
 mod metadata;
 
+pub use enrichment::*;
+pub use filter::*;
+pub use hints::*;
 pub use metadata::*;
 
 lazy_static! {
@@ -10,10 +16,12 @@ lazy_static! {
     ).unwrap_or_else(|e| panic!("K8S_REG Regex::new() failed: {}", e));
 }
 
-fn parse_container_path(path: &str) -> Option<(String, String)> {
+/// Returns the pod name, namespace and container name of a container log file
+fn parse_container_path(path: &str) -> Option<(String, String, String)> {
     let captures = K8S_REG.captures(path)?;
     Some((
         captures.get(1)?.as_str().into(),
         captures.get(2)?.as_str().into(),
+        captures.get(3)?.as_str().into(),
     ))
 }
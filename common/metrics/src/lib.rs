@@ -32,6 +32,11 @@ lazy_static! {
         "Retry attempts made to the http ingestion service"
     )
     .unwrap();
+    static ref INGEST_RETRIES_EVICTED: IntCounter = register_int_counter!(
+        "logdna_agent_ingest_retries_evicted",
+        "Retry files discarded to keep the retry directory within its limits"
+    )
+    .unwrap();
     static ref INGEST_RATE_LIMIT_HITS: IntCounter = register_int_counter!(
         "logdna_agent_ingest_rate_limit_hits",
         "Number of times the http request was delayed due to the rate limiter"
@@ -60,6 +65,12 @@ lazy_static! {
     .unwrap();
     static ref K8S_LINES: IntCounter =
         register_int_counter!("logdna_agent_k8s_lines", "Kubernetes event lines read").unwrap();
+    static ref DROPPED_LINES: IntCounterVec = register_int_counter_vec!(
+        "logdna_agent_dropped_lines",
+        "Lines dropped by the agent before being sent",
+        &["reason"]
+    )
+    .unwrap();
     static ref JOURNAL_RECORDS: Histogram = register_histogram!(
         "logdna_agent_journald_records",
         "Size of the Journald log entries read"
@@ -74,6 +85,11 @@ mod labels {
     pub const SUCCESS: &str = "success";
     pub const FAILURE: &str = "failure";
     pub const TIMEOUT: &str = "timeout";
+    pub const RATE_LIMITED: &str = "rate_limited";
+    pub const SAMPLED: &str = "sampled";
+    pub const DEDUPLICATED: &str = "deduplicated";
+    pub const LEVEL_FILTERED: &str = "level_filtered";
+    pub const K8S_FILTERED: &str = "k8s_filtered";
 }
 
 pub struct Metrics {
@@ -82,6 +98,7 @@ pub struct Metrics {
     http: Http,
     k8s: K8s,
     journald: Journald,
+    middleware: Middleware,
 }
 
 impl Metrics {
@@ -92,6 +109,7 @@ impl Metrics {
             http: Http::new(),
             k8s: K8s::new(),
             journald: Journald::new(),
+            middleware: Middleware::new(),
         }
     }
 
@@ -122,6 +140,10 @@ impl Metrics {
         &METRICS.journald
     }
 
+    pub fn middleware() -> &'static Middleware {
+        &METRICS.middleware
+    }
+
     pub fn print() -> String {
         let memory = Metrics::memory();
 
@@ -158,6 +180,7 @@ impl Metrics {
                 "requests_size" => INGEST_REQUEST_SIZE.get_sample_sum(),
                 "rate_limits" => INGEST_RATE_LIMIT_HITS.get(),
                 "retries" => INGEST_RETRIES.get(),
+                "retries_evicted" => INGEST_RETRIES_EVICTED.get(),
                 // The request duration is exported as a histogram in Prometheus,
                 // in this output is a simple sum
                 "requests_duration" => latency_success.get_sample_sum() + latency_failure.get_sample_sum() + latency_timeout.get_sample_sum(),
@@ -175,6 +198,13 @@ impl Metrics {
                 "lines" => JOURNAL_RECORDS.get_sample_count(),
                 "bytes" => JOURNAL_RECORDS.get_sample_sum(),
             },
+            "dropped_lines" => object!{
+                "rate_limited" => DROPPED_LINES.with_label_values(&[labels::RATE_LIMITED]).get(),
+                "sampled" => DROPPED_LINES.with_label_values(&[labels::SAMPLED]).get(),
+                "deduplicated" => DROPPED_LINES.with_label_values(&[labels::DEDUPLICATED]).get(),
+                "level_filtered" => DROPPED_LINES.with_label_values(&[labels::LEVEL_FILTERED]).get(),
+                "k8s_filtered" => DROPPED_LINES.with_label_values(&[labels::K8S_FILTERED]).get(),
+            },
         };
 
         object.to_string()
@@ -298,6 +328,10 @@ impl Http {
     pub fn increment_retries(&self) {
         INGEST_RETRIES.inc();
     }
+
+    pub fn add_retry_evictions(&self, num: u64) {
+        INGEST_RETRIES_EVICTED.inc_by(num);
+    }
 }
 
 #[derive(Default)]
@@ -334,6 +368,43 @@ impl Journald {
     }
 }
 
+#[derive(Default)]
+pub struct Middleware {}
+
+impl Middleware {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn increment_rate_limited(&self) {
+        DROPPED_LINES
+            .with_label_values(&[labels::RATE_LIMITED])
+            .inc();
+    }
+
+    pub fn increment_sampled(&self) {
+        DROPPED_LINES.with_label_values(&[labels::SAMPLED]).inc();
+    }
+
+    pub fn increment_deduplicated(&self) {
+        DROPPED_LINES
+            .with_label_values(&[labels::DEDUPLICATED])
+            .inc();
+    }
+
+    pub fn increment_level_filtered(&self) {
+        DROPPED_LINES
+            .with_label_values(&[labels::LEVEL_FILTERED])
+            .inc();
+    }
+
+    pub fn increment_k8s_filtered(&self) {
+        DROPPED_LINES
+            .with_label_values(&[labels::K8S_FILTERED])
+            .inc();
+    }
+}
+
 fn elapsed(start: Instant) -> f64 {
     start.elapsed().as_micros() as f64 / 1_000.0
 }
@@ -364,9 +435,15 @@ mod tests {
             .add_request_timeout(Instant::now().sub(Duration::from_micros(20137)));
         METRICS.http.increment_limit_hits();
         METRICS.http.increment_retries();
+        METRICS.http.add_retry_evictions(2);
         METRICS.journald.add_bytes(32);
         METRICS.k8s.increment_lines();
         METRICS.k8s.increment_deletes();
+        METRICS.middleware.increment_rate_limited();
+        METRICS.middleware.increment_sampled();
+        METRICS.middleware.increment_deduplicated();
+        METRICS.middleware.increment_level_filtered();
+        METRICS.middleware.increment_k8s_filtered();
         METRICS.k8s.increment_creates();
         let result = Metrics::print();
         assert!(result.starts_with('{') && result.ends_with('}'));
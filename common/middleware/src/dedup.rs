@@ -0,0 +1,350 @@
+use crate::routing::copy_line;
+use crate::{merge_meta, LineMut, Middleware, Status, TimestampedLine};
+use chrono::Utc;
+use http::types::body::LineBuilder;
+use metrics::Metrics;
+use regex::bytes::Regex;
+use serde_json::Map;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The time after which a repeated line is sent again
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(30);
+
+/// The number of sources tracked by default
+pub const DEFAULT_MAX_SOURCES: usize = 10_000;
+
+/// The consecutive identical lines of a source
+struct Run {
+    hash: u64,
+    started: Instant,
+    last_seen: Instant,
+    repeated: u64,
+    /// A copy of the first dropped line, the summary keeps its fields
+    summary: Option<LineBuilder>,
+    /// The timestamp of the last dropped line, the summary is sent with it
+    timestamp: Option<i64>,
+}
+
+impl Run {
+    fn new(hash: u64, now: Instant) -> Self {
+        Run {
+            hash,
+            started: now,
+            last_seen: now,
+            repeated: 0,
+            summary: None,
+            timestamp: None,
+        }
+    }
+
+    /// Creates the line reporting how many times the first line of the run was repeated
+    fn into_summary(self) -> Option<TimestampedLine> {
+        let mut summary = self
+            .summary?
+            .line(format!("last message repeated {} times", self.repeated));
+        let mut meta = Map::new();
+        meta.insert("repeated".into(), self.repeated.into());
+        if let Err(e) = merge_meta(&mut summary, meta) {
+            log::warn!("unable to set the repeated line meta: {:?}", e);
+        }
+        Some(TimestampedLine {
+            line: summary,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+#[derive(Default)]
+struct State {
+    runs: HashMap<String, Run>,
+    summaries: Vec<TimestampedLine>,
+    swept: Option<Instant>,
+}
+
+impl State {
+    fn end_run(&mut self, run: Run) {
+        if let Some(summary) = run.into_summary() {
+            self.summaries.push(summary);
+        }
+    }
+
+    /// Ends the runs that weren't repeated within the window so that their summary isn't
+    /// held until the source writes again, it's also called when the summaries are taken so
+    /// that the sources that stopped writing are summarized
+    fn sweep(&mut self, window: Duration, now: Instant) {
+        if self
+            .swept
+            .map_or(false, |swept| now.saturating_duration_since(swept) < window)
+        {
+            return;
+        }
+        self.swept = Some(now);
+
+        let expired: Vec<String> = self
+            .runs
+            .iter()
+            .filter(|(_, run)| now.saturating_duration_since(run.last_seen) >= window)
+            .map(|(source, _)| source.clone())
+            .collect();
+        for source in expired {
+            if let Some(run) = self.runs.remove(&source) {
+                self.end_run(run);
+            }
+        }
+    }
+
+    /// Makes room for a new source by ending the run of the least recently seen one
+    fn evict(&mut self) {
+        let oldest = self
+            .runs
+            .iter()
+            .min_by_key(|(_, run)| run.last_seen)
+            .map(|(source, _)| source.clone());
+        if let Some(run) = oldest.and_then(|source| self.runs.remove(&source)) {
+            self.end_run(run);
+        }
+    }
+}
+
+/// Collapses the consecutive identical lines of each source into the first line, followed by
+/// a line with the number of times it was repeated, like syslog's "last message repeated N
+/// times".
+///
+/// Lines are compared once the matches of the normalization regexes, such as timestamps or
+/// request ids, are removed. Only the hash of the last line of each source is kept, up to
+/// `max_sources` sources. The summary has the timestamp of the last repeated line, lines
+/// that don't hold their own timestamp use the time they were processed.
+pub struct Dedup {
+    window: Duration,
+    normalize: Vec<Regex>,
+    max_sources: usize,
+    state: Mutex<State>,
+}
+
+impl Dedup {
+    pub fn new(
+        window: Duration,
+        normalize_regex: &[String],
+        max_sources: usize,
+    ) -> Result<Self, regex::Error> {
+        Ok(Dedup {
+            window,
+            normalize: normalize_regex
+                .iter()
+                .map(|r| Regex::new(r))
+                .collect::<Result<_, _>>()?,
+            max_sources: max_sources.max(1),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn source(line: &mut dyn LineMut) -> Option<String> {
+        if let Some(file) = line.get_file() {
+            return Some(file.to_string());
+        }
+        match (line.get_host(), line.get_app()) {
+            (None, None) => None,
+            (host, app) => Some(format!(
+                "{}/{}",
+                host.unwrap_or_default(),
+                app.unwrap_or_default()
+            )),
+        }
+    }
+
+    fn hash(&self, line: &[u8]) -> u64 {
+        let mut normalized = line.to_vec();
+        for regex in &self.normalize {
+            normalized = regex.replace_all(&normalized, &b""[..]).into_owned();
+        }
+        let mut hasher = DefaultHasher::new();
+        normalized.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn process_at<'a>(
+        &self,
+        line: &'a mut dyn LineMut,
+        now: Instant,
+    ) -> Status<&'a mut dyn LineMut> {
+        let source = match Self::source(line) {
+            Some(source) => source,
+            None => return Status::Ok(line),
+        };
+        let hash = match line.get_line_buffer() {
+            Some(buf) => self.hash(buf),
+            None => return Status::Ok(line),
+        };
+
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+        state.sweep(self.window, now);
+
+        let window = self.window;
+        let repeated = match state.runs.get_mut(&source) {
+            Some(run)
+                if run.hash == hash && now.saturating_duration_since(run.started) < window =>
+            {
+                run.repeated += 1;
+                run.last_seen = now;
+                run.timestamp = Some(
+                    line.get_timestamp()
+                        .unwrap_or_else(|| Utc::now().timestamp()),
+                );
+                if run.summary.is_none() {
+                    run.summary = copy_line(line);
+                }
+                true
+            }
+            _ => false,
+        };
+        if repeated {
+            Metrics::middleware().increment_deduplicated();
+            return Status::Skip;
+        }
+
+        // The line starts a new run, the summary of the previous one goes before it
+        if let Some(run) = state.runs.remove(&source) {
+            state.end_run(run);
+        } else if state.runs.len() >= self.max_sources {
+            state.evict();
+        }
+        state.runs.insert(source, Run::new(hash, now));
+        Status::Ok(line)
+    }
+
+    fn take_lines_at(&self, now: Instant) -> Vec<TimestampedLine> {
+        let mut state = self.state.lock().expect("dedup lock poisoned");
+        state.sweep(self.window, now);
+        std::mem::take(&mut state.summaries)
+    }
+}
+
+impl Middleware for Dedup {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        self.process_at(line, Instant::now())
+    }
+
+    fn take_lines(&self) -> Vec<TimestampedLine> {
+        self.take_lines_at(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBufferMut, LineMeta};
+    use serde_json::Value;
+
+    fn kept(dedup: &Dedup, line: &str, file: &str, now: Instant) -> bool {
+        let mut line = LineBuilder::new().line(line).file(file);
+        matches!(dedup.process_at(&mut line, now), Status::Ok(_))
+    }
+
+    fn summaries(dedup: &Dedup) -> Vec<(String, Option<Value>)> {
+        dedup
+            .take_lines()
+            .into_iter()
+            .map(|TimestampedLine { mut line, .. }| {
+                let text = String::from_utf8(line.get_line_buffer().unwrap().to_vec()).unwrap();
+                (text, line.get_meta().cloned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collapses_consecutive_lines() {
+        let dedup = Dedup::new(Duration::from_secs(10), &[], 100).unwrap();
+        let now = Instant::now();
+
+        assert!(kept(&dedup, "connection refused", "/var/log/a.log", now));
+        for _ in 0..3 {
+            assert!(!kept(&dedup, "connection refused", "/var/log/a.log", now));
+        }
+        // Other sources are tracked separately
+        assert!(kept(&dedup, "connection refused", "/var/log/b.log", now));
+        assert!(summaries(&dedup).is_empty());
+
+        assert!(kept(&dedup, "started", "/var/log/a.log", now));
+        assert_eq!(
+            summaries(&dedup),
+            vec![(
+                "last message repeated 3 times".to_string(),
+                Some(serde_json::json!({"repeated": 3}))
+            )]
+        );
+        // Lines that were not repeated have no summary
+        assert!(kept(&dedup, "stopped", "/var/log/a.log", now));
+        assert!(summaries(&dedup).is_empty());
+    }
+
+    #[test]
+    fn ends_runs_after_the_window() {
+        let dedup = Dedup::new(Duration::from_secs(10), &[], 100).unwrap();
+        let now = Instant::now();
+
+        assert!(kept(&dedup, "retrying", "/var/log/a.log", now));
+        assert!(!kept(&dedup, "retrying", "/var/log/a.log", now));
+        let later = now + Duration::from_secs(10);
+        assert!(kept(&dedup, "retrying", "/var/log/a.log", later));
+        assert_eq!(summaries(&dedup).len(), 1);
+
+        // Idle sources are summarized when any line is processed after the window
+        assert!(!kept(&dedup, "retrying", "/var/log/a.log", later));
+        let idle = later + Duration::from_secs(20);
+        assert!(kept(&dedup, "other", "/var/log/b.log", idle));
+        assert_eq!(summaries(&dedup).len(), 1);
+    }
+
+    #[test]
+    fn summarizes_idle_sources_when_taking_lines() {
+        let dedup = Dedup::new(Duration::from_secs(10), &[], 100).unwrap();
+        let now = Instant::now();
+        let start = Utc::now().timestamp();
+
+        assert!(kept(&dedup, "retrying", "/var/log/a.log", now));
+        assert!(!kept(&dedup, "retrying", "/var/log/a.log", now));
+        assert!(dedup.take_lines_at(now).is_empty());
+
+        // The source stopped writing, its summary is taken once the window is over
+        let summaries = dedup.take_lines_at(now + Duration::from_secs(10));
+        assert_eq!(summaries.len(), 1);
+        // Lines without their own timestamp use the time they were dropped
+        assert!(summaries[0].timestamp.unwrap() >= start);
+    }
+
+    #[test]
+    fn compares_normalized_lines() {
+        let dedup = Dedup::new(
+            Duration::from_secs(10),
+            &[r"^\d{4}-\d{2}-\d{2}T\S+ ".to_string()],
+            100,
+        )
+        .unwrap();
+        let now = Instant::now();
+
+        assert!(kept(&dedup, "2021-05-01T10:00:00Z failed", "a.log", now));
+        assert!(!kept(&dedup, "2021-05-01T10:00:01Z failed", "a.log", now));
+        assert!(kept(&dedup, "2021-05-01T10:00:02Z passed", "a.log", now));
+    }
+
+    #[test]
+    fn bounds_the_tracked_sources() {
+        let dedup = Dedup::new(Duration::from_secs(10), &[], 2).unwrap();
+        let now = Instant::now();
+
+        assert!(kept(&dedup, "a", "a.log", now));
+        assert!(!kept(&dedup, "a", "a.log", now));
+        assert!(kept(&dedup, "b", "b.log", now + Duration::from_secs(1)));
+        // The least recently seen source is evicted, reporting its repeated lines
+        assert!(kept(&dedup, "c", "c.log", now + Duration::from_secs(2)));
+        assert_eq!(dedup.state.lock().unwrap().runs.len(), 2);
+        assert_eq!(summaries(&dedup).len(), 1);
+        assert!(kept(&dedup, "a", "a.log", now + Duration::from_secs(3)));
+    }
+}
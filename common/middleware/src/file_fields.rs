@@ -0,0 +1,234 @@
+use crate::{merge_meta, LineMut, Middleware, Status};
+use globber::Pattern;
+use http::types::body::KeyValueMap;
+use http::types::error::LineMetaError;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FileFieldsError {
+    #[error("invalid file fields glob: {0}")]
+    Glob(globber::Error),
+    #[error("invalid file fields regex: {0}")]
+    Regex(regex::Error),
+    #[error("file fields rules need either a glob or a regex")]
+    Matcher,
+}
+
+/// The fields set on the lines of the matching files, the values can reference the named
+/// and numbered capture groups of the regex as `$name` or `${1}`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldTemplates {
+    pub app: Option<String>,
+    pub env: Option<String>,
+    pub meta: BTreeMap<String, String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+enum FileMatcher {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+/// Sets fields on the lines of the files matching either a glob or a regex
+#[derive(Debug)]
+pub struct FileFieldRule {
+    matcher: FileMatcher,
+    fields: FieldTemplates,
+}
+
+impl FileFieldRule {
+    pub fn new(
+        glob: Option<&str>,
+        regex: Option<&str>,
+        fields: FieldTemplates,
+    ) -> Result<Self, FileFieldsError> {
+        let matcher = match (glob, regex) {
+            (Some(glob), None) => {
+                FileMatcher::Glob(Pattern::new(glob).map_err(FileFieldsError::Glob)?)
+            }
+            (None, Some(regex)) => {
+                FileMatcher::Regex(Regex::new(regex).map_err(FileFieldsError::Regex)?)
+            }
+            _ => return Err(FileFieldsError::Matcher),
+        };
+        Ok(FileFieldRule { matcher, fields })
+    }
+
+    /// Returns the fields for the file, when it matches the rule
+    fn fields(&self, file: &str) -> Option<FieldTemplates> {
+        match &self.matcher {
+            FileMatcher::Glob(glob) if glob.matches(file) => Some(self.fields.clone()),
+            FileMatcher::Glob(_) => None,
+            FileMatcher::Regex(regex) => {
+                let captures = regex.captures(file)?;
+                let expand = |template: &String| {
+                    let mut value = String::new();
+                    captures.expand(template, &mut value);
+                    value
+                };
+                let expand_map = |templates: &BTreeMap<String, String>| -> BTreeMap<_, _> {
+                    templates
+                        .iter()
+                        .map(|(key, template)| (key.clone(), expand(template)))
+                        .collect()
+                };
+                Some(FieldTemplates {
+                    app: self.fields.app.as_ref().map(expand),
+                    env: self.fields.env.as_ref().map(expand),
+                    meta: expand_map(&self.fields.meta),
+                    labels: expand_map(&self.fields.labels),
+                })
+            }
+        }
+    }
+}
+
+/// Sets the app, env, meta and labels of the lines from the name of their file, using the
+/// first matching rule
+pub struct FileFields {
+    rules: Vec<FileFieldRule>,
+}
+
+impl FileFields {
+    pub fn new(rules: Vec<FileFieldRule>) -> Self {
+        FileFields { rules }
+    }
+
+    fn apply(fields: FieldTemplates, line: &mut dyn LineMut) -> Result<(), LineMetaError> {
+        // Empty values come from the capture groups that didn't match
+        if let Some(app) = fields.app.filter(|app| !app.is_empty()) {
+            line.set_app(app)?;
+        }
+        if let Some(env) = fields.env.filter(|env| !env.is_empty()) {
+            line.set_env(env)?;
+        }
+        if !fields.meta.is_empty() {
+            let meta = fields
+                .meta
+                .into_iter()
+                .map(|(key, value)| (key, Value::String(value)))
+                .collect();
+            merge_meta(line, meta)?;
+        }
+        if !fields.labels.is_empty() {
+            // Keep the labels already set on the line, such as the pod labels
+            let labels = fields.labels.into_iter().fold(
+                line.get_labels().cloned().unwrap_or_else(KeyValueMap::new),
+                |labels, (key, value)| labels.add(key, value),
+            );
+            line.set_labels(labels)?;
+        }
+        Ok(())
+    }
+}
+
+impl Middleware for FileFields {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        let fields = match line
+            .get_file()
+            .and_then(|file| self.rules.iter().find_map(|rule| rule.fields(file)))
+        {
+            Some(fields) => fields,
+            None => return Status::Ok(line),
+        };
+        // The line is kept with the fields that could be set
+        if let Err(e) = Self::apply(fields, line) {
+            log::debug!("unable to set the file fields of the line: {:?}", e);
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta, LineMetaMut};
+    use serde_json::json;
+
+    fn templates(app: &str, meta: &[(&str, &str)]) -> FieldTemplates {
+        FieldTemplates {
+            app: Some(app.to_string()),
+            meta: meta
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..FieldTemplates::default()
+        }
+    }
+
+    #[test]
+    fn sets_fields_from_regex_captures() {
+        let file_fields = FileFields::new(vec![FileFieldRule::new(
+            None,
+            Some(r"^/var/log/(?P<app>[^/]+)/(?P<env>\w+)\.log$"),
+            FieldTemplates {
+                env: Some("$env".to_string()),
+                ..templates("$app", &[("team", "payments"), ("source", "${app}-${env}")])
+            },
+        )
+        .unwrap()]);
+
+        let mut line = LineBuilder::new()
+            .line("started")
+            .file("/var/log/billing/prod.log");
+        assert!(matches!(file_fields.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_app(), Some("billing"));
+        assert_eq!(line.get_env(), Some("prod"));
+        assert_eq!(
+            line.get_meta(),
+            Some(&json!({"team": "payments", "source": "billing-prod"}))
+        );
+
+        let mut line = LineBuilder::new().line("started").file("/var/log/syslog");
+        assert!(matches!(file_fields.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_app(), None);
+    }
+
+    #[test]
+    fn uses_the_first_matching_rule() {
+        let file_fields = FileFields::new(vec![
+            FileFieldRule::new(Some("/var/log/nginx/*"), None, templates("nginx", &[])).unwrap(),
+            FileFieldRule::new(Some("/var/log/**"), None, templates("other", &[])).unwrap(),
+        ]);
+
+        let mut line = LineBuilder::new()
+            .line("GET /")
+            .file("/var/log/nginx/access.log");
+        file_fields.process(&mut line);
+        assert_eq!(line.get_app(), Some("nginx"));
+
+        let mut line = LineBuilder::new().line("a").file("/var/log/app.log");
+        file_fields.process(&mut line);
+        assert_eq!(line.get_app(), Some("other"));
+    }
+
+    #[test]
+    fn keeps_existing_meta() {
+        let file_fields = FileFields::new(vec![FileFieldRule::new(
+            Some("*.log"),
+            None,
+            templates("app", &[("team", "core")]),
+        )
+        .unwrap()]);
+        let mut line = LineBuilder::new().line("a").file("a.log");
+        line.set_meta(json!({"stream": "stdout"})).unwrap();
+        file_fields.process(&mut line);
+        assert_eq!(
+            line.get_meta(),
+            Some(&json!({"stream": "stdout", "team": "core"}))
+        );
+    }
+
+    #[test]
+    fn requires_a_single_matcher() {
+        let fields = FieldTemplates::default();
+        assert!(FileFieldRule::new(None, None, fields.clone()).is_err());
+        assert!(FileFieldRule::new(Some("*"), Some(".*"), fields).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{merge_meta, LineMut, Middleware, Status};
+use chrono::DateTime;
+use http::types::error::LineMetaError;
+use serde_json::Value;
+
+/// Timestamps above this value are assumed to be in milliseconds rather than seconds
+const MAX_TIMESTAMP_SECS: i64 = 100_000_000_000;
+
+/// The fields of a JSON line to lift into the line metadata
+///
+/// Nested fields are referenced with a dotted path, e.g. `log.level`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JsonFieldNames {
+    pub level: Option<String>,
+    pub app: Option<String>,
+    pub meta: Option<String>,
+    pub timestamp: Option<String>,
+    pub message: Option<String>,
+}
+
+/// Extracts metadata from the lines that are JSON objects
+pub struct JsonFields {
+    level: Option<Vec<String>>,
+    app: Option<Vec<String>>,
+    meta: Option<Vec<String>>,
+    timestamp: Option<Vec<String>>,
+    message: Option<Vec<String>>,
+    rewrite_message: bool,
+}
+
+fn split_path(path: Option<String>) -> Option<Vec<String>> {
+    path.filter(|path| !path.is_empty())
+        .map(|path| path.split('.').map(String::from).collect())
+}
+
+fn lookup<'a>(value: &'a Value, path: &Option<Vec<String>>) -> Option<&'a Value> {
+    path.as_ref()?
+        .iter()
+        .try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn as_timestamp(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => {
+            let timestamp = n.as_i64().or_else(|| n.as_f64().map(|f| f as i64))?;
+            if timestamp > MAX_TIMESTAMP_SECS {
+                Some(timestamp / 1000)
+            } else {
+                Some(timestamp)
+            }
+        }
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|timestamp| timestamp.timestamp()),
+        _ => None,
+    }
+}
+
+impl JsonFields {
+    /// Creates a new JsonFields middleware, when rewrite_message is set the line is replaced
+    /// by the value of the message field
+    pub fn new(names: JsonFieldNames, rewrite_message: bool) -> Self {
+        JsonFields {
+            level: split_path(names.level),
+            app: split_path(names.app),
+            meta: split_path(names.meta),
+            timestamp: split_path(names.timestamp),
+            message: split_path(names.message),
+            rewrite_message,
+        }
+    }
+
+    fn process_value(&self, value: Value, line: &mut dyn LineMut) -> Result<(), LineMetaError> {
+        if let Some(level) = lookup(&value, &self.level).and_then(as_string) {
+            line.set_level(level)?;
+        }
+
+        if let Some(app) = lookup(&value, &self.app).and_then(as_string) {
+            line.set_app(app)?;
+        }
+
+        if let Some(Value::Object(lifted)) = lookup(&value, &self.meta) {
+            merge_meta(line, lifted.clone())?;
+        }
+
+        if let Some(timestamp) = lookup(&value, &self.timestamp).and_then(as_timestamp) {
+            line.set_timestamp(timestamp)?;
+        }
+
+        if self.rewrite_message {
+            if let Some(Value::String(message)) = lookup(&value, &self.message) {
+                line.set_line_buffer(message.clone().into_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Middleware for JsonFields {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        let value = match line.get_line_buffer() {
+            Some(buf) if buf.starts_with(b"{") => serde_json::from_slice::<Value>(buf),
+            _ => return Status::Ok(line),
+        };
+
+        match value {
+            Ok(value @ Value::Object(_)) => {
+                // The line is kept with the fields that could be set
+                if let Err(e) = self.process_value(value, line) {
+                    log::debug!("unable to set the json fields of the line: {:?}", e);
+                }
+                Status::Ok(line)
+            }
+            _ => Status::Ok(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimestampedLine;
+    use http::types::body::{LineBuilder, LineMetaMut};
+    use serde_json::json;
+
+    fn names() -> JsonFieldNames {
+        JsonFieldNames {
+            level: Some("level".into()),
+            app: Some("service.name".into()),
+            meta: Some("context".into()),
+            timestamp: Some("ts".into()),
+            message: Some("msg".into()),
+        }
+    }
+
+    #[test]
+    fn lifts_fields() {
+        let fields = JsonFields::new(names(), false);
+        let raw = r#"{"level":"warn","service":{"name":"api"},"context":{"user":1},"msg":"hi"}"#;
+        let mut line = LineBuilder::new().line(raw);
+
+        match fields.process(&mut line) {
+            Status::Ok(l) => {
+                assert_eq!(l.get_level(), Some("warn"));
+                assert_eq!(l.get_app(), Some("api"));
+                assert_eq!(l.get_meta(), Some(&json!({"user": 1})));
+                // The line is kept as is
+                assert_eq!(l.get_line_buffer().unwrap(), raw.as_bytes());
+            }
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn rewrites_message() {
+        let fields = JsonFields::new(names(), true);
+        let mut line = LineBuilder::new().line(r#"{"level":"info","msg":"hello world"}"#);
+
+        match fields.process(&mut line) {
+            Status::Ok(l) => {
+                assert_eq!(l.get_level(), Some("info"));
+                assert_eq!(l.get_line_buffer().unwrap(), b"hello world");
+            }
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn merges_existing_meta() {
+        let fields = JsonFields::new(names(), false);
+        let mut line = LineBuilder::new().line(r#"{"context":{"user":1}}"#);
+        line.set_meta(json!({"stream": "stdout"})).unwrap();
+
+        match fields.process(&mut line) {
+            Status::Ok(l) => {
+                assert_eq!(l.get_meta(), Some(&json!({"stream": "stdout", "user": 1})));
+            }
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        let fields = JsonFields::new(names(), true);
+        for raw in &["plain text", "{not json", r#"["an", "array"]"#] {
+            let mut line = LineBuilder::new().line(*raw);
+            match fields.process(&mut line) {
+                Status::Ok(l) => {
+                    assert_eq!(l.get_line_buffer().unwrap(), raw.as_bytes());
+                    assert!(l.get_level().is_none());
+                }
+                Status::Skip => panic!("should not have been skipped"),
+            }
+        }
+    }
+
+    #[test]
+    fn sets_the_timestamp_of_built_lines() {
+        let fields = JsonFields::new(names(), false);
+        let mut line = TimestampedLine {
+            line: LineBuilder::new().line(r#"{"ts":1619870400,"msg":"hi"}"#),
+            timestamp: None,
+        };
+        assert!(matches!(fields.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.timestamp, Some(1_619_870_400));
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(as_timestamp(&json!(1_619_870_400)), Some(1_619_870_400));
+        assert_eq!(
+            as_timestamp(&json!(1_619_870_400_123i64)),
+            Some(1_619_870_400)
+        );
+        assert_eq!(as_timestamp(&json!(1_619_870_400.5)), Some(1_619_870_400));
+        assert_eq!(
+            as_timestamp(&json!("2021-05-01T12:00:00.123Z")),
+            Some(1_619_870_400)
+        );
+        assert_eq!(as_timestamp(&json!("yesterday")), None);
+    }
+}
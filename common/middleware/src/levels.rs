@@ -0,0 +1,293 @@
+use crate::{LineMut, Middleware, Status};
+use globber::Pattern;
+use metrics::Metrics;
+use regex::bytes::Regex;
+use serde_json::Value;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The fields of JSON lines holding the level
+const JSON_LEVEL_FIELDS: [&str; 3] = ["level", "severity", "lvl"];
+
+/// The meta field holding the syslog priority of journald entries
+const PRIORITY_FIELD: &str = "PRIORITY";
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("unknown level: {0}")]
+    Level(String),
+    #[error("invalid level glob: {0}")]
+    Glob(globber::Error),
+}
+
+/// The severity of a line, ordered from the least to the most severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl Level {
+    /// The name set on the lines, the same as the syslog severity names
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Notice => "NOTICE",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR",
+            Level::Critical => "CRITICAL",
+            Level::Alert => "ALERT",
+            Level::Emergency => "EMERGENCY",
+        }
+    }
+
+    /// Maps a syslog severity, as used by journald's `PRIORITY`
+    pub fn from_priority(priority: u64) -> Option<Level> {
+        match priority {
+            0 => Some(Level::Emergency),
+            1 => Some(Level::Alert),
+            2 => Some(Level::Critical),
+            3 => Some(Level::Error),
+            4 => Some(Level::Warning),
+            5 => Some(Level::Notice),
+            6 => Some(Level::Info),
+            7 => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = LevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "trace" => Ok(Level::Trace),
+            "debug" | "dbg" => Ok(Level::Debug),
+            "info" | "information" | "informational" => Ok(Level::Info),
+            "notice" => Ok(Level::Notice),
+            "warn" | "warning" => Ok(Level::Warning),
+            "error" | "err" => Ok(Level::Error),
+            "critical" | "crit" | "fatal" | "panic" => Ok(Level::Critical),
+            "alert" => Ok(Level::Alert),
+            "emergency" | "emerg" => Ok(Level::Emergency),
+            _ => Err(LevelError::Level(s.to_string())),
+        }
+    }
+}
+
+/// Drops the lines of the matching sources that are less severe than `min_level`
+#[derive(Debug)]
+pub struct MinLevelRule {
+    glob: Option<Pattern>,
+    app: Option<String>,
+    min_level: Level,
+}
+
+impl MinLevelRule {
+    pub fn new(
+        glob: Option<&str>,
+        app: Option<&str>,
+        min_level: Level,
+    ) -> Result<Self, LevelError> {
+        Ok(MinLevelRule {
+            glob: glob
+                .map(Pattern::new)
+                .transpose()
+                .map_err(LevelError::Glob)?,
+            app: app.map(String::from),
+            min_level,
+        })
+    }
+
+    fn matches(&self, line: &mut dyn LineMut) -> bool {
+        if let Some(glob) = self.glob.as_ref() {
+            if !line.get_file().map_or(false, |file| glob.matches(file)) {
+                return false;
+            }
+        }
+        if let Some(app) = self.app.as_ref() {
+            if line.get_app() != Some(app.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Sets the level of the lines that don't have one from their content, and drops the lines
+/// below the minimum level of their source
+pub struct Levels {
+    detect: bool,
+    rules: Vec<MinLevelRule>,
+    /// Matches the levels written in upper case or following `[` or `level=`
+    pattern: Regex,
+}
+
+impl Levels {
+    pub fn new(detect: bool, rules: Vec<MinLevelRule>) -> Self {
+        Levels {
+            detect,
+            rules,
+            pattern: Regex::new(
+                r"\b(TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|ERR|CRIT|CRITICAL|FATAL|PANIC|ALERT|EMERG|EMERGENCY)\b|(?i:(?:\[|\blevel=|\blvl=)(trace|debug|info|notice|warn|warning|error|err|crit|critical|fatal|panic|alert|emerg|emergency))\b",
+            )
+            .expect("the level pattern is valid"),
+        }
+    }
+
+    fn from_meta(line: &mut dyn LineMut) -> Option<Level> {
+        match line.get_meta()?.get(PRIORITY_FIELD)? {
+            Value::Number(n) => Level::from_priority(n.as_u64()?),
+            Value::String(s) => Level::from_priority(s.parse().ok()?),
+            _ => None,
+        }
+    }
+
+    fn from_content(&self, buf: &[u8]) -> Option<Level> {
+        if buf.starts_with(b"{") {
+            if let Ok(Value::Object(object)) = serde_json::from_slice::<Value>(buf) {
+                return JSON_LEVEL_FIELDS
+                    .iter()
+                    .filter_map(|field| object.get(*field)?.as_str())
+                    .find_map(|level| level.parse().ok());
+            }
+        }
+
+        let captures = self.pattern.captures(buf)?;
+        let level = captures.get(1).or_else(|| captures.get(2))?;
+        std::str::from_utf8(level.as_bytes()).ok()?.parse().ok()
+    }
+
+    fn level(&self, line: &mut dyn LineMut) -> Option<Level> {
+        if let Some(level) = line.get_level() {
+            return level.parse().ok();
+        }
+        if !self.detect {
+            return None;
+        }
+
+        let level = match Self::from_meta(line) {
+            Some(level) => level,
+            None => self.from_content(line.get_line_buffer()?)?,
+        };
+        if let Err(e) = line.set_level(level.name().to_string()) {
+            log::warn!("unable to set the line level: {:?}", e);
+        }
+        Some(level)
+    }
+}
+
+impl Middleware for Levels {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        let level = match self.level(line) {
+            Some(level) => level,
+            // Lines without a level are always kept
+            None => return Status::Ok(line),
+        };
+        let below_min_level = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(line))
+            .map_or(false, |rule| level < rule.min_level);
+        if below_min_level {
+            Metrics::middleware().increment_level_filtered();
+            return Status::Skip;
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta, LineMetaMut};
+    use serde_json::json;
+
+    fn detect(line: &str) -> Option<String> {
+        let levels = Levels::new(true, Vec::new());
+        let mut line = LineBuilder::new().line(line);
+        assert!(matches!(levels.process(&mut line), Status::Ok(_)));
+        line.get_level().map(String::from)
+    }
+
+    #[test]
+    fn detects_levels() {
+        assert_eq!(detect("2021-05-01 ERROR failed").as_deref(), Some("ERROR"));
+        assert_eq!(
+            detect("[warn] disk is almost full").as_deref(),
+            Some("WARNING")
+        );
+        assert_eq!(detect("ts=1 level=debug msg=x").as_deref(), Some("DEBUG"));
+        assert_eq!(
+            detect(r#"{"severity": "Info", "msg": "error count is 0"}"#).as_deref(),
+            Some("INFO")
+        );
+        // Lower case words in the message are not levels
+        assert_eq!(detect("no error found"), None);
+        assert_eq!(detect("Errors: 0"), None);
+    }
+
+    #[test]
+    fn detects_journald_priorities() {
+        let levels = Levels::new(true, Vec::new());
+        let mut line = LineBuilder::new().line("Started Session 1");
+        line.set_meta(json!({"PRIORITY": "3"})).unwrap();
+        assert!(matches!(levels.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_level(), Some("ERROR"));
+    }
+
+    #[test]
+    fn filters_below_the_min_level() {
+        let levels = Levels::new(
+            true,
+            vec![
+                MinLevelRule::new(None, Some("chatty"), Level::Warning).unwrap(),
+                MinLevelRule::new(Some("/var/log/**"), None, Level::Debug).unwrap(),
+            ],
+        );
+        let kept = |line: &str, app: &str| {
+            let mut line = LineBuilder::new()
+                .line(line)
+                .file("/var/log/app.log")
+                .app(app);
+            matches!(levels.process(&mut line), Status::Ok(_))
+        };
+
+        assert!(!kept("DEBUG cache miss", "chatty"));
+        assert!(!kept("INFO request", "chatty"));
+        assert!(kept("WARN slow request", "chatty"));
+        assert!(kept("no level", "chatty"));
+        assert!(kept("DEBUG cache miss", "quiet"));
+        assert!(!kept("TRACE enter", "quiet"));
+    }
+
+    #[test]
+    fn keeps_existing_levels() {
+        let levels = Levels::new(
+            false,
+            vec![MinLevelRule::new(None, None, Level::Error).unwrap()],
+        );
+        let mut line = LineBuilder::new().line("ERROR failed").level("warn");
+        assert!(matches!(levels.process(&mut line), Status::Skip));
+        // Unknown levels are kept
+        assert!(matches!(
+            levels.process(&mut LineBuilder::new().line("a").level("[warn]")),
+            Status::Ok(_)
+        ));
+        let mut line = LineBuilder::new().line("ERROR failed");
+        assert!(matches!(levels.process(&mut line), Status::Ok(_)));
+        assert_eq!(line.get_level(), None);
+    }
+}
@@ -1,18 +1,160 @@
 use std::sync::Arc;
 
-use http::types::body::LineBufferMut;
+use http::types::body::{KeyValueMap, LineBufferMut, LineBuilder, LineMeta, LineMetaMut};
+use http::types::error::LineMetaError;
+use serde_json::{Map, Value};
 use std::thread::spawn;
 
+pub mod dedup;
+pub mod file_fields;
+pub mod json_fields;
+pub mod levels;
 pub mod line_rules;
+pub mod rate_limit;
+pub mod reload;
+pub mod routing;
+pub mod timestamps;
 
 pub enum Status<T> {
     Ok(T),
     Skip,
 }
 
+/// A line that can be processed by a middleware
+///
+/// The timestamp is not part of `LineMetaMut`, line types that can carry their own
+/// timestamp override `set_timestamp` and `get_timestamp` while the rest ignore it.
+pub trait LineMut: LineBufferMut {
+    fn set_timestamp(&mut self, _timestamp: i64) -> Result<(), LineMetaError> {
+        Ok(())
+    }
+
+    fn get_timestamp(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// The builder can't hold a timestamp, the one set by a middleware is dropped. The lines built
+/// by the sources and the middlewares are processed as a `TimestampedLine` to keep it.
+impl LineMut for LineBuilder {}
+
+/// Adds the fields to the metadata of the line, keeping the fields already set such as the
+/// container stream
+pub fn merge_meta(line: &mut dyn LineMut, fields: Map<String, Value>) -> Result<(), LineMetaError> {
+    let mut meta = match line.get_meta() {
+        Some(Value::Object(existing)) => existing.clone(),
+        _ => Map::new(),
+    };
+    meta.extend(fields);
+    line.set_meta(Value::Object(meta))
+}
+
+/// A line built with a `LineBuilder`, such as a line created by a middleware rather than read
+/// from a source, along with the timestamp it's sent with as the builder can't hold it
+pub struct TimestampedLine {
+    pub line: LineBuilder,
+    pub timestamp: Option<i64>,
+}
+
+impl LineMeta for TimestampedLine {
+    fn get_annotations(&self) -> Option<&KeyValueMap> {
+        self.line.get_annotations()
+    }
+    fn get_app(&self) -> Option<&str> {
+        self.line.get_app()
+    }
+    fn get_env(&self) -> Option<&str> {
+        self.line.get_env()
+    }
+    fn get_file(&self) -> Option<&str> {
+        self.line.get_file()
+    }
+    fn get_host(&self) -> Option<&str> {
+        self.line.get_host()
+    }
+    fn get_labels(&self) -> Option<&KeyValueMap> {
+        self.line.get_labels()
+    }
+    fn get_level(&self) -> Option<&str> {
+        self.line.get_level()
+    }
+    fn get_meta(&self) -> Option<&Value> {
+        self.line.get_meta()
+    }
+}
+
+impl LineMetaMut for TimestampedLine {
+    fn set_annotations(&mut self, annotations: KeyValueMap) -> Result<(), LineMetaError> {
+        self.line.set_annotations(annotations)
+    }
+    fn set_app(&mut self, app: String) -> Result<(), LineMetaError> {
+        self.line.set_app(app)
+    }
+    fn set_env(&mut self, env: String) -> Result<(), LineMetaError> {
+        self.line.set_env(env)
+    }
+    fn set_file(&mut self, file: String) -> Result<(), LineMetaError> {
+        self.line.set_file(file)
+    }
+    fn set_host(&mut self, host: String) -> Result<(), LineMetaError> {
+        self.line.set_host(host)
+    }
+    fn set_labels(&mut self, labels: KeyValueMap) -> Result<(), LineMetaError> {
+        self.line.set_labels(labels)
+    }
+    fn set_level(&mut self, level: String) -> Result<(), LineMetaError> {
+        self.line.set_level(level)
+    }
+    fn set_meta(&mut self, meta: Value) -> Result<(), LineMetaError> {
+        self.line.set_meta(meta)
+    }
+}
+
+impl LineBufferMut for TimestampedLine {
+    fn get_line_buffer(&mut self) -> Option<&[u8]> {
+        self.line.get_line_buffer()
+    }
+
+    fn set_line_buffer(&mut self, line: Vec<u8>) -> Result<(), LineMetaError> {
+        self.line.set_line_buffer(line)
+    }
+}
+
+impl LineMut for TimestampedLine {
+    fn set_timestamp(&mut self, timestamp: i64) -> Result<(), LineMetaError> {
+        self.timestamp = Some(timestamp);
+        Ok(())
+    }
+
+    fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+}
+
 pub trait Middleware: Send + Sync + 'static {
     fn run(&self);
-    fn process<'a>(&self, lines: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut>;
+    fn process<'a>(&self, lines: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut>;
+
+    /// Takes the lines created by the middleware rather than read from a source, such as the
+    /// summaries of the lines it dropped
+    fn take_lines(&self) -> Vec<TimestampedLine> {
+        Vec::new()
+    }
+}
+
+/// Lets a middleware be registered while it's still used elsewhere, such as by a source
+impl<T: Middleware> Middleware for Arc<T> {
+    fn run(&self) {
+        (**self).run()
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        (**self).process(line)
+    }
+
+    fn take_lines(&self) -> Vec<TimestampedLine> {
+        (**self).take_lines()
+    }
 }
 
 #[derive(Default)]
@@ -38,16 +180,87 @@ impl Executor {
         }
     }
 
-    pub fn process<'a>(
-        &self,
-        line: &'a mut dyn LineBufferMut,
-    ) -> Option<&'a mut dyn LineBufferMut> {
-        self.middlewares
-            .iter()
-            .try_fold(line, |l, m| match m.process(l) {
-                Status::Ok(l) => Ok(l),
-                Status::Skip => Err(()),
-            })
-            .ok()
+    pub fn process<'a>(&self, line: &'a mut dyn LineMut) -> Option<&'a mut dyn LineMut> {
+        process_with(&self.middlewares, line)
+    }
+
+    /// Takes the lines created by the middlewares since the last call, each line is processed
+    /// by the middlewares registered after the one that created it
+    pub fn take_lines(&self) -> Vec<TimestampedLine> {
+        let mut lines = Vec::new();
+        for (index, middleware) in self.middlewares.iter().enumerate() {
+            for mut created in middleware.take_lines() {
+                if process_with(&self.middlewares[index + 1..], &mut created).is_some() {
+                    lines.push(created);
+                }
+            }
+        }
+        lines
+    }
+}
+
+fn process_with<'a>(
+    middlewares: &[Arc<dyn Middleware>],
+    line: &'a mut dyn LineMut,
+) -> Option<&'a mut dyn LineMut> {
+    middlewares
+        .iter()
+        .try_fold(line, |l, m| match m.process(l) {
+            Status::Ok(l) => Ok(l),
+            Status::Skip => Err(()),
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_rules::LineRules;
+
+    #[test]
+    fn merges_meta() {
+        let mut line = LineBuilder::new().line("hello");
+        line.set_meta(serde_json::json!({"stream": "stdout"}))
+            .unwrap();
+        let mut fields = Map::new();
+        fields.insert("user".into(), "root".into());
+        merge_meta(&mut line, fields).unwrap();
+        assert_eq!(
+            line.get_meta(),
+            Some(&serde_json::json!({"stream": "stdout", "user": "root"}))
+        );
+    }
+
+    struct Summaries;
+
+    impl Middleware for Summaries {
+        fn run(&self) {}
+
+        fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+            Status::Ok(line)
+        }
+
+        fn take_lines(&self) -> Vec<TimestampedLine> {
+            ["DEBUG repeated", "ERROR repeated"]
+                .iter()
+                .map(|line| TimestampedLine {
+                    line: LineBuilder::new().line(*line),
+                    timestamp: Some(1),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn created_lines_are_processed_by_the_next_middlewares() {
+        let mut executor = Executor::new();
+        executor.register(LineRules::new(&["ERROR".into()], &[], &[]).unwrap());
+        executor.register(Summaries);
+        executor.register(LineRules::new(&["DEBUG".into()], &[], &[]).unwrap());
+
+        let mut lines = executor.take_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line.get_line_buffer().unwrap(), b"ERROR repeated");
+        assert_eq!(lines[0].timestamp, Some(1));
     }
 }
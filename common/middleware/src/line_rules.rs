@@ -1,10 +1,12 @@
-use crate::{Middleware, Status};
-use http::types::body::LineBufferMut;
+use crate::{LineMut, Middleware, Status};
 use regex::bytes::{Regex, RegexSet};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 use std::cmp;
 use thiserror::Error;
 
 static REDACT_BYTES: &[u8] = b"[REDACTED]";
+static REDACT_STR: &str = "[REDACTED]";
 
 pub struct LineRules {
     exclusion: RegexSet,
@@ -12,10 +14,153 @@ pub struct LineRules {
     redact: Vec<Regex>,
 }
 
+/// Redacts the fields of JSON lines
+///
+/// It runs before the fields of the lines are extracted, such as by `JsonFields`, so that the
+/// redacted values are not copied into the metadata of the lines.
+pub struct FieldRedactor {
+    rules: Vec<FieldRule>,
+}
+
 #[derive(Clone, Debug, Error)]
 pub enum LineRulesError {
     #[error(transparent)]
     RegexError(regex::Error),
+    #[error("invalid JSON path: {0}")]
+    JsonPathError(String),
+}
+
+/// How the value of a redacted field is replaced
+#[derive(Clone, Debug, PartialEq)]
+pub enum RedactMode {
+    /// Replaces the value with `[REDACTED]`
+    Replace,
+    /// Replaces the value with the hex encoded SHA-256 hash of the salt followed by the value
+    Hash { salt: String },
+    /// Masks all but the last 4 characters of the value
+    KeepLastFour,
+}
+
+impl RedactMode {
+    fn apply(&self, value: &Value) -> Value {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+
+        match self {
+            RedactMode::Replace => Value::String(REDACT_STR.into()),
+            RedactMode::Hash { salt } => {
+                let mut hasher = Sha256::new();
+                hasher.update(salt.as_bytes());
+                hasher.update(text.as_bytes());
+                Value::String(hex::encode(hasher.finalize()))
+            }
+            RedactMode::KeepLastFour => {
+                let len = text.chars().count();
+                // Short values are masked entirely rather than disclosed
+                let keep = if len > 4 { 4 } else { 0 };
+                let masked = "*".repeat(len - keep);
+                Value::String(masked + &text.chars().skip(len - keep).collect::<String>())
+            }
+        }
+    }
+}
+
+/// Redacts the field of JSON lines found at a path such as `$.user.email`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldRedaction {
+    pub path: String,
+    pub mode: RedactMode,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+struct FieldRule {
+    path: Vec<PathSegment>,
+    mode: RedactMode,
+}
+
+/// Parses a path made of `.key`, `['key']`, `[index]` and `*` segments following the `$` root
+fn parse_path(path: &str) -> Result<Vec<PathSegment>, LineRulesError> {
+    let error = || LineRulesError::JsonPathError(path.into());
+    let mut rest = path.strip_prefix('$').ok_or_else(error)?;
+    let mut segments = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after
+                .find(|c: char| c == '.' || c == '[')
+                .unwrap_or(after.len());
+            let segment = match &after[..end] {
+                "" => return Err(error()),
+                "*" => PathSegment::Wildcard,
+                key => PathSegment::Key(key.into()),
+            };
+            segments.push(segment);
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(error)?;
+            let inner = &after[..end];
+            let segment = if inner == "*" {
+                PathSegment::Wildcard
+            } else if let Ok(index) = inner.parse::<usize>() {
+                PathSegment::Index(index)
+            } else if inner.len() >= 2
+                && ((inner.starts_with('\'') && inner.ends_with('\''))
+                    || (inner.starts_with('"') && inner.ends_with('"')))
+            {
+                PathSegment::Key(inner[1..inner.len() - 1].into())
+            } else {
+                return Err(error());
+            };
+            segments.push(segment);
+            rest = &after[end + 1..];
+        } else {
+            return Err(error());
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(error());
+    }
+    Ok(segments)
+}
+
+/// Redacts the values found at path, returns true when any value was redacted
+fn redact_path(value: &mut Value, path: &[PathSegment], mode: &RedactMode) -> bool {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *value = mode.apply(value);
+            return true;
+        }
+    };
+
+    match (segment, value) {
+        (PathSegment::Key(key), Value::Object(map)) => map
+            .get_mut(key)
+            .map_or(false, |value| redact_path(value, rest, mode)),
+        (PathSegment::Index(index), Value::Array(values)) => values
+            .get_mut(*index)
+            .map_or(false, |value| redact_path(value, rest, mode)),
+        (PathSegment::Wildcard, Value::Object(map)) => {
+            map.values_mut().fold(false, |redacted, value| {
+                redact_path(value, rest, mode) || redacted
+            })
+        }
+        (PathSegment::Wildcard, Value::Array(values)) => {
+            values.iter_mut().fold(false, |redacted, value| {
+                redact_path(value, rest, mode) || redacted
+            })
+        }
+        _ => false,
+    }
 }
 
 impl LineRules {
@@ -38,10 +183,7 @@ impl LineRules {
     }
 
     /// Applies inclusion and exclusion rules and replaces the redacted values.
-    fn process_line<'a>(
-        &self,
-        line: &'a mut dyn LineBufferMut,
-    ) -> Status<&'a mut dyn LineBufferMut> {
+    fn process_line<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
         let value = line.get_line_buffer().unwrap();
 
         // If it doesn't match any inclusion rule -> skip
@@ -55,88 +197,93 @@ impl LineRules {
         }
 
         if !self.redact.is_empty() {
-            return self.redact(value.to_owned(), line);
+            let value = value.to_owned();
+            return self.redact(value, line);
+        }
+
+        Status::Ok(line)
+    }
+
+    fn redact<'a>(&self, value: Vec<u8>, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        if let Some(redacted) = redact(&self.redact, &value) {
+            if line.set_line_buffer(redacted).is_err() {
+                return Status::Skip;
+            }
         }
 
         Status::Ok(line)
     }
+}
 
-    fn redact<'a>(
-        &self,
-        value: Vec<u8>,
-        line: &'a mut dyn LineBufferMut,
-    ) -> Status<&'a mut dyn LineBufferMut> {
-        let mut matches: Vec<(usize, usize)> = vec![];
-        for r in self.redact.iter() {
-            for m in r.find_iter(&value) {
-                let mut overlapping_match = None;
-                let mut insert_index = None;
-                for (i, existing) in matches.iter().enumerate() {
-                    let overlaps =
-                        // Overlaps when it starts between an existing match
-                        (m.start() >= existing.0 && m.start() <= existing.1)
-                        // or it starts before an existing match
-                        // and ends after the existing match end
-                        || (m.start() <= existing.0 && m.end() >= existing.0);
-
-                    if overlaps {
-                        overlapping_match = Some((
-                            i,
-                            cmp::min(existing.0, m.start()),
-                            cmp::max(existing.1, m.end()),
-                        ));
-                        // Order is guaranteed so there's no need to continue processing
-                        break;
-                    }
-
-                    if m.start() < existing.0 {
-                        insert_index = Some(i);
-                        // Order is guaranteed so there's no need to continue processing
-                        break;
-                    }
+/// Replaces the values matching any of the patterns with `[REDACTED]`, overlapping matches
+/// are redacted once. Returns `None` when nothing matches.
+pub fn redact(patterns: &[Regex], value: &[u8]) -> Option<Vec<u8>> {
+    let mut matches: Vec<(usize, usize)> = vec![];
+    for r in patterns.iter() {
+        for m in r.find_iter(value) {
+            let mut overlapping_match = None;
+            let mut insert_index = None;
+            for (i, existing) in matches.iter().enumerate() {
+                let overlaps =
+                    // Overlaps when it starts between an existing match
+                    (m.start() >= existing.0 && m.start() <= existing.1)
+                    // or it starts before an existing match
+                    // and ends after the existing match end
+                    || (m.start() <= existing.0 && m.end() >= existing.0);
+
+                if overlaps {
+                    overlapping_match = Some((
+                        i,
+                        cmp::min(existing.0, m.start()),
+                        cmp::max(existing.1, m.end()),
+                    ));
+                    // Order is guaranteed so there's no need to continue processing
+                    break;
                 }
 
-                if let Some(item) = overlapping_match {
-                    // Replace existing
-                    matches[item.0] = (item.1, item.2);
-                } else if let Some(index) = insert_index {
-                    // Insert at position and shift all elements after it to the right
-                    matches.insert(index, (m.start(), m.end()));
-                } else {
-                    // Append
-                    matches.push((m.start(), m.end()));
+                if m.start() < existing.0 {
+                    insert_index = Some(i);
+                    // Order is guaranteed so there's no need to continue processing
+                    break;
                 }
             }
-        }
 
-        if matches.is_empty() {
-            return Status::Ok(line);
-        }
-
-        let mut redacted = Vec::with_capacity(value.len());
-        let mut index = 0;
-        for item in matches {
-            redacted.extend_from_slice(&value[index..item.0]);
-            redacted.extend_from_slice(REDACT_BYTES);
-            index = item.1;
+            if let Some(item) = overlapping_match {
+                // Replace existing
+                matches[item.0] = (item.1, item.2);
+            } else if let Some(index) = insert_index {
+                // Insert at position and shift all elements after it to the right
+                matches.insert(index, (m.start(), m.end()));
+            } else {
+                // Append
+                matches.push((m.start(), m.end()));
+            }
         }
+    }
 
-        if index < value.len() {
-            redacted.extend_from_slice(&value[index..]);
-        }
+    if matches.is_empty() {
+        return None;
+    }
 
-        if line.set_line_buffer(redacted).is_err() {
-            return Status::Skip;
-        }
+    let mut redacted = Vec::with_capacity(value.len());
+    let mut index = 0;
+    for item in matches {
+        redacted.extend_from_slice(&value[index..item.0]);
+        redacted.extend_from_slice(REDACT_BYTES);
+        index = item.1;
+    }
 
-        Status::Ok(line)
+    if index < value.len() {
+        redacted.extend_from_slice(&value[index..]);
     }
+
+    Some(redacted)
 }
 
 impl Middleware for LineRules {
     fn run(&self) {}
 
-    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
         if self.exclusion.is_empty() && self.inclusion.is_empty() && self.redact.is_empty() {
             // Avoid unnecessary allocations when no rules were defined
             return Status::Ok(line);
@@ -149,10 +296,67 @@ impl Middleware for LineRules {
     }
 }
 
+impl FieldRedactor {
+    pub fn new(redact_fields: &[FieldRedaction]) -> Result<FieldRedactor, LineRulesError> {
+        Ok(FieldRedactor {
+            rules: redact_fields
+                .iter()
+                .map(|field| {
+                    Ok(FieldRule {
+                        path: parse_path(&field.path)?,
+                        mode: field.mode.clone(),
+                    })
+                })
+                .collect::<Result<_, LineRulesError>>()?,
+        })
+    }
+
+    /// Redacts the fields of a JSON object line, returns None when the line was left unchanged
+    fn redact_fields(&self, value: &[u8]) -> Option<Vec<u8>> {
+        if !value.starts_with(b"{") {
+            return None;
+        }
+
+        let mut json: Value = serde_json::from_slice(value).ok()?;
+        let mut redacted = false;
+        for rule in self.rules.iter() {
+            redacted |= redact_path(&mut json, &rule.path, &rule.mode);
+        }
+
+        if redacted {
+            serde_json::to_vec(&json).ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for FieldRedactor {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        if self.rules.is_empty() {
+            return Status::Ok(line);
+        }
+
+        let redacted = match line.get_line_buffer() {
+            Some(value) => self.redact_fields(value),
+            None => return Status::Skip,
+        };
+        if let Some(redacted) = redacted {
+            if line.set_line_buffer(redacted).is_err() {
+                return Status::Skip;
+            }
+        }
+        Status::Ok(line)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use http::types::body::LineBuilder;
+    use crate::json_fields::{JsonFieldNames, JsonFields};
+    use http::types::body::{LineBuilder, LineMeta};
 
     macro_rules! s {
         ($val: expr) => {
@@ -339,4 +543,132 @@ mod tests {
         );
         is_match!(p, "ERROR DEBUG SENSITIVE", Status::Skip);
     }
+
+    #[test]
+    fn should_parse_json_paths() {
+        assert_eq!(
+            parse_path("$.user.email").unwrap(),
+            vec![PathSegment::Key(s!("user")), PathSegment::Key(s!("email"))]
+        );
+        assert_eq!(
+            parse_path("$.users[1]['first name'].*").unwrap(),
+            vec![
+                PathSegment::Key(s!("users")),
+                PathSegment::Index(1),
+                PathSegment::Key(s!("first name")),
+                PathSegment::Wildcard
+            ]
+        );
+        assert!(parse_path("$").is_err());
+        assert!(parse_path("user.email").is_err());
+        assert!(parse_path("$.user..email").is_err());
+        assert!(parse_path("$.users[one]").is_err());
+    }
+
+    #[test]
+    fn should_redact_fields() {
+        let fields = &vec![
+            FieldRedaction {
+                path: s!("$.user.email"),
+                mode: RedactMode::Replace,
+            },
+            FieldRedaction {
+                path: s!("$.headers.authorization"),
+                mode: RedactMode::Hash { salt: s!("salt") },
+            },
+            FieldRedaction {
+                path: s!("$.cards[*].number"),
+                mode: RedactMode::KeepLastFour,
+            },
+        ];
+        let p = FieldRedactor::new(fields).unwrap();
+
+        redact_match!(
+            p,
+            r#"{"level":"info","user":{"email":"support@logdna.com","id":1}}"#,
+            r#"{"level":"info","user":{"email":"[REDACTED]","id":1}}"#
+        );
+        // The same value always hashes to the same result
+        redact_match!(
+            p,
+            r#"{"headers":{"authorization":"Bearer abc"}}"#,
+            r#"{"headers":{"authorization":"bad24b3980b76a926f09a6e1b321dbb636cbdb81d6e0f40f2bc18e891bd6cfb1"}}"#
+        );
+        redact_match!(
+            p,
+            r#"{"cards":[{"number":"4111111111111111"},{"number":1234}]}"#,
+            r#"{"cards":[{"number":"************1111"},{"number":"****"}]}"#
+        );
+        // Lines that are not JSON objects or don't contain the fields are left unchanged
+        redact_match!(
+            p,
+            r#"{"user": "support@logdna.com"}"#,
+            r#"{"user": "support@logdna.com"}"#
+        );
+        redact_match!(
+            p,
+            "user.email=support@logdna.com",
+            "user.email=support@logdna.com"
+        );
+    }
+
+    #[test]
+    fn should_redact_fields_and_lines() {
+        let fields = &vec![FieldRedaction {
+            path: s!("$.password"),
+            mode: RedactMode::Replace,
+        }];
+        let fields = FieldRedactor::new(fields).unwrap();
+        let p = LineRules::new(&[], &[], &[s!("(?i:SENSITIVE)")]).unwrap();
+        let mut line = LineBuilder::new().line(r#"{"msg":"sensitive","password":"hunter2"}"#);
+        let line = match fields.process(&mut line) {
+            Status::Ok(l) => p.process(l),
+            Status::Skip => panic!("should not have been skipped"),
+        };
+        match line {
+            Status::Ok(l) => assert_eq!(
+                l.get_line_buffer().unwrap(),
+                br#"{"msg":"[REDACTED]","password":"[REDACTED]"}"#
+            ),
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn should_redact_fields_before_json_fields() {
+        let fields = FieldRedactor::new(&[FieldRedaction {
+            path: s!("$.context.email"),
+            mode: RedactMode::Replace,
+        }])
+        .unwrap();
+        let json = JsonFields::new(
+            JsonFieldNames {
+                meta: Some(s!("context")),
+                ..JsonFieldNames::default()
+            },
+            false,
+        );
+        let mut line =
+            LineBuilder::new().line(r#"{"context":{"email":"support@logdna.com","id":1}}"#);
+        let line = match fields.process(&mut line) {
+            Status::Ok(l) => json.process(l),
+            Status::Skip => panic!("should not have been skipped"),
+        };
+        match line {
+            Status::Ok(l) => assert_eq!(
+                l.get_meta(),
+                Some(&serde_json::json!({"email": "[REDACTED]", "id": 1}))
+            ),
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_paths() {
+        let fields = &vec![FieldRedaction {
+            path: s!("user.email"),
+            mode: RedactMode::Replace,
+        }];
+        assert!(FieldRedactor::new(fields).is_err());
+    }
 }
@@ -0,0 +1,291 @@
+use crate::{LineMut, Middleware, Status};
+use metrics::Metrics;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The maximum number of buckets kept for each rule, idle buckets are dropped past it
+const MAX_BUCKETS: usize = 10_000;
+
+/// The field of a line used to group or sample the lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineField {
+    File,
+    App,
+    /// The Kubernetes namespace, read from the container log file name
+    Namespace,
+    Line,
+}
+
+impl LineField {
+    fn value(&self, line: &mut dyn LineMut) -> Option<Vec<u8>> {
+        match self {
+            LineField::File => line.get_file().map(|file| file.as_bytes().to_vec()),
+            LineField::App => line.get_app().map(|app| app.as_bytes().to_vec()),
+            LineField::Namespace => line
+                .get_file()
+                .and_then(parse_namespace)
+                .map(|namespace| namespace.as_bytes().to_vec()),
+            LineField::Line => line.get_line_buffer().map(|buf| buf.to_vec()),
+        }
+    }
+}
+
+/// Reads the namespace from the name of a container log file, formatted as
+/// `/var/log/containers/<pod>_<namespace>_<container>-<id>.log`
+fn parse_namespace(path: &str) -> Option<&str> {
+    let name = path.strip_prefix("/var/log/containers/")?;
+    let mut parts = name.split('_');
+    parts.next()?;
+    let namespace = parts.next()?;
+    parts.next()?;
+    Some(namespace)
+}
+
+/// Limits the lines sharing the same value of `key` to `lines_per_second`, allowing
+/// bursts of up to `burst` lines
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitRule {
+    pub key: LineField,
+    pub lines_per_second: u64,
+    pub burst: u64,
+}
+
+/// Which lines are kept when sampling
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sampling {
+    /// Keeps one of every `n` lines
+    OneIn(u64),
+    /// Keeps the lines whose hashed `field` is a multiple of `n`, so the lines with the same
+    /// value of the field are either all kept or all dropped
+    Hash { field: LineField, n: u64 },
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Limiter {
+    rule: RateLimitRule,
+    buckets: Mutex<HashMap<Vec<u8>, Bucket>>,
+}
+
+impl Limiter {
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rule.lines_per_second as f64)
+            .min(self.rule.burst as f64);
+        bucket.updated = now;
+    }
+
+    fn allow(&self, key: Vec<u8>, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            // Full buckets are the same as new ones
+            buckets.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.tokens < self.rule.burst as f64
+            });
+            if buckets.len() >= MAX_BUCKETS {
+                buckets.clear();
+            }
+        }
+
+        let burst = self.rule.burst as f64;
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        self.refill(bucket, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Drops the lines exceeding the rate limits or left out by the sampling
+pub struct RateLimit {
+    limiters: Vec<Limiter>,
+    sampling: Option<Sampling>,
+    count: AtomicU64,
+}
+
+impl RateLimit {
+    pub fn new(rules: Vec<RateLimitRule>, sampling: Option<Sampling>) -> Self {
+        RateLimit {
+            limiters: rules
+                .into_iter()
+                .map(|rule| Limiter {
+                    rule,
+                    buckets: Mutex::new(HashMap::new()),
+                })
+                .collect(),
+            sampling,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn sampled(&self, line: &mut dyn LineMut) -> bool {
+        match self.sampling {
+            None => true,
+            Some(Sampling::OneIn(n)) => self.count.fetch_add(1, Ordering::Relaxed) % n.max(1) == 0,
+            Some(Sampling::Hash { field, n }) => field
+                .value(line)
+                .map_or(true, |value| fnv1a(&value) % n.max(1) == 0),
+        }
+    }
+
+    fn allowed(&self, line: &mut dyn LineMut, now: Instant) -> bool {
+        // Every limiter is updated, so a line counts towards all of its keys' limits
+        self.limiters.iter().fold(true, |allowed, limiter| {
+            match limiter.rule.key.value(line) {
+                Some(key) => limiter.allow(key, now) && allowed,
+                None => allowed,
+            }
+        })
+    }
+
+    fn process_at<'a>(
+        &self,
+        line: &'a mut dyn LineMut,
+        now: Instant,
+    ) -> Status<&'a mut dyn LineMut> {
+        if !self.sampled(line) {
+            Metrics::middleware().increment_sampled();
+            return Status::Skip;
+        }
+        if !self.allowed(line, now) {
+            Metrics::middleware().increment_rate_limited();
+            return Status::Skip;
+        }
+        Status::Ok(line)
+    }
+}
+
+/// A stable hash, so the same lines are sampled across restarts and agents
+fn fnv1a(value: &[u8]) -> u64 {
+    value.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Middleware for RateLimit {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        self.process_at(line, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use std::time::Duration;
+
+    fn kept(rate_limit: &RateLimit, line: &mut LineBuilder, now: Instant) -> bool {
+        matches!(rate_limit.process_at(line, now), Status::Ok(_))
+    }
+
+    #[test]
+    fn limits_lines_per_key() {
+        let rate_limit = RateLimit::new(
+            vec![RateLimitRule {
+                key: LineField::File,
+                lines_per_second: 2,
+                burst: 3,
+            }],
+            None,
+        );
+        let now = Instant::now();
+        let mut noisy = LineBuilder::new().line("noisy").file("/var/log/noisy.log");
+        let mut quiet = LineBuilder::new().line("quiet").file("/var/log/quiet.log");
+
+        for _ in 0..3 {
+            assert!(kept(&rate_limit, &mut noisy, now));
+        }
+        assert!(!kept(&rate_limit, &mut noisy, now));
+        assert!(kept(&rate_limit, &mut quiet, now));
+
+        // Two tokens are added every second
+        let later = now + Duration::from_secs(1);
+        assert!(kept(&rate_limit, &mut noisy, later));
+        assert!(kept(&rate_limit, &mut noisy, later));
+        assert!(!kept(&rate_limit, &mut noisy, later));
+    }
+
+    #[test]
+    fn limits_namespaces() {
+        let rate_limit = RateLimit::new(
+            vec![RateLimitRule {
+                key: LineField::Namespace,
+                lines_per_second: 1,
+                burst: 1,
+            }],
+            None,
+        );
+        let now = Instant::now();
+        let file = |pod: &str| {
+            format!(
+                "/var/log/containers/{}_kube-system_app-{}.log",
+                pod,
+                "0".repeat(64)
+            )
+        };
+
+        let mut line = LineBuilder::new().line("a").file(file("pod-a"));
+        assert!(kept(&rate_limit, &mut line, now));
+        let mut line = LineBuilder::new().line("a").file(file("pod-b"));
+        assert!(!kept(&rate_limit, &mut line, now));
+        // Lines without a namespace are not limited
+        let mut line = LineBuilder::new().line("a").file("/var/log/syslog");
+        assert!(kept(&rate_limit, &mut line, now));
+        assert!(kept(&rate_limit, &mut line, now));
+    }
+
+    #[test]
+    fn samples_one_in_n() {
+        let rate_limit = RateLimit::new(Vec::new(), Some(Sampling::OneIn(3)));
+        let now = Instant::now();
+        let kept = (0..9)
+            .filter(|_| kept(&rate_limit, &mut LineBuilder::new().line("a"), now))
+            .count();
+        assert_eq!(kept, 3);
+    }
+
+    #[test]
+    fn samples_by_hash() {
+        let rate_limit = RateLimit::new(
+            Vec::new(),
+            Some(Sampling::Hash {
+                field: LineField::Line,
+                n: 4,
+            }),
+        );
+        let now = Instant::now();
+        let mut sampled = 0;
+        for i in 0..1000 {
+            let line = format!("request {}", i);
+            let first = kept(&rate_limit, &mut LineBuilder::new().line(&line), now);
+            let second = kept(&rate_limit, &mut LineBuilder::new().line(&line), now);
+            assert_eq!(first, second);
+            if first {
+                sampled += 1;
+            }
+        }
+        // About one in n distinct lines is kept
+        assert!((200..=300).contains(&sampled), "{} lines kept", sampled);
+    }
+
+    #[test]
+    fn hashes_with_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
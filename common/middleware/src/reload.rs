@@ -0,0 +1,86 @@
+use crate::{LineMut, Middleware, Status};
+use std::sync::{Arc, RwLock};
+
+/// A middleware that can be replaced while lines are being processed
+///
+/// Only the `run` method of the initial middleware is called, so it should be used for
+/// middlewares that don't rely on it.
+pub struct Reloadable<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+/// Replaces the middleware of a `Reloadable`
+pub struct ReloadHandle<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Clone for ReloadHandle<T> {
+    fn clone(&self) -> Self {
+        ReloadHandle {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T: Middleware> Reloadable<T> {
+    pub fn new(middleware: T) -> Self {
+        Reloadable {
+            current: Arc::new(RwLock::new(Arc::new(middleware))),
+        }
+    }
+
+    pub fn handle(&self) -> ReloadHandle<T> {
+        ReloadHandle {
+            current: self.current.clone(),
+        }
+    }
+
+    fn current(&self) -> Arc<T> {
+        self.current
+            .read()
+            .expect("middleware lock poisoned")
+            .clone()
+    }
+}
+
+impl<T: Middleware> ReloadHandle<T> {
+    /// Replaces the middleware, lines already being processed complete with the previous one
+    pub fn swap(&self, middleware: T) {
+        *self.current.write().expect("middleware lock poisoned") = Arc::new(middleware);
+    }
+}
+
+impl<T: Middleware> Middleware for Reloadable<T> {
+    fn run(&self) {
+        self.current().run()
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        self.current().process(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_rules::LineRules;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn swaps_middleware() {
+        let reloadable = Reloadable::new(LineRules::new(&["DEBUG".into()], &[], &[]).unwrap());
+        let handle = reloadable.handle();
+
+        let mut line = LineBuilder::new().line("DEBUG message");
+        assert!(matches!(reloadable.process(&mut line), Status::Skip));
+        let mut line = LineBuilder::new().line("TRACE message");
+        assert!(matches!(reloadable.process(&mut line), Status::Ok(_)));
+
+        handle.swap(LineRules::new(&["TRACE".into()], &[], &[]).unwrap());
+
+        let mut line = LineBuilder::new().line("DEBUG message");
+        assert!(matches!(reloadable.process(&mut line), Status::Ok(_)));
+        let mut line = LineBuilder::new().line("TRACE message");
+        assert!(matches!(reloadable.process(&mut line), Status::Skip));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::LineMut;
+use globber::Pattern;
+use http::types::body::{LineBuilder, LineMetaMut};
+use regex::bytes::Regex;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error("invalid route glob: {0}")]
+    Glob(globber::Error),
+    #[error("invalid route regex: {0}")]
+    Regex(regex::Error),
+}
+
+/// Matches the lines that have all of the given fields
+#[derive(Debug)]
+pub struct RouteRule {
+    glob: Option<Pattern>,
+    app: Option<String>,
+    level: Option<String>,
+    line: Option<Regex>,
+}
+
+impl RouteRule {
+    pub fn new(
+        glob: Option<&str>,
+        app: Option<&str>,
+        level: Option<&str>,
+        line_regex: Option<&str>,
+    ) -> Result<Self, RouteError> {
+        Ok(RouteRule {
+            glob: glob
+                .map(Pattern::new)
+                .transpose()
+                .map_err(RouteError::Glob)?,
+            app: app.map(String::from),
+            level: level.map(String::from),
+            line: line_regex
+                .map(Regex::new)
+                .transpose()
+                .map_err(RouteError::Regex)?,
+        })
+    }
+
+    pub fn matches(&self, line: &mut dyn LineMut) -> bool {
+        if let Some(glob) = self.glob.as_ref() {
+            if !line.get_file().map_or(false, |file| glob.matches(file)) {
+                return false;
+            }
+        }
+        if let Some(app) = self.app.as_ref() {
+            if line.get_app() != Some(app.as_str()) {
+                return false;
+            }
+        }
+        if let Some(level) = self.level.as_ref() {
+            if !line
+                .get_level()
+                .map_or(false, |l| l.eq_ignore_ascii_case(level))
+            {
+                return false;
+            }
+        }
+        if let Some(regex) = self.line.as_ref() {
+            if !line
+                .get_line_buffer()
+                .map_or(false, |buf| regex.is_match(buf))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Decides whether a line is sent to a destination other than the primary one
+#[derive(Debug)]
+pub struct Route {
+    pub rules: Vec<RouteRule>,
+    /// Lines sent to the destination are not sent to the primary destination
+    pub exclusive: bool,
+}
+
+impl Route {
+    pub fn matches(&self, line: &mut dyn LineMut) -> bool {
+        self.rules.iter().any(|rule| rule.matches(line))
+    }
+}
+
+/// Copies the line so it can be sent to more than one destination
+///
+/// The builder can't hold the timestamp of the line, it's read with `LineMut::get_timestamp`
+/// and set on the built copy instead.
+pub fn copy_line(line: &mut dyn LineMut) -> Option<LineBuilder> {
+    let mut copy = LineBuilder::new().line(String::from_utf8_lossy(line.get_line_buffer()?));
+    // Setting the fields of a builder doesn't fail
+    if let Some(annotations) = line.get_annotations() {
+        copy.set_annotations(annotations.clone()).ok()?;
+    }
+    if let Some(app) = line.get_app() {
+        copy.set_app(app.to_string()).ok()?;
+    }
+    if let Some(env) = line.get_env() {
+        copy.set_env(env.to_string()).ok()?;
+    }
+    if let Some(file) = line.get_file() {
+        copy.set_file(file.to_string()).ok()?;
+    }
+    if let Some(host) = line.get_host() {
+        copy.set_host(host.to_string()).ok()?;
+    }
+    if let Some(labels) = line.get_labels() {
+        copy.set_labels(labels.clone()).ok()?;
+    }
+    if let Some(level) = line.get_level() {
+        copy.set_level(level.to_string()).ok()?;
+    }
+    if let Some(meta) = line.get_meta() {
+        copy.set_meta(meta.clone()).ok()?;
+    }
+    Some(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBufferMut, LineMeta};
+
+    #[test]
+    fn matches_all_the_rule_fields() {
+        let rule = RouteRule::new(
+            Some("/var/log/auth*.log"),
+            None,
+            Some("error"),
+            Some("denied"),
+        )
+        .unwrap();
+
+        let mut line = LineBuilder::new()
+            .line("access denied for root")
+            .file("/var/log/auth.log")
+            .level("ERROR");
+        assert!(rule.matches(&mut line));
+
+        let mut line = LineBuilder::new()
+            .line("access denied for root")
+            .file("/var/log/syslog.log")
+            .level("ERROR");
+        assert!(!rule.matches(&mut line));
+
+        let mut line = LineBuilder::new()
+            .line("session opened")
+            .file("/var/log/auth.log")
+            .level("ERROR");
+        assert!(!rule.matches(&mut line));
+    }
+
+    #[test]
+    fn matches_any_rule() {
+        let route = Route {
+            rules: vec![
+                RouteRule::new(None, Some("sshd"), None, None).unwrap(),
+                RouteRule::new(None, Some("sudo"), None, None).unwrap(),
+            ],
+            exclusive: false,
+        };
+        assert!(route.matches(&mut LineBuilder::new().line("a").app("sudo")));
+        assert!(!route.matches(&mut LineBuilder::new().line("a").app("cron")));
+        assert!(!route.matches(&mut LineBuilder::new().line("a")));
+    }
+
+    #[test]
+    fn copies_lines() {
+        let mut line = LineBuilder::new()
+            .line("hello")
+            .file("/var/log/app.log")
+            .app("app")
+            .level("INFO");
+        let mut copy = copy_line(&mut line).unwrap();
+        assert_eq!(copy.get_line_buffer().unwrap(), b"hello");
+        assert_eq!(copy.get_file(), Some("/var/log/app.log"));
+        assert_eq!(copy.get_app(), Some("app"));
+        assert_eq!(copy.get_level(), Some("INFO"));
+        assert_eq!(copy.get_host(), None);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{LineMut, Middleware, Status};
+use chrono::{DateTime, NaiveDateTime};
+use globber::Pattern;
+use regex::Regex;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TimestampError {
+    #[error("invalid timestamp glob: {0}")]
+    Glob(globber::Error),
+    #[error("invalid timestamp regex: {0}")]
+    Regex(regex::Error),
+    #[error("unknown timestamp format: {0}")]
+    Format(String),
+}
+
+/// How a timestamp is written in the lines
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimestampFormat {
+    /// `2021-05-01T10:00:00.123Z`, a space can separate the date from the time
+    Rfc3339,
+    /// The Apache and nginx access logs time, `[01/May/2021:10:00:00 +0000]`
+    CommonLog,
+    /// The nginx error log time, `2021/05/01 10:00:00`, in UTC
+    NginxError,
+    /// The number of seconds since the epoch, at the start of the line unless a regex is given
+    EpochSeconds,
+    /// The number of milliseconds since the epoch, at the start of the line unless a regex
+    /// is given
+    EpochMillis,
+    /// A strftime format, such as `%b %d %H:%M:%S %Y`, read as UTC when it has no offset
+    Custom(String),
+}
+
+impl std::str::FromStr for TimestampFormat {
+    type Err = TimestampError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "common_log" => Ok(TimestampFormat::CommonLog),
+            "nginx_error" => Ok(TimestampFormat::NginxError),
+            "epoch" => Ok(TimestampFormat::EpochSeconds),
+            "epoch_millis" => Ok(TimestampFormat::EpochMillis),
+            custom if custom.contains('%') => Ok(TimestampFormat::Custom(custom.to_string())),
+            other => Err(TimestampError::Format(other.to_string())),
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// The regex finding the timestamp in the line, custom formats are read from the start of
+    /// the line unless a regex is given
+    fn regex(&self) -> Option<&'static str> {
+        match self {
+            TimestampFormat::Rfc3339 => {
+                Some(r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2})")
+            }
+            TimestampFormat::CommonLog => {
+                Some(r"\[(\d{2}/\w{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4})\]")
+            }
+            TimestampFormat::NginxError => Some(r"^\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2}"),
+            // Any number in the line could be taken for an epoch timestamp, such as an id
+            TimestampFormat::EpochSeconds => Some(r"^\d{10}\b"),
+            TimestampFormat::EpochMillis => Some(r"^\d{13}\b"),
+            TimestampFormat::Custom(_) => None,
+        }
+    }
+
+    /// Parses the timestamp into seconds since the epoch
+    fn parse(&self, value: &str) -> Option<i64> {
+        match self {
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(&value.replacen(' ', "T", 1))
+                .ok()
+                .map(|t| t.timestamp()),
+            TimestampFormat::CommonLog => DateTime::parse_from_str(value, "%d/%b/%Y:%H:%M:%S %z")
+                .ok()
+                .map(|t| t.timestamp()),
+            TimestampFormat::NginxError => {
+                NaiveDateTime::parse_from_str(value, "%Y/%m/%d %H:%M:%S")
+                    .ok()
+                    .map(|t| t.timestamp())
+            }
+            TimestampFormat::EpochSeconds => value.parse().ok(),
+            TimestampFormat::EpochMillis => value.parse::<i64>().ok().map(|t| t / 1000),
+            TimestampFormat::Custom(format) => DateTime::parse_from_str(value, format)
+                .map(|t| t.timestamp())
+                .or_else(|_| NaiveDateTime::parse_from_str(value, format).map(|t| t.timestamp()))
+                .ok(),
+        }
+    }
+}
+
+/// Reads the timestamp of the lines of the files matching the glob
+#[derive(Debug)]
+pub struct TimestampRule {
+    glob: Pattern,
+    format: TimestampFormat,
+    regex: Option<Regex>,
+    /// The number of whitespace separated words read for custom formats without a regex
+    words: usize,
+}
+
+impl TimestampRule {
+    /// Creates a rule, the first capture group of the regex is parsed when it has one,
+    /// otherwise the whole match
+    pub fn new(
+        glob: &str,
+        format: TimestampFormat,
+        regex: Option<&str>,
+    ) -> Result<Self, TimestampError> {
+        let regex = match regex.or_else(|| format.regex()) {
+            Some(regex) => Some(Regex::new(regex).map_err(TimestampError::Regex)?),
+            None => None,
+        };
+        let words = match &format {
+            TimestampFormat::Custom(format) => format.split_whitespace().count(),
+            _ => 0,
+        };
+        Ok(TimestampRule {
+            glob: Pattern::new(glob).map_err(TimestampError::Glob)?,
+            format,
+            regex,
+            words,
+        })
+    }
+
+    fn timestamp(&self, line: &str) -> Option<i64> {
+        match &self.regex {
+            Some(regex) => {
+                let captures = regex.captures(line)?;
+                let value = captures.get(1).or_else(|| captures.get(0))?;
+                self.format.parse(value.as_str())
+            }
+            None => {
+                let end = line
+                    .split_whitespace()
+                    .take(self.words)
+                    .last()
+                    .map(|word| word.as_ptr() as usize + word.len() - line.as_ptr() as usize)?;
+                self.format.parse(line[..end].trim_start())
+            }
+        }
+    }
+}
+
+/// Sets the timestamp of the lines from their content, the first rule matching the file and
+/// finding a timestamp is used. Lines without one keep the time they were read.
+pub struct Timestamps {
+    rules: Vec<TimestampRule>,
+}
+
+impl Timestamps {
+    pub fn new(rules: Vec<TimestampRule>) -> Self {
+        Timestamps { rules }
+    }
+
+    fn timestamp(&self, line: &mut dyn LineMut) -> Option<i64> {
+        let file = line.get_file()?.to_string();
+        let buf = line.get_line_buffer()?;
+        let text = String::from_utf8_lossy(buf);
+        self.rules
+            .iter()
+            .filter(|rule| rule.glob.matches(&file))
+            .find_map(|rule| rule.timestamp(&text))
+    }
+}
+
+impl Middleware for Timestamps {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineMut) -> Status<&'a mut dyn LineMut> {
+        if let Some(timestamp) = self.timestamp(line) {
+            // The line is still sent, with the time it was read
+            if let Err(e) = line.set_timestamp(timestamp) {
+                log::debug!("unable to set the line timestamp: {:?}", e);
+            }
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: &str, regex: Option<&str>, line: &str) -> Option<i64> {
+        TimestampRule::new("*", format.parse().unwrap(), regex)
+            .unwrap()
+            .timestamp(line)
+    }
+
+    #[test]
+    fn parses_the_formats() {
+        assert_eq!(
+            parse(
+                "rfc3339",
+                None,
+                "level=info ts=2021-05-01T10:00:00.5Z msg=started"
+            ),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse("rfc3339", None, "2021-05-01 12:00:00+02:00 started"),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse(
+                "common_log",
+                None,
+                r#"127.0.0.1 - - [01/May/2021:10:00:00 +0000] "GET / HTTP/1.1" 200 612"#
+            ),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse(
+                "nginx_error",
+                None,
+                "2021/05/01 10:00:00 [error] 7#7: failed"
+            ),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse("epoch", None, "1619863200 started"),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse("epoch_millis", None, "1619863200123 started"),
+            Some(1_619_863_200)
+        );
+        // Numbers elsewhere in the line are not read without a regex
+        assert_eq!(parse("epoch", None, "request 1619863200 failed"), None);
+        assert_eq!(
+            parse(
+                "epoch_millis",
+                Some(r"at=(\d{13})"),
+                "at=1619863200123 started"
+            ),
+            Some(1_619_863_200)
+        );
+        assert_eq!(parse("rfc3339", None, "no timestamp here"), None);
+    }
+
+    #[test]
+    fn parses_custom_formats() {
+        assert_eq!(
+            parse("%b %d %H:%M:%S %Y", None, "May 01 10:00:00 2021 started"),
+            Some(1_619_863_200)
+        );
+        assert_eq!(
+            parse(
+                "%d.%m.%Y %H:%M",
+                Some(r"time=(\S+ \S+)"),
+                "id=1 time=01.05.2021 10:00 done"
+            ),
+            Some(1_619_863_200)
+        );
+        assert_eq!(parse("%Y-%m-%d", None, "not a date"), None);
+        assert!("iso".parse::<TimestampFormat>().is_err());
+    }
+
+    #[test]
+    fn uses_the_first_matching_rule() {
+        let timestamps = Timestamps::new(vec![
+            TimestampRule::new("/var/log/nginx/*", TimestampFormat::CommonLog, None).unwrap(),
+            TimestampRule::new("/var/log/**", TimestampFormat::Rfc3339, None).unwrap(),
+            TimestampRule::new("/var/log/**", TimestampFormat::EpochSeconds, None).unwrap(),
+        ]);
+        let mut line = http::types::body::LineBuilder::new()
+            .line("1619863200 started")
+            .file("/var/log/app.log");
+        assert_eq!(timestamps.timestamp(&mut line), Some(1_619_863_200));
+
+        let mut line = http::types::body::LineBuilder::new()
+            .line("2021-05-01T10:00:00Z started")
+            .file("/tmp/app.log");
+        assert_eq!(timestamps.timestamp(&mut line), None);
+    }
+}
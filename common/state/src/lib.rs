@@ -6,12 +6,22 @@ use futures::stream::StreamExt;
 
 use log::{error, info, warn};
 
+use std::collections::{BTreeMap, HashMap};
 use std::convert::{AsRef, Into, TryInto};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 const OFFSET_NAME: &str = "file_offsets";
+const JOURNALD_CURSOR_NAME: &str = "journald_cursors";
+
+/// The key of the journald entries among the file offsets, their offsets are the sequence
+/// numbers given by [`JournaldCursors::track`]. It's out of the range of the inode numbers.
+pub const JOURNALD_OFFSET_KEY: u64 = u64::MAX;
+
+/// The most journald cursors kept waiting for their batch, the oldest are forgotten past it and
+/// the sources resume from an earlier cursor
+const MAX_PENDING_CURSORS: usize = 100_000;
 
 #[derive(Debug, Error)]
 pub enum StateError {
@@ -32,6 +42,8 @@ pub struct AgentState {
     db: Arc<DB>,
     #[derivative(Debug = "ignore")]
     offset_cf_opt: Options,
+    #[derivative(Debug = "ignore")]
+    journald_cursors: JournaldCursors,
 }
 
 // This was the old new fn implementation. It could probably be broken up
@@ -48,12 +60,16 @@ fn _construct_agent_state(path: &Path) -> Result<AgentState, StateError> {
     db_opts.create_if_missing(true);
 
     let offset_cf_opt = Options::default();
-    let offset_cf = ColumnFamilyDescriptor::new(OFFSET_NAME, offset_cf_opt.clone());
-    let cfs = vec![offset_cf];
+    let cfs = || {
+        vec![
+            ColumnFamilyDescriptor::new(OFFSET_NAME, offset_cf_opt.clone()),
+            ColumnFamilyDescriptor::new(JOURNALD_CURSOR_NAME, Options::default()),
+        ]
+    };
 
     info!("Opening state db at {:?}", path);
 
-    let db = match DB::open_cf_descriptors(&db_opts, &path, cfs) {
+    let db = match DB::open_cf_descriptors(&db_opts, &path, cfs()) {
         Ok(db) => db,
         // Attempt to repair a badly closed DB
         Err(e) => {
@@ -61,30 +77,16 @@ fn _construct_agent_state(path: &Path) -> Result<AgentState, StateError> {
             DB::repair(&db_opts, &path).map_or_else(
                 |_| {
                     DB::destroy(&db_opts, &path)?;
-                    DB::open_cf_descriptors(
-                        &db_opts,
-                        &path,
-                        vec![ColumnFamilyDescriptor::new(
-                            OFFSET_NAME,
-                            offset_cf_opt.clone(),
-                        )],
-                    )
-                },
-                |_| {
-                    DB::open_cf_descriptors(
-                        &db_opts,
-                        &path,
-                        vec![ColumnFamilyDescriptor::new(
-                            OFFSET_NAME,
-                            offset_cf_opt.clone(),
-                        )],
-                    )
+                    DB::open_cf_descriptors(&db_opts, &path, cfs())
                 },
+                |_| DB::open_cf_descriptors(&db_opts, &path, cfs()),
             )?
         }
     };
+    let db = Arc::new(db);
     Ok(AgentState {
-        db: Arc::new(db),
+        journald_cursors: JournaldCursors::new(db.clone()),
+        db,
         offset_cf_opt,
     })
 }
@@ -106,7 +108,90 @@ impl AgentState {
 
 impl AgentState {
     pub fn get_offset_state(&self) -> FileOffsetState {
-        FileOffsetState::new(self.db.clone(), self.offset_cf_opt.clone())
+        FileOffsetState::new(
+            self.db.clone(),
+            self.offset_cf_opt.clone(),
+            self.journald_cursors.clone(),
+        )
+    }
+
+    /// The journald cursors are written along with the offsets of the offset state
+    pub fn get_journald_cursors(&self) -> JournaldCursors {
+        self.journald_cursors.clone()
+    }
+}
+
+#[derive(Default)]
+struct PendingCursors {
+    next: u64,
+    // The source and cursor of the entries that are not acknowledged yet
+    cursors: BTreeMap<u64, (String, String)>,
+}
+
+/// Keeps the cursors of the journald entries being sent until they are acknowledged, so that
+/// the sources resume after the last entry that was sent.
+///
+/// Each entry is given a sequence number to be used as its offset under
+/// [`JOURNALD_OFFSET_KEY`]. When the offset is committed, the last cursor of each source up to
+/// it is stored with the file offsets. The sequence numbers start over with each run, the bodies
+/// retried from a previous run don't commit offsets.
+#[derive(Clone)]
+pub struct JournaldCursors {
+    db: Arc<DB>,
+    pending: Arc<Mutex<PendingCursors>>,
+}
+
+impl JournaldCursors {
+    fn new(db: Arc<DB>) -> Self {
+        JournaldCursors {
+            db,
+            pending: Arc::new(Mutex::new(PendingCursors::default())),
+        }
+    }
+
+    /// The stored cursors by source
+    pub fn cursors(&self) -> Result<HashMap<String, String>, FileOffsetStateError> {
+        let cf_handle = self.db.cf_handle(JOURNALD_CURSOR_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        Ok(self
+            .db
+            .iterator_cf(cf_handle, IteratorMode::Start)
+            .map(|(k, v)| {
+                (
+                    String::from_utf8_lossy(&k).into_owned(),
+                    String::from_utf8_lossy(&v).into_owned(),
+                )
+            })
+            .collect())
+    }
+
+    /// Records the cursor of an entry about to be sent and returns its sequence number
+    pub fn track(&self, source: &str, cursor: String) -> u64 {
+        let mut pending = self.pending.lock().expect("cursors lock poisoned");
+        let seq = pending.next;
+        pending.next += 1;
+        if pending.cursors.len() >= MAX_PENDING_CURSORS {
+            if let Some(oldest) = pending.cursors.keys().next().copied() {
+                pending.cursors.remove(&oldest);
+            }
+        }
+        pending.cursors.insert(seq, (source.to_string(), cursor));
+        seq
+    }
+
+    /// Removes the cursors up to the committed sequence number and returns the last one of
+    /// each source
+    fn commit(&self, seq: u64) -> HashMap<String, String> {
+        let mut pending = self.pending.lock().expect("cursors lock poisoned");
+        let remaining = match seq.checked_add(1) {
+            Some(next) => pending.cursors.split_off(&next),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut pending.cursors, remaining)
+            .into_iter()
+            .map(|(_, source_cursor)| source_cursor)
+            .collect()
     }
 }
 
@@ -209,18 +294,20 @@ impl FileOffsetShutdownHandle {
 pub struct FileOffsetState {
     db: Arc<DB>,
     cf_opts: Options,
+    journald_cursors: JournaldCursors,
     rx: std::cell::RefCell<Option<async_channel::Receiver<FileOffsetEvent>>>,
     shutdown: std::cell::RefCell<Option<async_channel::Sender<FileOffsetEvent>>>,
     tx: async_channel::Sender<FileOffsetEvent>,
 }
 
 impl FileOffsetState {
-    fn new(db: Arc<DB>, cf_opts: Options) -> Self {
+    fn new(db: Arc<DB>, cf_opts: Options, journald_cursors: JournaldCursors) -> Self {
         let (tx, rx) = async_channel::unbounded();
 
         FileOffsetState {
             db,
             cf_opts,
+            journald_cursors,
             rx: std::cell::RefCell::new(Some(rx)),
             shutdown: std::cell::RefCell::new(Some(tx.clone())),
             tx,
@@ -267,6 +354,8 @@ impl FileOffsetState {
         })
     }
 
+    /// Returns the future writing the offsets, it completes after the shutdown handle is used
+    /// and the events sent before are processed. Updates that were not flushed are discarded.
     pub fn run(&self) -> Result<impl Future<Output = ()>, FileOffsetStateError> {
         let rx = self
             .rx
@@ -274,14 +363,21 @@ impl FileOffsetState {
             .take()
             .ok_or(FileOffsetStateError::AlreadyRunning)?;
         let db = self.db.clone();
+        let journald_cursors = self.journald_cursors.clone();
         Ok(rx
             .fold(Some(WriteBatch::default()), move |acc, event| {
                 let db = db.clone();
+                let journald_cursors = journald_cursors.clone();
                 async move {
-                    match db.cf_handle(OFFSET_NAME).ok_or_else(|| {
-                        FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
-                    }) {
-                        Ok(cf_handle) => match (acc, event) {
+                    match db
+                        .cf_handle(OFFSET_NAME)
+                        .zip(db.cf_handle(JOURNALD_CURSOR_NAME))
+                        .ok_or_else(|| {
+                            FileOffsetStateError::DbError(
+                                "Failed to get ColumnFamily handle".into(),
+                            )
+                        }) {
+                        Ok((cf_handle, cursor_cf_handle)) => match (acc, event) {
                             (Some(wb), FileOffsetEvent::Flush) => {
                                 let ret = db.write(wb).map(|_| ());
                                 ret.map(|_| None).map_err(|e| e.into())
@@ -290,6 +386,14 @@ impl FileOffsetState {
                             (wb, FileOffsetEvent::Update(e)) => {
                                 let mut wb = wb.unwrap_or_default();
                                 match e {
+                                    FileOffsetUpdate::Update(FileOffset {
+                                        key: FileId(JOURNALD_OFFSET_KEY),
+                                        offset,
+                                    }) => {
+                                        for (source, cursor) in journald_cursors.commit(offset) {
+                                            wb.put_cf(cursor_cf_handle, source, cursor)
+                                        }
+                                    }
                                     FileOffsetUpdate::Update(FileOffset { key, offset }) => wb
                                         .put_cf(
                                             cf_handle,
@@ -392,6 +496,63 @@ mod test {
         _test(&data_dir, 2);
     }
 
+    #[test]
+    fn journald_cursors() {
+        let data_dir = tempdir().expect("Could not create temp dir").into_path();
+        let agent_state = AgentState::new(&data_dir).unwrap();
+        let offset_state = agent_state.get_offset_state();
+        let cursors = agent_state.get_journald_cursors();
+        assert!(cursors.cursors().unwrap().is_empty());
+
+        let first = cursors.track("journalctl", "c1".into());
+        let second = cursors.track("/var/log/journal", "c2".into());
+        let third = cursors.track("journalctl", "c3".into());
+        assert!(first < second && second < third);
+
+        let wh = offset_state.write_handle();
+        let fh = offset_state.flush_handle();
+        let sh = offset_state.shutdown_handle().unwrap();
+        tokio_test::block_on(async {
+            let _ = tokio::join!(
+                async move {
+                    wh.update(&JOURNALD_OFFSET_KEY, second).await.unwrap();
+                    fh.flush().await.unwrap();
+                    sh.shutdown();
+                },
+                offset_state.run().unwrap()
+            );
+        });
+
+        let stored = cursors.cursors().unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored["journalctl"], "c1");
+        assert_eq!(stored["/var/log/journal"], "c2");
+        // The cursors are not file offsets
+        assert!(offset_state.offsets().unwrap().is_empty());
+        assert_eq!(
+            cursors.commit(third).into_iter().collect::<Vec<_>>(),
+            vec![("journalctl".to_string(), "c3".to_string())]
+        );
+    }
+
+    #[test]
+    fn journald_cursors_are_capped() {
+        let data_dir = tempdir().expect("Could not create temp dir").into_path();
+        let agent_state = AgentState::new(&data_dir).unwrap();
+        let cursors = agent_state.get_journald_cursors();
+
+        let first = cursors.track("journalctl", "c0".into());
+        for i in 1..=MAX_PENDING_CURSORS {
+            cursors.track("journalctl", format!("c{}", i));
+        }
+        assert_eq!(
+            cursors.pending.lock().unwrap().cursors.len(),
+            MAX_PENDING_CURSORS
+        );
+        // The oldest cursor was forgotten
+        assert!(cursors.commit(first).is_empty());
+    }
+
     #[test]
     fn load_agent_state_dir_missing() {
         // build a path with multiple levels of missing directories to ensure they're all created
@@ -0,0 +1,132 @@
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::Decoder;
+
+use crate::error::SyslogError;
+
+/// The maximum size of a message, larger messages are dropped along with the connection
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// The number of digits needed to express the maximum frame size
+const MAX_FRAME_SIZE_DIGITS: usize = 5;
+
+/// Splits a syslog stream into messages, supporting both the octet counting and the
+/// non-transparent (newline delimited) framing from RFC 6587
+#[derive(Debug, Default)]
+pub struct SyslogDecoder;
+
+impl Decoder for SyslogDecoder {
+    type Item = BytesMut;
+    type Error = SyslogError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Skip the trailers left between messages
+        let start = src
+            .iter()
+            .position(|b| !matches!(b, b'\n' | b'\r' | b'\0'))
+            .unwrap_or(src.len());
+        src.advance(start);
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        // Messages always start with the priority, so a digit is the start of an octet count
+        if src[0].is_ascii_digit() {
+            let space = match src.iter().position(|b| *b == b' ') {
+                Some(space) if space <= MAX_FRAME_SIZE_DIGITS => space,
+                Some(_) => return Err(SyslogError::InvalidFrame),
+                None if src.len() > MAX_FRAME_SIZE_DIGITS => return Err(SyslogError::InvalidFrame),
+                None => return Ok(None),
+            };
+            let len = std::str::from_utf8(&src[..space])
+                .ok()
+                .and_then(|len| len.parse::<usize>().ok())
+                .ok_or(SyslogError::InvalidFrame)?;
+            if len > MAX_FRAME_SIZE {
+                return Err(SyslogError::FrameTooLarge(len));
+            }
+            if src.len() < space + 1 + len {
+                src.reserve(space + 1 + len - src.len());
+                return Ok(None);
+            }
+            src.advance(space + 1);
+            return Ok(Some(src.split_to(len)));
+        }
+
+        match src.iter().position(|b| *b == b'\n') {
+            Some(end) => {
+                let frame = src.split_to(end);
+                src.advance(1);
+                Ok(Some(frame))
+            }
+            None if src.len() > MAX_FRAME_SIZE => Err(SyslogError::FrameTooLarge(src.len())),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(frame) = self.decode(src)? {
+            return Ok(Some(frame));
+        }
+
+        // The last delimited message may not be terminated when the connection is closed,
+        // while an incomplete counted message is truncated
+        if src.is_empty() || src[0].is_ascii_digit() {
+            src.clear();
+            Ok(None)
+        } else {
+            Ok(Some(src.split()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(input: &[u8]) -> Result<Vec<String>, SyslogError> {
+        let mut decoder = SyslogDecoder::default();
+        let mut src = BytesMut::from(input);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.decode_eof(&mut src)? {
+            frames.push(String::from_utf8(frame.to_vec()).unwrap());
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn decodes_octet_counted_frames() {
+        let frames = decode_all(b"11 <13>1 - - a7 <13>x\ny\n").unwrap();
+        assert_eq!(frames, vec!["<13>1 - - a", "<13>x\ny"]);
+    }
+
+    #[test]
+    fn decodes_delimited_frames() {
+        let frames = decode_all(b"<13>first\r\n<13>second\n\n<13>last").unwrap();
+        assert_eq!(frames, vec!["<13>first\r", "<13>second", "<13>last"]);
+    }
+
+    #[test]
+    fn waits_for_complete_frames() {
+        let mut decoder = SyslogDecoder::default();
+        let mut src = BytesMut::from(&b"20 <13>incomp"[..]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"lete message");
+        assert_eq!(
+            decoder.decode(&mut src).unwrap().unwrap(),
+            &b"<13>incomplete messa"[..]
+        );
+        assert_eq!(decoder.decode_eof(&mut src).unwrap().unwrap(), &b"ge"[..]);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        assert!(matches!(
+            decode_all(b"123456789 <13>"),
+            Err(SyslogError::InvalidFrame)
+        ));
+        assert!(matches!(
+            decode_all(b"99999 <13>"),
+            Err(SyslogError::FrameTooLarge(99999))
+        ));
+    }
+}
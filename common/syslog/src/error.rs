@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+use tokio_rustls::rustls::TLSError;
+
+#[derive(Debug, Error)]
+pub enum SyslogError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{0} is not a valid syslog priority")]
+    InvalidPriority(String),
+    #[error("the syslog frame length is invalid")]
+    InvalidFrame,
+    #[error("the syslog frame of {0} bytes exceeds the maximum size")]
+    FrameTooLarge(usize),
+    #[error("no valid certificate or key found in {0:?}")]
+    InvalidPem(PathBuf),
+    #[error(transparent)]
+    Tls(#[from] TLSError),
+}
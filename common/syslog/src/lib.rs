@@ -0,0 +1,278 @@
+#[macro_use]
+extern crate log;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use serde_json::{Map, Value};
+use tokio::io::AsyncRead;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::codec::FramedRead;
+
+use http::types::body::{LineBuilder, LineMetaMut};
+
+use crate::codec::{SyslogDecoder, MAX_FRAME_SIZE};
+use crate::error::SyslogError;
+use crate::parser::Message;
+
+pub mod codec;
+pub mod error;
+pub mod parser;
+
+/// The number of lines buffered between the listeners and the agent
+const LINE_BUFFER_SIZE: usize = 1024;
+
+/// A socket receiving syslog messages
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listener {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Tls {
+        address: SocketAddr,
+        cert: PathBuf,
+        key: PathBuf,
+    },
+}
+
+/// A line received from a listener, with the parts of its message that the line builder can't
+/// hold
+pub struct SyslogLine {
+    pub line: LineBuilder,
+    /// The time in the header of the message, in seconds since the epoch
+    pub timestamp: Option<i64>,
+}
+
+/// Binds the listeners and returns the stream of the lines they receive
+pub async fn create_source(
+    listeners: &[Listener],
+) -> Result<impl Stream<Item = SyslogLine>, SyslogError> {
+    let (tx, rx) = mpsc::channel(LINE_BUFFER_SIZE);
+
+    for listener in listeners {
+        match listener {
+            Listener::Udp(address) => {
+                let socket = UdpSocket::bind(address).await?;
+                info!("listening for syslog messages on udp://{}", address);
+                tokio::spawn(receive_datagrams(socket, tx.clone()));
+            }
+            Listener::Tcp(address) => {
+                let listener = TcpListener::bind(address).await?;
+                info!("listening for syslog messages on tcp://{}", address);
+                tokio::spawn(accept_connections(listener, None, tx.clone()));
+            }
+            Listener::Tls { address, cert, key } => {
+                let acceptor = tls_acceptor(cert, key)?;
+                let listener = TcpListener::bind(address).await?;
+                info!("listening for syslog messages on tls://{}", address);
+                tokio::spawn(accept_connections(listener, Some(acceptor), tx.clone()));
+            }
+        }
+    }
+
+    Ok(rx)
+}
+
+fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, SyslogError> {
+    let invalid_pem = |path: &Path| SyslogError::InvalidPem(path.to_path_buf());
+
+    let certs = certs(&mut BufReader::new(File::open(cert)?)).map_err(|_| invalid_pem(cert))?;
+    let mut keys =
+        pkcs8_private_keys(&mut BufReader::new(File::open(key)?)).map_err(|_| invalid_pem(key))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key)?))
+            .map_err(|_| invalid_pem(key))?;
+    }
+    let key = keys.into_iter().next().ok_or_else(|| invalid_pem(key))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn receive_datagrams(socket: UdpSocket, mut tx: mpsc::Sender<SyslogLine>) {
+    let mut buf = vec![0; MAX_FRAME_SIZE];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("error receiving syslog message: {}", e);
+                continue;
+            }
+        };
+        if let Some(line) = decode(&buf[..len], peer) {
+            if tx.send(line).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    tx: mpsc::Sender<SyslogLine>,
+) {
+    while !tx.is_closed() {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("error accepting syslog connection: {}", e);
+                // Give the errors such as reaching the open files limit a chance to clear
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        debug!("accepted syslog connection from {}", peer);
+
+        let tx = tx.clone();
+        match tls.clone() {
+            Some(acceptor) => {
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => receive_messages(stream, peer, tx).await,
+                        Err(e) => warn!("syslog TLS handshake with {} failed: {}", peer, e),
+                    }
+                });
+            }
+            None => {
+                tokio::spawn(receive_messages(stream, peer, tx));
+            }
+        }
+    }
+}
+
+async fn receive_messages<S: AsyncRead + Unpin>(
+    stream: S,
+    peer: SocketAddr,
+    mut tx: mpsc::Sender<SyslogLine>,
+) {
+    let mut frames = FramedRead::new(stream, SyslogDecoder::default());
+    while let Some(frame) = frames.next().await {
+        match frame {
+            Ok(frame) => {
+                if let Some(line) = decode(&frame, peer) {
+                    if tx.send(line).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("closing syslog connection from {}: {}", peer, e);
+                return;
+            }
+        }
+    }
+}
+
+fn decode(frame: &[u8], peer: SocketAddr) -> Option<SyslogLine> {
+    match parser::parse(&String::from_utf8_lossy(frame)) {
+        Ok(message) => Some(into_line(message, peer)),
+        Err(e) => {
+            debug!("discarding syslog message from {}: {}", peer, e);
+            None
+        }
+    }
+}
+
+/// Maps the message to a line, the header fields without a line field are kept in the meta
+///
+/// The timestamp of the message is also kept in the meta, as the line timestamp loses its offset.
+pub fn into_line(message: Message, peer: SocketAddr) -> SyslogLine {
+    let level = message.severity_name();
+    let mut meta = Map::new();
+    meta.insert("facility".into(), message.facility_name().into());
+    meta.insert("peer".into(), peer.to_string().into());
+    if let Some(timestamp) = message.timestamp {
+        meta.insert("timestamp".into(), timestamp.to_rfc3339().into());
+    }
+    if let Some(proc_id) = message.proc_id {
+        meta.insert("procid".into(), proc_id.into());
+    }
+    if let Some(msg_id) = message.msg_id {
+        meta.insert("msgid".into(), msg_id.into());
+    }
+    if let Some(structured_data) = message.structured_data {
+        meta.insert("structured_data".into(), structured_data.into());
+    }
+
+    let mut line = LineBuilder::new()
+        .line(message.message)
+        .level(level)
+        .host(message.hostname.unwrap_or_else(|| peer.ip().to_string()));
+    if let Some(app_name) = message.app_name {
+        line = line.app(app_name);
+    }
+    if let Err(e) = line.set_meta(Value::Object(meta)) {
+        warn!("unable to set the syslog message meta: {:?}", e);
+    }
+    SyslogLine {
+        line,
+        timestamp: message.timestamp.map(|timestamp| timestamp.timestamp()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBufferMut, LineMeta};
+    use serde_json::json;
+
+    #[test]
+    fn maps_message_fields() {
+        let peer = "10.0.0.1:514".parse().unwrap();
+        let message = parser::parse("<86>1 - - sshd 42 - - accepted publickey").unwrap();
+        let SyslogLine { line, timestamp } = into_line(message, peer);
+
+        assert_eq!(timestamp, None);
+        assert_eq!(line.get_level(), Some("INFO"));
+        assert_eq!(line.get_app(), Some("sshd"));
+        assert_eq!(line.get_host(), Some("10.0.0.1"));
+        assert_eq!(
+            line.get_meta(),
+            Some(&json!({"facility": "authpriv", "peer": "10.0.0.1:514", "procid": "42"}))
+        );
+    }
+
+    #[test]
+    fn keeps_the_message_timestamp() {
+        let peer = "10.0.0.1:514".parse().unwrap();
+        let message =
+            parser::parse("<86>1 2021-05-01T12:00:00+02:00 web sshd - - - accepted").unwrap();
+        let SyslogLine { line, timestamp } = into_line(message, peer);
+
+        assert_eq!(timestamp, Some(1_619_863_200));
+        assert_eq!(
+            line.get_meta().and_then(|meta| meta.get("timestamp")),
+            Some(&json!("2021-05-01T12:00:00+02:00"))
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_udp_messages() {
+        let address = "127.0.0.1:0".parse().unwrap();
+        let socket = UdpSocket::bind(address).await.unwrap();
+        let address = socket.local_addr().unwrap();
+        drop(socket);
+
+        let mut source = Box::pin(create_source(&[Listener::Udp(address)]).await.unwrap());
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(b"<13>Oct  1 22:14:15 web nginx: GET /", address)
+            .await
+            .unwrap();
+
+        let SyslogLine { mut line, .. } = source.next().await.unwrap();
+        assert_eq!(line.get_host(), Some("web"));
+        assert_eq!(line.get_app(), Some("nginx"));
+        assert_eq!(line.get_line_buffer().unwrap(), b"GET /");
+    }
+}
@@ -0,0 +1,341 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, TimeZone, Utc};
+
+use crate::error::SyslogError;
+
+/// The facility names, indexed by their code
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// The level names, indexed by the severity code
+const SEVERITIES: [&str; 8] = [
+    "EMERGENCY",
+    "ALERT",
+    "CRITICAL",
+    "ERROR",
+    "WARNING",
+    "NOTICE",
+    "INFO",
+    "DEBUG",
+];
+
+/// Messages without a priority are considered `user.notice`, as RFC 3164 relays do
+const DEFAULT_PRIORITY: u8 = 13;
+
+const NIL_VALUE: &str = "-";
+
+/// A syslog message, fields that are missing or set to the nil value are `None`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    pub structured_data: Option<String>,
+    pub message: String,
+}
+
+impl Message {
+    pub fn facility_name(&self) -> &'static str {
+        FACILITIES[self.facility as usize]
+    }
+
+    pub fn severity_name(&self) -> &'static str {
+        SEVERITIES[self.severity as usize]
+    }
+}
+
+/// Parses a RFC 5424 or a RFC 3164 message, the latter being parsed leniently as senders
+/// rarely follow it strictly
+pub fn parse(input: &str) -> Result<Message, SyslogError> {
+    let input = input.trim_end_matches(|c: char| c == '\n' || c == '\r' || c == '\0');
+
+    let (priority, rest) = match input.strip_prefix('<').and_then(|rest| {
+        rest.find('>')
+            .filter(|end| (1..=3).contains(end))
+            .map(|end| (&rest[..end], &rest[end + 1..]))
+    }) {
+        Some((priority, rest)) => (
+            priority
+                .parse::<u8>()
+                .ok()
+                .filter(|priority| *priority < 192)
+                .ok_or_else(|| SyslogError::InvalidPriority(priority.to_string()))?,
+            rest,
+        ),
+        None => (DEFAULT_PRIORITY, input),
+    };
+
+    let (facility, severity) = (priority / 8, priority % 8);
+    Ok(match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(facility, severity, rest),
+        None => parse_rfc3164(facility, severity, rest),
+    })
+}
+
+/// Takes the next space separated field
+fn next_field<'a>(input: &mut &'a str) -> Option<&'a str> {
+    if input.is_empty() {
+        return None;
+    }
+    let (field, rest) = match input.find(' ') {
+        Some(end) => (&input[..end], &input[end + 1..]),
+        None => (*input, ""),
+    };
+    *input = rest;
+    Some(field)
+}
+
+fn non_nil(field: Option<&str>) -> Option<String> {
+    field
+        .filter(|field| !field.is_empty() && *field != NIL_VALUE)
+        .map(String::from)
+}
+
+fn parse_rfc5424(facility: u8, severity: u8, mut input: &str) -> Message {
+    let timestamp =
+        next_field(&mut input).and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok());
+    let hostname = non_nil(next_field(&mut input));
+    let app_name = non_nil(next_field(&mut input));
+    let proc_id = non_nil(next_field(&mut input));
+    let msg_id = non_nil(next_field(&mut input));
+    let (structured_data, rest) = split_structured_data(input);
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+
+    Message {
+        facility,
+        severity,
+        timestamp,
+        hostname,
+        app_name,
+        proc_id,
+        msg_id,
+        structured_data,
+        message: message.trim_start_matches('\u{feff}').to_string(),
+    }
+}
+
+/// Splits the structured data elements from the message, values can contain escaped quotes
+/// and brackets
+fn split_structured_data(input: &str) -> (Option<String>, &str) {
+    if let Some(rest) = input.strip_prefix(NIL_VALUE) {
+        return (None, rest);
+    }
+
+    let bytes = input.as_bytes();
+    if bytes.first() != Some(&b'[') {
+        return (None, input);
+    }
+
+    let mut end = 0;
+    let mut in_value = false;
+    let mut escaped = false;
+    for (i, byte) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match byte {
+            b'\\' if in_value => escaped = true,
+            b'"' => in_value = !in_value,
+            b']' if !in_value => {
+                end = i + 1;
+                // Elements are not separated from each other
+                if bytes.get(end) != Some(&b'[') {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if end == 0 {
+        (None, input)
+    } else {
+        (Some(input[..end].to_string()), &input[end..])
+    }
+}
+
+fn parse_rfc3164(facility: u8, severity: u8, input: &str) -> Message {
+    let (timestamp, rest) = parse_rfc3164_timestamp(input);
+
+    // The hostname is only expected after the timestamp
+    let (hostname, rest) = match rest.find(' ') {
+        Some(end)
+            if timestamp.is_some() && !rest[..end].contains(|c: char| c == ':' || c == '[') =>
+        {
+            (Some(rest[..end].to_string()), &rest[end + 1..])
+        }
+        _ => (None, rest),
+    };
+
+    let (app_name, proc_id, message) = parse_tag(rest);
+    Message {
+        facility,
+        severity,
+        timestamp,
+        hostname,
+        app_name,
+        proc_id,
+        msg_id: None,
+        structured_data: None,
+        message: message.to_string(),
+    }
+}
+
+fn parse_rfc3164_timestamp(input: &str) -> (Option<DateTime<FixedOffset>>, &str) {
+    // Some senders use RFC 3339 timestamps instead
+    if let Some(end) = input.find(' ') {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(&input[..end]) {
+            return (Some(timestamp), &input[end + 1..]);
+        }
+    }
+
+    // e.g. "Oct  1 22:14:15 ", the year is not part of the timestamp
+    if input.len() > 15 && input.is_char_boundary(15) && input.as_bytes()[15] == b' ' {
+        let timestamp = format!("{} {}", Utc::now().year(), &input[..15]);
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(&timestamp, "%Y %b %e %H:%M:%S") {
+            return (Some(Utc.from_utc_datetime(&timestamp).into()), &input[16..]);
+        }
+    }
+
+    (None, input)
+}
+
+/// Splits the `TAG[PID]: ` prefix from the message
+fn parse_tag(input: &str) -> (Option<String>, Option<String>, &str) {
+    let end = input
+        .find(|c: char| c == ':' || c == '[' || c.is_whitespace())
+        .unwrap_or(input.len());
+    let (tag, rest) = input.split_at(end);
+    if tag.is_empty() {
+        return (None, None, input);
+    }
+
+    let (proc_id, rest) = match rest
+        .strip_prefix('[')
+        .and_then(|rest| rest.find(']').map(|end| (&rest[..end], &rest[end + 1..])))
+    {
+        Some((proc_id, rest)) => (Some(proc_id.to_string()), rest),
+        None => (None, rest),
+    };
+
+    match rest.strip_prefix(':') {
+        Some(message) => (
+            Some(tag.to_string()),
+            proc_id,
+            message.strip_prefix(' ').unwrap_or(message),
+        ),
+        None => (None, None, input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc5424() {
+        let message = parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"Appl\\\"ication]\"][other a=\"b\"] \
+             \u{feff}An application event log entry...\n",
+        )
+        .unwrap();
+
+        assert_eq!(message.facility_name(), "local4");
+        assert_eq!(message.severity_name(), "NOTICE");
+        assert_eq!(
+            message.timestamp,
+            DateTime::parse_from_rfc3339("2003-10-11T22:14:15.003Z").ok()
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(
+            message.structured_data.as_deref(),
+            Some("[exampleSDID@32473 iut=\"3\" eventSource=\"Appl\\\"ication]\"][other a=\"b\"]")
+        );
+        assert_eq!(message.message, "An application event log entry...");
+    }
+
+    #[test]
+    fn parses_rfc5424_nil_values() {
+        let message = parse("<34>1 - - - - - -").unwrap();
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity_name(), "CRITICAL");
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.structured_data, None);
+        assert_eq!(message.message, "");
+    }
+
+    #[test]
+    fn parses_rfc3164() {
+        let message = parse("<34>Oct  1 22:14:15 mymachine su[123]: 'su root' failed").unwrap();
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity_name(), "CRITICAL");
+        let timestamp = message.timestamp.unwrap();
+        assert_eq!((timestamp.month(), timestamp.day()), (10, 1));
+        assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.proc_id.as_deref(), Some("123"));
+        assert_eq!(message.message, "'su root' failed");
+
+        let message = parse("<13>2021-05-01T12:00:00+02:00 host app: started").unwrap();
+        assert_eq!(
+            message.timestamp,
+            DateTime::parse_from_rfc3339("2021-05-01T12:00:00+02:00").ok()
+        );
+        assert_eq!(message.hostname.as_deref(), Some("host"));
+        assert_eq!(message.app_name.as_deref(), Some("app"));
+    }
+
+    #[test]
+    fn parses_lenient_rfc3164() {
+        let message = parse("<30>dhclient: bound to 10.0.0.2").unwrap();
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("dhclient"));
+        assert_eq!(message.message, "bound to 10.0.0.2");
+
+        let message = parse("just a message").unwrap();
+        assert_eq!(message.facility_name(), "user");
+        assert_eq!(message.severity_name(), "NOTICE");
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "just a message");
+
+        assert!(matches!(
+            parse("<999>1 - - - - - -"),
+            Err(SyslogError::InvalidPriority(_))
+        ));
+    }
+}